@@ -0,0 +1,64 @@
+//! Interact with any of the registered state machines from the command line.
+//!
+//! Usage: `cargo run --bin repl -- <machine> [script]`
+//!
+//! When a script is given, its commands are applied before the prompt appears.
+
+use diy_blockchain::p1_state_machine::repl::{driver, MACHINES};
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("Usage: repl <machine> [script]");
+    eprintln!("Available machines:");
+    for (name, _) in MACHINES {
+        eprintln!("  {}", name);
+    }
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let name = args.first().unwrap_or_else(|| usage());
+    let mut driver = driver(name).unwrap_or_else(|| usage());
+
+    println!("{}", driver.name());
+    println!("Type :help for a list of commands.");
+
+    if let Some(script) = args.get(1) {
+        match driver.execute(&format!(":load {}", script)) {
+            Ok(output) => println!("{}", output),
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+    println!("{}", driver.state());
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().expect("stdout should be writable");
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == ":quit" {
+            break;
+        }
+        match driver.execute(line) {
+            Ok(output) => println!("{}", output),
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
 
-mod p1_state_machine;
+pub mod p1_state_machine;
 mod p2_blockchain;
 // mod p3_client;
 
@@ -0,0 +1,331 @@
+//! Big systems are usually built from small ones. Rather than writing a new state machine from
+//! scratch every time, we can combine the ones we already have.
+//!
+//! * `Product` runs two machines side by side. Each transition affects exactly one of them.
+//! * `Sequence` applies a whole batch of transitions at once, much like a block of extrinsics.
+//! * `Mapped` translates, and possibly filters, transitions before they reach the inner machine.
+//! * `Many` manages any number of independent instances of the same machine, keyed by an id.
+//!
+//! Each combinator is itself a `StateMachine`, so they can be nested as deeply as you like.
+
+use super::{FiniteStateMachine, StateMachine, TryStateMachine};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+/// One of two things
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+/// Two machines running side by side. The state is a pair of states, and each transition
+/// is meant for exactly one of the machines. The other machine is left untouched.
+pub struct Product<A, B>(PhantomData<(A, B)>);
+
+impl<A, B> StateMachine for Product<A, B>
+where
+    A: StateMachine,
+    B: StateMachine,
+    A::State: Clone,
+    B::State: Clone,
+{
+    type State = (A::State, B::State);
+    type Transition = Either<A::Transition, B::Transition>;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        let (a, b) = starting_state;
+        match t {
+            Either::Left(t) => (A::next_state(a, t), b.clone()),
+            Either::Right(t) => (a.clone(), B::next_state(b, t)),
+        }
+    }
+
+    fn human_name() -> String {
+        format!("{} and {}", A::human_name(), B::human_name())
+    }
+}
+
+impl<A, B> FiniteStateMachine for Product<A, B>
+where
+    A: FiniteStateMachine,
+    B: FiniteStateMachine,
+    A::State: Clone,
+    B::State: Clone,
+{
+    fn transitions(state: &Self::State) -> Vec<Self::Transition> {
+        let (a, b) = state;
+        A::transitions(a)
+            .into_iter()
+            .map(Either::Left)
+            .chain(B::transitions(b).into_iter().map(Either::Right))
+            .collect()
+    }
+}
+
+/// Applies a whole batch of transitions to the inner machine, one after the other.
+pub struct Sequence<M>(PhantomData<M>);
+
+impl<M> StateMachine for Sequence<M>
+where
+    M: StateMachine,
+    M::State: Clone,
+{
+    type State = M::State;
+    type Transition = Vec<M::Transition>;
+
+    fn next_state(starting_state: &M::State, t: &Vec<M::Transition>) -> M::State {
+        t.iter()
+            .fold(starting_state.clone(), |state, t| M::next_state(&state, t))
+    }
+
+    fn human_name() -> String {
+        M::human_name()
+    }
+}
+
+/// Translates one kind of transition into another. Returning `None` filters the transition out.
+///
+/// Like state machines themselves, maps are described entirely by their types, so that the
+/// machines built from them are too.
+pub trait TransitionMap {
+    /// The transitions accepted by the mapped machine
+    type Input;
+
+    /// The transitions understood by the inner machine
+    type Output;
+
+    /// Translate an input transition, or return `None` to ignore it.
+    fn map(input: &Self::Input) -> Option<Self::Output>;
+}
+
+/// A machine whose transitions pass through a `TransitionMap` before reaching the inner machine.
+/// Transitions that the map filters out leave the state unchanged.
+pub struct Mapped<M, F>(PhantomData<(M, F)>);
+
+impl<M, F> StateMachine for Mapped<M, F>
+where
+    M: StateMachine,
+    M::State: Clone,
+    F: TransitionMap<Output = M::Transition>,
+{
+    type State = M::State;
+    type Transition = F::Input;
+
+    fn next_state(starting_state: &M::State, t: &F::Input) -> M::State {
+        match F::map(t) {
+            Some(t) => M::next_state(starting_state, &t),
+            None => starting_state.clone(),
+        }
+    }
+
+    fn human_name() -> String {
+        M::human_name()
+    }
+}
+
+/// Transitions for a collection of machine instances
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Instance<K, S, T> {
+    /// Add a new instance in the given state, replacing any existing instance with the same id.
+    Add(K, S),
+    /// Remove an instance entirely.
+    Remove(K),
+    /// Apply a transition to a single instance. Nothing happens if there is no such instance.
+    Apply(K, T),
+}
+
+/// Why a transition for a collection of machine instances was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InstanceError<K, E> {
+    /// There is no instance with this id
+    NoSuchInstance(K),
+    /// The instance with this id rejected the transition
+    Rejected(K, E),
+}
+
+/// Any number of independent instances of the same machine, each identified by a key.
+/// A wardrobe full of garments, for example.
+///
+/// The instances are kept in a `BTreeMap` so that the state is ordered deterministically.
+pub struct Many<M, K>(PhantomData<(M, K)>);
+
+impl<M, K> StateMachine for Many<M, K>
+where
+    M: StateMachine,
+    M::State: Clone,
+    K: Clone + Ord,
+{
+    type State = BTreeMap<K, M::State>;
+    type Transition = Instance<K, M::State, M::Transition>;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        let mut state = starting_state.clone();
+        match t {
+            Instance::Add(key, instance) => {
+                state.insert(key.clone(), instance.clone());
+            }
+            Instance::Remove(key) => {
+                state.remove(key);
+            }
+            Instance::Apply(key, t) => {
+                if let Some(instance) = state.get_mut(key) {
+                    *instance = M::next_state(instance, t);
+                }
+            }
+        }
+        state
+    }
+
+    fn human_name() -> String {
+        format!("Many {}", M::human_name())
+    }
+}
+
+/// When the instances can reject transitions, so can the collection. Adding and removing
+/// instances still always succeeds.
+impl<M, K> TryStateMachine for Many<M, K>
+where
+    M: TryStateMachine,
+    M::State: Clone,
+    K: Clone + Ord,
+{
+    type State = BTreeMap<K, M::State>;
+    type Transition = Instance<K, M::State, M::Transition>;
+    type Error = InstanceError<K, M::Error>;
+
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, Self::Error> {
+        let mut state = starting_state.clone();
+        match t {
+            Instance::Add(key, instance) => {
+                state.insert(key.clone(), instance.clone());
+            }
+            Instance::Remove(key) => {
+                state.remove(key);
+            }
+            Instance::Apply(key, t) => {
+                let instance = state
+                    .get_mut(key)
+                    .ok_or_else(|| InstanceError::NoSuchInstance(key.clone()))?;
+                *instance = M::try_next_state(instance, t)
+                    .map_err(|e| InstanceError::Rejected(key.clone(), e))?;
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        format!("Many {}", M::human_name())
+    }
+}
+
+#[cfg(test)]
+use super::p1_switches::LightSwitch;
+#[cfg(test)]
+use super::p2_laundry_machine::{ClothesAction, ClothesMachine, ClothesState};
+
+#[test]
+fn combinators_product_only_touches_one_side() {
+    type Room = Product<LightSwitch, ClothesMachine>;
+    let start = (false, ClothesState::Clean(5));
+
+    assert_eq!(
+        Room::next_state(&start, &Either::Left(())),
+        (true, ClothesState::Clean(5))
+    );
+    assert_eq!(
+        Room::next_state(&start, &Either::Right(ClothesAction::Wear)),
+        (false, ClothesState::Dirty(4))
+    );
+}
+
+#[test]
+fn combinators_product_is_finite() {
+    use super::model_check::{StateGraph, DEFAULT_STATE_LIMIT};
+
+    type TwoLights = Product<LightSwitch, LightSwitch>;
+    let graph = StateGraph::<TwoLights>::explore(&(false, false), DEFAULT_STATE_LIMIT);
+    assert_eq!(graph.states.len(), 4);
+    assert_eq!(graph.edges.len(), 8);
+}
+
+#[test]
+fn combinators_sequence_applies_in_order() {
+    let batch = vec![ClothesAction::Wear, ClothesAction::Wash, ClothesAction::Dry];
+    assert_eq!(
+        Sequence::<ClothesMachine>::next_state(&ClothesState::Clean(10), &batch),
+        ClothesState::Clean(7)
+    );
+    assert_eq!(
+        Sequence::<ClothesMachine>::next_state(&ClothesState::Clean(10), &vec![]),
+        ClothesState::Clean(10)
+    );
+}
+
+#[test]
+fn combinators_mapped_translates_and_filters() {
+    /// Only lets the clothes be worn on even days
+    struct EvenDays;
+    impl TransitionMap for EvenDays {
+        type Input = u32;
+        type Output = ClothesAction;
+        fn map(day: &u32) -> Option<ClothesAction> {
+            day.is_multiple_of(2).then_some(ClothesAction::Wear)
+        }
+    }
+    type Weekly = Mapped<ClothesMachine, EvenDays>;
+
+    assert_eq!(
+        Weekly::next_state(&ClothesState::Clean(5), &2),
+        ClothesState::Dirty(4)
+    );
+    assert_eq!(
+        Weekly::next_state(&ClothesState::Clean(5), &3),
+        ClothesState::Clean(5)
+    );
+}
+
+#[test]
+fn combinators_wardrobe_of_garments() {
+    type Wardrobe = Many<ClothesMachine, &'static str>;
+    let mut wardrobe = Wardrobe::next_state(
+        &BTreeMap::new(),
+        &Instance::Add("shirt", ClothesState::Clean(5)),
+    );
+    wardrobe = Wardrobe::next_state(&wardrobe, &Instance::Add("socks", ClothesState::Clean(2)));
+    wardrobe = Wardrobe::next_state(&wardrobe, &Instance::Apply("shirt", ClothesAction::Wear));
+
+    assert_eq!(wardrobe["shirt"], ClothesState::Dirty(4));
+    assert_eq!(wardrobe["socks"], ClothesState::Clean(2));
+
+    // Wearing a garment we don't own does nothing
+    let unchanged = Wardrobe::next_state(&wardrobe, &Instance::Apply("hat", ClothesAction::Wear));
+    assert_eq!(unchanged, wardrobe);
+
+    wardrobe = Wardrobe::next_state(&wardrobe, &Instance::Remove("socks"));
+    assert_eq!(wardrobe.keys().collect::<Vec<_>>(), vec![&"shirt"]);
+}
+
+#[test]
+fn combinators_many_reports_rejections() {
+    use super::p3_atm::{Action, Atm, AtmError, Key};
+
+    type Atms = Many<Atm, u8>;
+    let atms = <Atms as TryStateMachine>::try_next_state(
+        &BTreeMap::new(),
+        &Instance::Add(1, Atm::new(100, 3)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        Atms::try_next_state(&atms, &Instance::Apply(2, Action::PressKey(Key::One))),
+        Err(InstanceError::NoSuchInstance(2))
+    );
+    assert_eq!(
+        Atms::try_next_state(&atms, &Instance::Apply(1, Action::PressKey(Key::One))),
+        Err(InstanceError::Rejected(1, AtmError::NoCardInserted))
+    );
+}
@@ -0,0 +1,175 @@
+//! Pictures of state machines are much easier to understand than tables of transitions. Graphviz
+//! can draw them for us if we describe the machine in its DOT language. Each reachable state
+//! becomes a node, and each transition becomes a labelled edge.
+//!
+//! The output can be rendered with, for example, `dot -Tsvg machine.dot > machine.svg`.
+
+use super::model_check::{StateGraph, DEFAULT_STATE_LIMIT};
+use super::FiniteStateMachine;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Renders the reachable part of a finite state machine as a Graphviz DOT graph.
+pub struct DotExporter<M: FiniteStateMachine> {
+    /// A run of the machine to draw attention to
+    highlight: Vec<M::Transition>,
+    /// Stop exploring after this many states
+    limit: usize,
+    _machine: PhantomData<M>,
+}
+
+impl<M> DotExporter<M>
+where
+    M: FiniteStateMachine,
+    M::State: Clone + Eq + Hash + Debug,
+    M::Transition: Clone + Debug,
+{
+    /// An exporter that highlights nothing.
+    pub fn new() -> Self {
+        Self {
+            highlight: Vec::new(),
+            limit: DEFAULT_STATE_LIMIT,
+            _machine: PhantomData,
+        }
+    }
+
+    /// Highlight the states and edges visited by applying these transitions, in order,
+    /// starting from the start state.
+    pub fn highlight(mut self, trace: &[M::Transition]) -> Self {
+        self.highlight = trace.to_vec();
+        self
+    }
+
+    /// Stop exploring after this many states.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Explore from the given start state and render everything that is reachable.
+    ///
+    /// The start state is drawn with a double border. If the state limit was reached, the
+    /// graph is incomplete and a comment saying so is included.
+    pub fn render(&self, start: &M::State) -> String {
+        let graph = StateGraph::<M>::explore(start, self.limit);
+
+        // Walk the highlighted trace through the graph to find which nodes and edges it touches.
+        let mut nodes = HashSet::from([0]);
+        let mut edges = HashSet::new();
+        let mut current = start.clone();
+        for t in &self.highlight {
+            let next = M::next_state(&current, t);
+            let (Some(from), Some(to)) = (graph.index_of(&current), graph.index_of(&next)) else {
+                break;
+            };
+            edges.insert((from, to, format!("{:?}", t)));
+            nodes.insert(to);
+            current = next;
+        }
+        let highlighting = !self.highlight.is_empty();
+
+        let mut dot = format!("digraph \"{}\" {{\n", escape(&M::human_name()));
+        dot.push_str("    rankdir=LR;\n");
+        if graph.truncated {
+            dot.push_str(&format!(
+                "    // Truncated after {} states\n",
+                graph.states.len()
+            ));
+        }
+
+        for (i, state) in graph.states.iter().enumerate() {
+            let mut attributes = vec![format!("label=\"{}\"", escape(&format!("{:?}", state)))];
+            if i == 0 {
+                attributes.push("peripheries=2".into());
+            }
+            if highlighting && nodes.contains(&i) {
+                attributes.push("color=red".into());
+            }
+            dot.push_str(&format!("    {} [{}];\n", i, attributes.join(", ")));
+        }
+
+        for (from, t, to) in &graph.edges {
+            let label = format!("{:?}", t);
+            let mut attributes = vec![format!("label=\"{}\"", escape(&label))];
+            if edges.contains(&(*from, *to, label)) {
+                attributes.push("color=red".into());
+                attributes.push("penwidth=2".into());
+            }
+            dot.push_str(&format!(
+                "    {} -> {} [{}];\n",
+                from,
+                to,
+                attributes.join(", ")
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl<M> Default for DotExporter<M>
+where
+    M: FiniteStateMachine,
+    M::State: Clone + Eq + Hash + Debug,
+    M::Transition: Clone + Debug,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Escape a string so it can appear inside a quoted DOT identifier.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+use super::p1_switches::LightSwitch;
+#[cfg(test)]
+use super::p2_laundry_machine::{ClothesAction, ClothesMachine, ClothesState};
+
+#[test]
+fn dot_light_switch() {
+    let dot = DotExporter::<LightSwitch>::new().render(&false);
+    assert_eq!(
+        dot,
+        "digraph \"Light Switch\" {\n    \
+            rankdir=LR;\n    \
+            0 [label=\"false\", peripheries=2];\n    \
+            1 [label=\"true\"];\n    \
+            0 -> 1 [label=\"()\"];\n    \
+            1 -> 0 [label=\"()\"];\n\
+        }\n"
+    );
+}
+
+#[test]
+fn dot_highlights_trace() {
+    let dot = DotExporter::<ClothesMachine>::new()
+        .highlight(&[ClothesAction::Wear, ClothesAction::Wash])
+        .render(&ClothesState::Clean(4));
+
+    assert!(dot.contains("0 [label=\"Clean(4)\", peripheries=2, color=red];"));
+    assert!(dot.contains("[label=\"Dirty(3)\", color=red];"));
+    assert!(dot.contains("[label=\"Wet(2)\", color=red];"));
+    assert!(dot.contains("[label=\"Wear\", color=red, penwidth=2];"));
+    assert!(dot.contains("[label=\"Wash\", color=red, penwidth=2];"));
+    // Only the two edges in the trace are highlighted
+    assert_eq!(dot.matches("penwidth").count(), 2);
+}
+
+#[test]
+fn dot_notes_truncation() {
+    let dot = DotExporter::<ClothesMachine>::new()
+        .limit(3)
+        .render(&ClothesState::Clean(10));
+    assert!(dot.contains("// Truncated after 3 states"));
+}
+
+#[test]
+fn dot_escapes_labels() {
+    assert_eq!(escape(r#"say "hi" \ bye"#), r#"say \"hi\" \\ bye"#);
+}
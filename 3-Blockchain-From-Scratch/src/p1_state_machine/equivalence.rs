@@ -0,0 +1,382 @@
+//! Two machines may look nothing alike inside and still behave identically. A student's switch
+//! machine may store its state differently from the reference, and a dial with four positions
+//! behaves just like a light switch if all we can see is whether the position is odd.
+//!
+//! What we can see is given by an observation function over states. Two machines are equivalent
+//! from a pair of start states when every sequence of transitions leads them to states with the
+//! same observation. To check this we explore both machines in lockstep, one pair of states at a
+//! time. Because the search is breadth first, when the machines differ we find a shortest
+//! sequence of transitions that tells them apart.
+//!
+//! The same idea applied to a single machine lets us minimize it, in the style of a DFA. States
+//! that no sequence of transitions can tell apart are merged into one, until every remaining
+//! state is distinguishable from every other.
+
+use super::model_check::{Report, StateGraph, DEFAULT_STATE_LIMIT};
+use super::FiniteStateMachine;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// An observation of a state
+type Observer<S, O> = Box<dyn Fn(&S) -> O>;
+
+/// A shortest sequence of transitions after which two machines can be told apart.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Distinction<T, O> {
+    /// The transitions that lead both machines from their start states to states that differ.
+    /// Empty if the start states themselves differ.
+    pub trace: Vec<T>,
+    /// What was observed of the first machine after the trace
+    pub a: O,
+    /// What was observed of the second machine after the trace
+    pub b: O,
+}
+
+/// Checks whether two finite state machines with the same transitions behave identically.
+///
+/// From each pair of states, every transition offered by either machine is tried on both.
+pub struct EquivalenceChecker<A, B, O>
+where
+    A: FiniteStateMachine,
+    B: FiniteStateMachine<Transition = A::Transition>,
+{
+    observe_a: Observer<A::State, O>,
+    observe_b: Observer<B::State, O>,
+    limit: usize,
+}
+
+impl<A, B, O> EquivalenceChecker<A, B, O>
+where
+    A: FiniteStateMachine,
+    B: FiniteStateMachine<Transition = A::Transition>,
+    A::State: Clone + Eq + Hash,
+    B::State: Clone + Eq + Hash,
+    A::Transition: Clone + PartialEq,
+    O: PartialEq,
+{
+    /// A checker that compares the machines by what `observe_a` and `observe_b` see of their
+    /// states.
+    pub fn new(
+        observe_a: impl Fn(&A::State) -> O + 'static,
+        observe_b: impl Fn(&B::State) -> O + 'static,
+    ) -> Self {
+        Self {
+            observe_a: Box::new(observe_a),
+            observe_b: Box::new(observe_b),
+            limit: DEFAULT_STATE_LIMIT,
+        }
+    }
+
+    /// Stop exploring after this many pairs of states.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Explore both machines in lockstep from the given start states. The report counts pairs
+    /// of states rather than single states.
+    pub fn check(
+        &self,
+        a: &A::State,
+        b: &B::State,
+    ) -> Result<Report, Distinction<A::Transition, O>> {
+        let mut pairs = vec![(a.clone(), b.clone())];
+        let mut parents: Vec<Option<(usize, A::Transition)>> = vec![None];
+        let mut index = HashMap::from([((a.clone(), b.clone()), 0)]);
+        let mut queue = VecDeque::from([0]);
+        let mut transitions = 0;
+        let mut truncated = false;
+
+        let trace_to = |parents: &[Option<(usize, A::Transition)>], mut pair: usize| {
+            let mut trace = Vec::new();
+            while let Some((from, t)) = &parents[pair] {
+                trace.push(t.clone());
+                pair = *from;
+            }
+            trace.reverse();
+            trace
+        };
+        self.compare(&pairs[0], Vec::new)?;
+
+        while let Some(from) = queue.pop_front() {
+            let (a, b) = pairs[from].clone();
+            let mut offered = A::transitions(&a);
+            for t in B::transitions(&b) {
+                if !offered.contains(&t) {
+                    offered.push(t);
+                }
+            }
+            for t in offered {
+                transitions += 1;
+                let pair = (A::next_state(&a, &t), B::next_state(&b, &t));
+                if index.contains_key(&pair) {
+                    continue;
+                }
+                if pairs.len() >= self.limit {
+                    truncated = true;
+                    continue;
+                }
+                self.compare(&pair, || {
+                    let mut trace = trace_to(&parents, from);
+                    trace.push(t.clone());
+                    trace
+                })?;
+                index.insert(pair.clone(), pairs.len());
+                queue.push_back(pairs.len());
+                pairs.push(pair);
+                parents.push(Some((from, t)));
+            }
+        }
+
+        Ok(Report {
+            states: pairs.len(),
+            transitions,
+            truncated,
+        })
+    }
+
+    /// Fail with the given trace if the pair of states can be told apart.
+    fn compare(
+        &self,
+        (a, b): &(A::State, B::State),
+        trace: impl FnOnce() -> Vec<A::Transition>,
+    ) -> Result<(), Distinction<A::Transition, O>> {
+        let (a, b) = ((self.observe_a)(a), (self.observe_b)(b));
+        if a == b {
+            Ok(())
+        } else {
+            Err(Distinction {
+                trace: trace(),
+                a,
+                b,
+            })
+        }
+    }
+}
+
+/// The reachable part of a finite state machine with indistinguishable states merged.
+///
+/// A transition a state doesn't offer counts as a difference between it and a state that does.
+pub struct Minimized<M: FiniteStateMachine> {
+    /// The reachable states, grouped into classes that can't be told apart. Classes are ordered
+    /// by their first state's discovery, so the start state is always in the first class.
+    pub classes: Vec<Vec<M::State>>,
+    /// Every transition between classes, as `(from, transition, to)` where `from` and `to` are
+    /// indices into `classes`.
+    pub edges: Vec<(usize, M::Transition, usize)>,
+    /// Whether exploration stopped early because the state limit was reached. When this is
+    /// true the classes are only a guess.
+    pub truncated: bool,
+    /// Look up a state's class
+    index: HashMap<M::State, usize>,
+}
+
+impl<M> Minimized<M>
+where
+    M: FiniteStateMachine,
+    M::State: Clone + Eq + Hash,
+    M::Transition: Clone + PartialEq,
+{
+    /// Explore every state reachable from `start`, stopping once `limit` states are known, and
+    /// merge those that `observe` can't tell apart after any sequence of transitions.
+    pub fn new<O: Eq + Hash>(
+        start: &M::State,
+        observe: impl Fn(&M::State) -> O,
+        limit: usize,
+    ) -> Self {
+        let graph = StateGraph::<M>::explore(start, limit);
+        let n = graph.states.len();
+
+        // Number the distinct transitions, and record where each state goes under each one.
+        let mut alphabet: Vec<M::Transition> = Vec::new();
+        let mut successors = vec![Vec::new(); n];
+        for (from, t, to) in &graph.edges {
+            let letter = match alphabet.iter().position(|known| known == t) {
+                Some(letter) => letter,
+                None => {
+                    alphabet.push(t.clone());
+                    alphabet.len() - 1
+                }
+            };
+            successors[*from].push((letter, *to));
+        }
+
+        // Start with states split by what can be seen of them, then keep splitting classes whose
+        // members lead to different classes, until nothing changes.
+        let mut class = renumber(graph.states.iter().map(&observe));
+        let mut count = class.iter().max().map_or(0, |max| max + 1);
+        loop {
+            let refined = renumber((0..n).map(|state| {
+                let mut row = vec![None; alphabet.len()];
+                for &(letter, to) in &successors[state] {
+                    row[letter] = Some(class[to]);
+                }
+                (class[state], row)
+            }));
+            let refined_count = refined.iter().max().map_or(0, |max| max + 1);
+            class = refined;
+            if refined_count == count {
+                break;
+            }
+            count = refined_count;
+        }
+
+        let mut classes = vec![Vec::new(); count];
+        let mut index = HashMap::new();
+        for (state, &c) in graph.states.iter().zip(&class) {
+            classes[c].push(state.clone());
+            index.insert(state.clone(), c);
+        }
+        // Every member of a class behaves the same way, so the first one speaks for them all.
+        let mut edges = Vec::new();
+        let mut done = vec![false; count];
+        for state in 0..n {
+            if !done[class[state]] {
+                done[class[state]] = true;
+                for &(letter, to) in &successors[state] {
+                    edges.push((class[state], alphabet[letter].clone(), class[to]));
+                }
+            }
+        }
+
+        Self {
+            classes,
+            edges,
+            truncated: graph.truncated,
+            index,
+        }
+    }
+
+    /// The index of the class the given state belongs to, if it was discovered.
+    pub fn class_of(&self, state: &M::State) -> Option<usize> {
+        self.index.get(state).copied()
+    }
+
+    /// How many states the minimized machine has.
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Whether the minimized machine has no states. This never happens, as the start state is
+    /// always reachable.
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+}
+
+/// Number each distinct key in order of first appearance.
+fn renumber<K: Eq + Hash>(keys: impl Iterator<Item = K>) -> Vec<usize> {
+    let mut numbers = HashMap::new();
+    keys.map(|key| {
+        let next = numbers.len();
+        *numbers.entry(key).or_insert(next)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+use super::p1_switches::{LightSwitch, Toggle, TwoSwitches, WeirdSwitchMachine};
+#[cfg(test)]
+use super::repl::ReplMachine;
+#[cfg(test)]
+use super::StateMachine;
+
+/// A dial with some number of positions that turns one step at a time, wrapping around.
+#[cfg(test)]
+struct Dial<const N: u8>;
+
+#[cfg(test)]
+impl<const N: u8> StateMachine for Dial<N> {
+    type State = u8;
+    type Transition = ();
+
+    fn next_state(starting_state: &u8, _: &()) -> u8 {
+        (starting_state + 1) % N
+    }
+}
+
+#[cfg(test)]
+impl<const N: u8> FiniteStateMachine for Dial<N> {
+    fn transitions(_: &u8) -> Vec<()> {
+        vec![()]
+    }
+}
+
+#[test]
+fn equivalence_dial_behaves_like_light_switch() {
+    let report = EquivalenceChecker::<LightSwitch, Dial<4>, bool>::new(|on| *on, |n| n % 2 == 1)
+        .check(&false, &0)
+        .unwrap();
+    assert_eq!(report.states, 4);
+    assert_eq!(report.transitions, 4);
+    assert!(!report.truncated);
+}
+
+#[test]
+fn equivalence_finds_shortest_distinction() {
+    // With three positions, the dial comes back round to an even number after an odd number of
+    // turns.
+    let distinction =
+        EquivalenceChecker::<LightSwitch, Dial<3>, bool>::new(|on| *on, |n| n % 2 == 1)
+            .check(&false, &0)
+            .unwrap_err();
+    assert_eq!(distinction.trace, vec![(), (), ()]);
+    assert_eq!((distinction.a, distinction.b), (true, false));
+
+    let distinction =
+        EquivalenceChecker::<LightSwitch, Dial<4>, bool>::new(|on| *on, |n| n % 2 == 1)
+            .check(&true, &0)
+            .unwrap_err();
+    assert!(distinction.trace.is_empty());
+}
+
+#[test]
+fn equivalence_limit_truncates() {
+    let report = EquivalenceChecker::<Dial<100>, Dial<100>, u8>::new(|n| *n, |n| *n)
+        .limit(10)
+        .check(&0, &0)
+        .unwrap();
+    assert_eq!(report.states, 10);
+    assert!(report.truncated);
+}
+
+#[test]
+fn equivalence_minimizes_dial() {
+    let minimized = Minimized::<Dial<6>>::new(&0, |n| n % 3 == 0, DEFAULT_STATE_LIMIT);
+    assert_eq!(minimized.len(), 3);
+    assert_eq!(minimized.classes, vec![vec![0, 3], vec![1, 4], vec![2, 5]]);
+    assert_eq!(minimized.edges, vec![(0, (), 1), (1, (), 2), (2, (), 0)]);
+    assert_eq!(minimized.class_of(&4), Some(1));
+    assert!(!minimized.truncated);
+
+    // Seeing nothing at all, every position looks the same.
+    let minimized = Minimized::<Dial<6>>::new(&0, |_| (), DEFAULT_STATE_LIMIT);
+    assert_eq!(minimized.len(), 1);
+    assert_eq!(minimized.edges, vec![(0, (), 0)]);
+}
+
+#[test]
+fn equivalence_weird_switch_is_already_minimal() {
+    let start = WeirdSwitchMachine::initial_state();
+    let minimized = Minimized::<WeirdSwitchMachine>::new(
+        &start,
+        |s: &TwoSwitches| s.clone(),
+        DEFAULT_STATE_LIMIT,
+    );
+    assert_eq!(minimized.len(), 4);
+    assert_eq!(minimized.edges.len(), 8);
+
+    // If all we can see is whether every light is off, then once the first switch is on we
+    // can't find out about the second. Toggling it never turns everything off, and toggling
+    // the first switch always does.
+    let all_off = start.clone();
+    let minimized =
+        Minimized::<WeirdSwitchMachine>::new(&start, move |s| s == &all_off, DEFAULT_STATE_LIMIT);
+    let first = WeirdSwitchMachine::next_state(&start, &Toggle::FirstSwitch);
+    let second = WeirdSwitchMachine::next_state(&start, &Toggle::SecondSwitch);
+    let both = WeirdSwitchMachine::next_state(&first, &Toggle::SecondSwitch);
+    assert_eq!(minimized.len(), 3);
+    assert_eq!(minimized.class_of(&start), Some(0));
+    assert_eq!(minimized.class_of(&first), minimized.class_of(&both));
+    assert_ne!(minimized.class_of(&first), minimized.class_of(&second));
+}
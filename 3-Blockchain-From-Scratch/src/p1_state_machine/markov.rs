@@ -0,0 +1,503 @@
+//! Some machines are most interesting when they are used at random. How many times can a shirt
+//! be worn before it falls apart, if it is usually washed after wearing but now and then gets
+//! worn again while dirty? How long does a prize game last when every player has a small chance
+//! of stopping it each tick?
+//!
+//! If we know how likely each transition is from each state, the machine becomes a Markov chain.
+//! This module studies such chains in two ways:
+//! * Monte Carlo: take many random walks with a seeded random number generator and count what
+//!   happens. This works for any machine, however large its state space.
+//! * Exactly: discover every state the walks could reach and solve the linear equations for the
+//!   expected number of steps until absorption. This only works when the reachable states are few.
+//!
+//! A state is absorbing when nothing more can happen in it: either the distribution gives it no
+//! transitions at all, or the user has said so with a predicate, e.g. "the clothes are tattered".
+
+use super::StateMachine;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// The default number of steps a single walk may take before it is abandoned.
+pub const DEFAULT_STEP_LIMIT: usize = 10_000;
+
+/// The default number of states to discover before giving up on an exact answer.
+pub const DEFAULT_STATE_LIMIT: usize = 1_000;
+
+/// The transitions possible from a state, each with a relative weight
+type Distribution<M> =
+    Box<dyn Fn(&<M as StateMachine>::State) -> Vec<(<M as StateMachine>::Transition, u32)>>;
+
+/// A predicate over a single state
+type StatePredicate<M> = Box<dyn Fn(&<M as StateMachine>::State) -> bool>;
+
+/// A predicate over a single transition
+type TransitionPredicate<M> = Box<dyn Fn(&<M as StateMachine>::Transition) -> bool>;
+
+/// A state machine together with a probability distribution over its transitions.
+///
+/// Weights are integers so that walks are exactly reproducible from their seed. A transition's
+/// probability is its weight divided by the total weight of every transition from that state.
+pub struct MarkovChain<M: StateMachine> {
+    distribution: Distribution<M>,
+    absorbing: Vec<StatePredicate<M>>,
+    counted: Option<TransitionPredicate<M>>,
+    step_limit: usize,
+}
+
+/// A single random walk
+pub struct Walk<M: StateMachine> {
+    /// Every state visited, in order. The start state comes first.
+    pub states: Vec<M::State>,
+    /// How many of the steps taken were counted
+    pub counted: usize,
+    /// Whether the walk ended in an absorbing state, rather than hitting the step limit
+    pub absorbed: bool,
+}
+
+/// What happened over many random walks
+pub struct Statistics<M: StateMachine> {
+    /// How many walks were taken
+    pub walks: usize,
+    /// How many walks ended in an absorbing state. The rest hit the step limit.
+    pub absorbed: usize,
+    /// For each number of counted steps, how many walks were absorbed after exactly that many
+    pub absorption_times: BTreeMap<usize, usize>,
+    /// How many times each state was visited across every walk, in the order the states were
+    /// first seen. The start state comes first.
+    pub occupancy: Vec<(M::State, usize)>,
+}
+
+impl<M> MarkovChain<M>
+where
+    M: StateMachine,
+    M::State: Clone + Eq + Hash,
+{
+    /// A chain where transitions from each state are chosen according to `distribution`.
+    ///
+    /// Transitions with zero weight are never taken. A state with no positive weight
+    /// transitions is absorbing.
+    pub fn new(distribution: impl Fn(&M::State) -> Vec<(M::Transition, u32)> + 'static) -> Self {
+        Self {
+            distribution: Box::new(distribution),
+            absorbing: Vec::new(),
+            counted: None,
+            step_limit: DEFAULT_STEP_LIMIT,
+        }
+    }
+
+    /// Also treat states matching `predicate` as absorbing.
+    pub fn absorbing(mut self, predicate: impl Fn(&M::State) -> bool + 'static) -> Self {
+        self.absorbing.push(Box::new(predicate));
+        self
+    }
+
+    /// Only count transitions matching `predicate` as steps, e.g. only wears rather than every
+    /// wash and dry in between. By default every transition counts.
+    pub fn count_only(mut self, predicate: impl Fn(&M::Transition) -> bool + 'static) -> Self {
+        self.counted = Some(Box::new(predicate));
+        self
+    }
+
+    /// Abandon any walk after this many steps, counted or not.
+    pub fn step_limit(mut self, limit: usize) -> Self {
+        self.step_limit = limit;
+        self
+    }
+
+    /// Whether a walk stops when it reaches this state.
+    pub fn is_absorbing(&self, state: &M::State) -> bool {
+        self.absorbing.iter().any(|predicate| predicate(state))
+            || (self.distribution)(state)
+                .iter()
+                .all(|(_, weight)| *weight == 0)
+    }
+
+    /// Whether a transition counts as a step.
+    fn counts(&self, transition: &M::Transition) -> bool {
+        match &self.counted {
+            Some(predicate) => predicate(transition),
+            None => true,
+        }
+    }
+
+    /// Choose a transition from `state` at random, or `None` if the state is absorbing.
+    fn choose(&self, state: &M::State, rng: &mut StdRng) -> Option<M::Transition> {
+        if self.absorbing.iter().any(|predicate| predicate(state)) {
+            return None;
+        }
+        let options = (self.distribution)(state);
+        let total: u64 = options.iter().map(|(_, weight)| *weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = rng.gen_range(0..total);
+        for (transition, weight) in options {
+            if roll < weight as u64 {
+                return Some(transition);
+            }
+            roll -= weight as u64;
+        }
+        unreachable!("the roll is less than the total weight")
+    }
+
+    /// Take a single random walk from `start`.
+    pub fn walk(&self, start: &M::State, rng: &mut StdRng) -> Walk<M> {
+        let mut walk = Walk {
+            states: vec![start.clone()],
+            counted: 0,
+            absorbed: false,
+        };
+        let mut state = start.clone();
+        for _ in 0..self.step_limit {
+            let Some(transition) = self.choose(&state, rng) else {
+                walk.absorbed = true;
+                return walk;
+            };
+            if self.counts(&transition) {
+                walk.counted += 1;
+            }
+            state = M::next_state(&state, &transition);
+            walk.states.push(state.clone());
+        }
+        walk.absorbed = self.is_absorbing(&state);
+        walk
+    }
+
+    /// Take `walks` random walks from `start`, seeding the random number generator with `seed`.
+    pub fn simulate(&self, start: &M::State, walks: usize, seed: u64) -> Statistics<M> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut statistics = Statistics {
+            walks,
+            absorbed: 0,
+            absorption_times: BTreeMap::new(),
+            occupancy: Vec::new(),
+        };
+        let mut index = HashMap::new();
+        for _ in 0..walks {
+            let walk = self.walk(start, &mut rng);
+            if walk.absorbed {
+                statistics.absorbed += 1;
+                *statistics.absorption_times.entry(walk.counted).or_insert(0) += 1;
+            }
+            for state in walk.states {
+                let i = *index.entry(state.clone()).or_insert_with(|| {
+                    statistics.occupancy.push((state, 0));
+                    statistics.occupancy.len() - 1
+                });
+                statistics.occupancy[i].1 += 1;
+            }
+        }
+        statistics
+    }
+
+    /// The exact expected number of counted steps from `start` until absorption.
+    ///
+    /// Returns `None` if more than `limit` states are reachable, or if absorption is not
+    /// certain, i.e. some reachable state cannot reach an absorbing one.
+    pub fn expected_steps(&self, start: &M::State, limit: usize) -> Option<f64> {
+        if limit == 0 {
+            // Not even the start fits
+            return None;
+        }
+        // Discover every reachable state, writing one equation for each as we go:
+        // E[s] = cost(s) + sum over transitions of p(t) * E[next(s, t)]
+        let mut states = vec![start.clone()];
+        let mut index = HashMap::from([(start.clone(), 0)]);
+        let mut queue = VecDeque::from([0]);
+        let mut equations = Vec::new();
+        while let Some(from) = queue.pop_front() {
+            let state = states[from].clone();
+            let mut terms = Vec::new();
+            let mut cost = 0.0;
+            if !self.absorbing.iter().any(|predicate| predicate(&state)) {
+                let options = (self.distribution)(&state);
+                let total: u64 = options.iter().map(|(_, weight)| *weight as u64).sum();
+                for (transition, weight) in options.into_iter().filter(|(_, w)| *w > 0) {
+                    let p = weight as f64 / total as f64;
+                    if self.counts(&transition) {
+                        cost += p;
+                    }
+                    let next = M::next_state(&state, &transition);
+                    let to = match index.get(&next) {
+                        Some(&to) => to,
+                        None => {
+                            if states.len() >= limit {
+                                return None;
+                            }
+                            index.insert(next.clone(), states.len());
+                            queue.push_back(states.len());
+                            states.push(next);
+                            states.len() - 1
+                        }
+                    };
+                    terms.push((to, p));
+                }
+            }
+            equations.push((from, terms, cost));
+        }
+
+        // Rearranged, each equation reads E[s] - sum of p(t) * E[next] = cost(s).
+        let n = states.len();
+        let mut matrix = vec![vec![0.0; n + 1]; n];
+        for (from, terms, cost) in equations {
+            matrix[from][from] += 1.0;
+            for (to, p) in terms {
+                matrix[from][to] -= p;
+            }
+            matrix[from][n] = cost;
+        }
+        solve(matrix).map(|solution| solution[0])
+    }
+}
+
+/// Solve a system of linear equations by Gaussian elimination with partial pivoting. Each row
+/// holds the coefficients followed by the right hand side. Returns `None` if the system is
+/// singular.
+fn solve(mut matrix: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let n = matrix.len();
+    for column in 0..n {
+        let pivot = (column..n)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        if matrix[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        for row in 0..n {
+            if row != column {
+                let factor = matrix[row][column] / matrix[column][column];
+                if factor != 0.0 {
+                    let pivot_row = matrix[column].clone();
+                    for (x, p) in matrix[row].iter_mut().zip(pivot_row).skip(column) {
+                        *x -= factor * p;
+                    }
+                }
+            }
+        }
+    }
+    Some(
+        (0..n)
+            .map(|row| matrix[row][n] / matrix[row][row])
+            .collect(),
+    )
+}
+
+impl<M> Statistics<M>
+where
+    M: StateMachine,
+    M::State: Eq,
+{
+    /// The mean number of counted steps taken by walks that were absorbed, or `None` if none
+    /// were.
+    pub fn mean_steps(&self) -> Option<f64> {
+        if self.absorbed == 0 {
+            return None;
+        }
+        let total: usize = self
+            .absorption_times
+            .iter()
+            .map(|(steps, walks)| steps * walks)
+            .sum();
+        Some(total as f64 / self.absorbed as f64)
+    }
+
+    /// How many times the given state was visited across every walk.
+    pub fn visits(&self, state: &M::State) -> usize {
+        self.occupancy
+            .iter()
+            .find(|(s, _)| s == state)
+            .map_or(0, |(_, visits)| *visits)
+    }
+
+    /// The occupancy as a text histogram, one state per line, with the longest bar `width`
+    /// characters wide.
+    pub fn histogram(&self, width: usize) -> String
+    where
+        M::State: Debug,
+    {
+        let most = self.occupancy.iter().map(|(_, visits)| *visits).max();
+        let labels: Vec<String> = self
+            .occupancy
+            .iter()
+            .map(|(state, _)| format!("{:?}", state))
+            .collect();
+        let label_width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+        let mut out = String::new();
+        for (label, (_, visits)) in labels.iter().zip(&self.occupancy) {
+            let bar = most.map_or(0, |most| visits * width / most);
+            out.push_str(&format!(
+                "{:<label_width$} {} {}\n",
+                label,
+                "#".repeat(bar),
+                visits
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+use super::p1_switches::LightSwitch;
+#[cfg(test)]
+use super::p2_laundry_machine::{ClothesAction, ClothesMachine, ClothesState};
+#[cfg(test)]
+use super::p4_open_ended::growing_prize::{Game, GrowingPrize, Phase, PrizeAction, Rules};
+
+/// Clothes that are usually washed and dried after wearing, but sometimes worn again as they
+/// are, and sometimes ironed or folded.
+#[cfg(test)]
+fn everyday_use() -> MarkovChain<ClothesMachine> {
+    MarkovChain::new(|state| match state {
+        ClothesState::Dirty(_) => vec![(ClothesAction::Wash, 3), (ClothesAction::Wear, 1)],
+        ClothesState::Wet(_) => vec![(ClothesAction::Dry, 1)],
+        _ => vec![
+            (ClothesAction::Wear, 4),
+            (ClothesAction::Iron, 1),
+            (ClothesAction::Fold, 1),
+        ],
+    })
+    .absorbing(|state| state == &ClothesState::Tattered)
+    .count_only(|action| action == &ClothesAction::Wear)
+}
+
+#[test]
+fn markov_deterministic_use_matches_by_hand() {
+    // Wearing and nothing else: one wear takes clean clothes from 10 to 9, then four dirty wears
+    // take them to 1, and the sixth tears them.
+    let chain = MarkovChain::<ClothesMachine>::new(|_| vec![(ClothesAction::Wear, 1)])
+        .absorbing(|state| state == &ClothesState::Tattered);
+    let start = ClothesState::Clean(10);
+    assert_eq!(chain.expected_steps(&start, DEFAULT_STATE_LIMIT), Some(6.0));
+
+    let statistics = chain.simulate(&start, 10, 0);
+    assert_eq!(statistics.absorbed, 10);
+    assert_eq!(statistics.absorption_times, BTreeMap::from([(6, 10)]));
+    assert_eq!(statistics.mean_steps(), Some(6.0));
+    assert_eq!(statistics.visits(&ClothesState::Dirty(7)), 10);
+    assert_eq!(statistics.visits(&ClothesState::Wet(7)), 0);
+}
+
+#[test]
+fn markov_expected_wears_before_tattered() {
+    let chain = everyday_use();
+    let start = ClothesState::Clean(20);
+    let exact = chain.expected_steps(&start, DEFAULT_STATE_LIMIT).unwrap();
+    let statistics = chain.simulate(&start, 20_000, 42);
+    assert_eq!(statistics.absorbed, statistics.walks);
+    let estimate = statistics.mean_steps().unwrap();
+    assert!(
+        (estimate - exact).abs() < 0.05,
+        "estimate {} exact {}",
+        estimate,
+        exact
+    );
+    // Wearing is the only thing done to clean clothes that always wears them down, so they
+    // can't possibly last more than 20 wears.
+    assert!(exact > 1.0 && exact < 20.0);
+}
+
+#[test]
+fn markov_occupancy_counts_every_visit() {
+    let chain = everyday_use();
+    let start = ClothesState::Clean(10);
+    let statistics = chain.simulate(&start, 1_000, 7);
+    assert_eq!(statistics.occupancy[0], (start.clone(), 1_000));
+    assert_eq!(statistics.visits(&ClothesState::Tattered), 1_000);
+    assert_eq!(statistics.visits(&ClothesState::Clean(11)), 0);
+
+    // Every walk visits one more state than the steps it takes.
+    let mut rng = StdRng::seed_from_u64(7);
+    let steps: usize = (0..1_000)
+        .map(|_| chain.walk(&start, &mut rng).states.len())
+        .sum();
+    let visits: usize = statistics.occupancy.iter().map(|(_, v)| v).sum();
+    assert_eq!(visits, steps);
+
+    let histogram = statistics.histogram(20);
+    assert_eq!(histogram.lines().count(), statistics.occupancy.len());
+    assert!(histogram.starts_with("Clean(10)"));
+    assert!(histogram.contains("Tattered"));
+}
+
+#[test]
+fn markov_same_seed_same_statistics() {
+    let chain = everyday_use();
+    let start = ClothesState::Clean(10);
+    let a = chain.simulate(&start, 500, 1);
+    let b = chain.simulate(&start, 500, 1);
+    let c = chain.simulate(&start, 500, 2);
+    assert_eq!(a.absorption_times, b.absorption_times);
+    assert_eq!(a.occupancy, b.occupancy);
+    assert_ne!(a.absorption_times, c.absorption_times);
+}
+
+#[test]
+fn markov_step_limit_without_absorption() {
+    let chain = MarkovChain::<LightSwitch>::new(|_| vec![((), 1)]).step_limit(10);
+    assert_eq!(chain.expected_steps(&false, DEFAULT_STATE_LIMIT), None);
+
+    let statistics = chain.simulate(&false, 3, 0);
+    assert_eq!(statistics.absorbed, 0);
+    assert_eq!(statistics.mean_steps(), None);
+    assert_eq!(statistics.occupancy, vec![(false, 18), (true, 15)]);
+}
+
+#[test]
+fn markov_state_limit() {
+    let chain = everyday_use();
+    assert_eq!(chain.expected_steps(&ClothesState::Clean(20), 10), None);
+    assert_eq!(chain.expected_steps(&ClothesState::Clean(20), 0), None);
+    assert_eq!(chain.expected_steps(&ClothesState::Tattered, 0), None);
+    assert_eq!(chain.expected_steps(&ClothesState::Tattered, 1), Some(0.0));
+}
+
+#[test]
+fn markov_prize_game_length() {
+    // Once the game is running, each tick somebody stops it with probability one in five, so it
+    // lasts until the first stop or the deadline of 20 ticks, whichever comes first.
+    let rules = Rules::default();
+    let joined = [
+        PrizeAction::Join {
+            account: "alice".into(),
+            amount: 100,
+        },
+        PrizeAction::Join {
+            account: "bob".into(),
+            amount: 100,
+        },
+        PrizeAction::Tick,
+    ];
+    let start = joined.iter().fold(
+        Game::new(rules, 10_000, &[("alice", 1_000), ("bob", 1_000)]),
+        |game, action| GrowingPrize::next_state(&game, action),
+    );
+    assert_eq!(start.phase(), &Phase::Running);
+    let chain = MarkovChain::<GrowingPrize>::new(|game: &Game| match game.phase() {
+        Phase::Running => vec![
+            (PrizeAction::Tick, 4),
+            (
+                PrizeAction::Stop {
+                    account: "alice".into(),
+                },
+                1,
+            ),
+        ],
+        _ => vec![],
+    })
+    .count_only(|action| action == &PrizeAction::Tick);
+
+    // The chance of reaching tick k is 0.8^k, so the expected number of ticks is the sum of
+    // those chances for k from 1 to 20.
+    let by_hand: f64 = (1..=rules.deadline()).map(|k| 0.8f64.powi(k as i32)).sum();
+    let exact = chain.expected_steps(&start, DEFAULT_STATE_LIMIT).unwrap();
+    assert!((exact - by_hand).abs() < 1e-9);
+
+    let statistics = chain.simulate(&start, 10_000, 3);
+    assert_eq!(statistics.absorbed, 10_000);
+    assert!((statistics.mean_steps().unwrap() - by_hand).abs() < 0.1);
+    assert_eq!(
+        statistics.absorption_times.keys().last(),
+        Some(&(rules.deadline() as usize))
+    );
+}
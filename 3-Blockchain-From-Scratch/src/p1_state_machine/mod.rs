@@ -1,9 +1,22 @@
 //! This module is all about modeling phenomina and systems as state machines. We begin with a few simple
 //! examples, and then proceed to build bigger and more complex state machines all implementing the same simple interface.
 
-mod p1_switches;
-mod p2_laundry_machine;
-mod p3_atm;
+pub mod p1_switches;
+pub mod p2_laundry_machine;
+pub mod combinators;
+pub mod dot;
+pub mod equivalence;
+pub mod markov;
+pub mod model_check;
+pub mod p3_atm;
+pub mod p4_open_ended;
+pub mod repl;
+pub mod rollback;
+pub mod table;
+pub mod trace;
+
+use std::convert::Infallible;
+use std::marker::PhantomData;
 
 /// A state machine - Generic over the transition type
 pub trait StateMachine {
@@ -18,12 +31,219 @@ pub trait StateMachine {
     fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
 
     /// A human-readable name for this state machine. This may be used in user-facing
-    /// programs such as the repl described below. This is not in any way related to
+    /// programs such as the one in the `repl` module. This is not in any way related to
     /// the correctness of the state machine.
     fn human_name() -> String {
         "Unnamed state machine".into()
     }
 }
 
-//TODO Some kind of main program that allows users to interact with their state machine in a repl-like way.
-// Might require From<String> implementation for the transition type.
\ No newline at end of file
+/// A state machine whose transitions can be listed.
+///
+/// Most interesting machines have far too many states to write down by hand, but it is often
+/// easy to say which transitions are worth trying from any particular state. Starting from a
+/// known state, that is enough for tools to discover every reachable state on their own.
+pub trait FiniteStateMachine: StateMachine {
+    /// Every transition worth trying from the given state.
+    fn transitions(state: &Self::State) -> Vec<Self::Transition>;
+}
+
+/// A state machine whose transitions may fail.
+///
+/// Not every transition makes sense from every state. An ATM cannot accept a pin before a card
+/// has been swiped, and tattered clothes cannot be worn. Rather than quietly producing some
+/// nonsense state, a fallible machine reports why the transition was rejected.
+pub trait TryStateMachine {
+    /// The states that can be occupied by this machine
+    type State;
+
+    /// The transitions that can be attempted on this machine
+    type Transition;
+
+    /// The reasons a transition may be rejected
+    type Error;
+
+    /// Calculate the resulting state when this state undergoes the given transition,
+    /// or explain why the transition is not possible from this state.
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, Self::Error>;
+
+    /// A human-readable name for this state machine. See `StateMachine::human_name`.
+    fn human_name() -> String {
+        "Unnamed state machine".into()
+    }
+}
+
+/// Adapts any `StateMachine` into a `TryStateMachine` whose transitions never fail.
+pub struct NeverFails<M>(PhantomData<M>);
+
+impl<M: StateMachine> TryStateMachine for NeverFails<M> {
+    type State = M::State;
+    type Transition = M::Transition;
+    type Error = Infallible;
+
+    fn try_next_state(
+        starting_state: &M::State,
+        t: &M::Transition,
+    ) -> Result<M::State, Infallible> {
+        Ok(M::next_state(starting_state, t))
+    }
+
+    fn human_name() -> String {
+        M::human_name()
+    }
+}
+
+/// Adapts any `TryStateMachine` into a `StateMachine`. When a transition is rejected,
+/// the machine simply stays in the state it started in.
+pub struct StayOnError<M>(PhantomData<M>);
+
+impl<M> StateMachine for StayOnError<M>
+where
+    M: TryStateMachine,
+    M::State: Clone,
+{
+    type State = M::State;
+    type Transition = M::Transition;
+
+    fn next_state(starting_state: &M::State, t: &M::Transition) -> M::State {
+        M::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn human_name() -> String {
+        M::human_name()
+    }
+}
+
+/// Marks a `TryStateMachine` that is also a `StateMachine`, behaving exactly like `StayOnError`
+/// around it. Most fallible machines want nothing more than that, so they only need to opt in.
+pub trait RejectsInPlace: TryStateMachine {}
+
+impl<M> StateMachine for M
+where
+    M: RejectsInPlace,
+    <M as TryStateMachine>::State: Clone,
+{
+    type State = <M as TryStateMachine>::State;
+    type Transition = <M as TryStateMachine>::Transition;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        StayOnError::<M>::next_state(starting_state, t)
+    }
+
+    fn human_name() -> String {
+        <M as TryStateMachine>::human_name()
+    }
+}
+
+/// A state machine whose transitions also emit events, in the style of a Mealy machine.
+///
+/// The new state is not always the only interesting result of a transition. An ATM hands
+/// your card back and dispenses cash. A blockchain records what each extrinsic did so that
+/// users can find out later. These outputs are not part of the state, they are things that
+/// happened on the way from one state to the next.
+///
+/// Implementors should make sure that `next_state` agrees with the state returned here.
+pub trait MealyMachine: StateMachine {
+    /// The things that can happen as a side effect of a transition
+    type Event;
+
+    /// Calculate the resulting state when this state undergoes the given transition, along
+    /// with every event emitted along the way, in the order they happened.
+    fn next_state_with_events(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> (Self::State, Vec<Self::Event>);
+}
+
+/// Adapts any `StateMachine` into a `MealyMachine` that never emits any events.
+pub struct Silent<M>(PhantomData<M>);
+
+impl<M: StateMachine> StateMachine for Silent<M> {
+    type State = M::State;
+    type Transition = M::Transition;
+
+    fn next_state(starting_state: &M::State, t: &M::Transition) -> M::State {
+        M::next_state(starting_state, t)
+    }
+
+    fn human_name() -> String {
+        M::human_name()
+    }
+}
+
+impl<M: StateMachine> MealyMachine for Silent<M> {
+    type Event = Infallible;
+
+    fn next_state_with_events(
+        starting_state: &M::State,
+        t: &M::Transition,
+    ) -> (M::State, Vec<Infallible>) {
+        (M::next_state(starting_state, t), Vec::new())
+    }
+}
+
+/// A state machine whose transitions can be undone.
+///
+/// Chain reorgs and undo buttons both need to walk backwards through history. Keeping a copy of
+/// every state visited would work, but states can be large while a single transition usually
+/// touches very little. Instead, applying a transition also produces a small record of what
+/// changed, and that record alone is enough to get the previous state back.
+///
+/// Implementors must make sure that `revert` really is the inverse of `apply`, and that `apply`
+/// agrees with `next_state`.
+pub trait ReversibleMachine: StateMachine {
+    /// Whatever is needed to undo a single transition
+    type Undo;
+
+    /// Calculate the resulting state, along with the record needed to undo the transition.
+    fn apply(starting_state: &Self::State, t: &Self::Transition) -> (Self::State, Self::Undo);
+
+    /// Recover the state a transition was applied to, given the state it produced and the
+    /// record returned by `apply`.
+    fn revert(state: &Self::State, undo: &Self::Undo) -> Self::State;
+}
+
+#[test]
+fn sm_adapter_never_fails_wraps_next_state() {
+    use p1_switches::LightSwitch;
+    assert_eq!(
+        NeverFails::<LightSwitch>::try_next_state(&false, &()),
+        Ok(true)
+    );
+}
+
+#[test]
+fn sm_adapter_stay_on_error_keeps_starting_state() {
+    use p2_laundry_machine::{ClothesAction, ClothesMachine, ClothesState};
+    assert_eq!(
+        StayOnError::<ClothesMachine>::next_state(&ClothesState::Clean(1), &ClothesAction::Wash),
+        ClothesState::Clean(1)
+    );
+    assert_eq!(
+        StayOnError::<ClothesMachine>::next_state(&ClothesState::Clean(3), &ClothesAction::Wash),
+        ClothesState::Wet(1)
+    );
+}
+
+#[test]
+fn sm_adapter_rejects_in_place_keeps_starting_state() {
+    use p4_open_ended::tic_tac_toe::{Game, Move, Player, TicTacToe};
+    let mark = |player, cell| Move { player, cell };
+    let game = TicTacToe::next_state(&Game::new(), &mark(Player::X, 4));
+    assert_ne!(game, Game::new());
+    // The centre is already taken
+    assert_eq!(TicTacToe::next_state(&game, &mark(Player::O, 4)), game);
+    assert_eq!(<TicTacToe as StateMachine>::human_name(), "Tic Tac Toe");
+}
+
+#[test]
+fn sm_adapter_silent_emits_nothing() {
+    use p1_switches::LightSwitch;
+    assert_eq!(
+        Silent::<LightSwitch>::next_state_with_events(&true, &()),
+        (false, Vec::new())
+    );
+}
@@ -0,0 +1,413 @@
+//! When a machine's transitions can be listed, we don't have to guess which states it can reach.
+//! We can simply try every transition from every state we know about until no new states turn up.
+//! This is a breadth first search over the machine's state graph, and it is the foundation of
+//! model checking.
+//!
+//! While exploring, we check invariants supplied by the user. There are two kinds:
+//! * State invariants must hold in every reachable state, e.g. "life never exceeds ten".
+//! * Step invariants must hold for every transition taken, e.g. "tattered is absorbing".
+//!
+//! When an invariant fails, or a transition panics, the checker returns a counterexample: the
+//! sequence of transitions that leads from the start state to the problem. Because the search is
+//! breadth first, it is always a shortest such sequence.
+
+use super::FiniteStateMachine;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fmt;
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+
+/// The default number of states to explore before giving up.
+pub const DEFAULT_STATE_LIMIT: usize = 100_000;
+
+/// A predicate over a single state
+type StateInvariant<M> = Box<dyn Fn(&<M as super::StateMachine>::State) -> bool>;
+
+/// A predicate over a single step: the starting state, the transition, and the resulting state
+type StepInvariant<M> = Box<
+    dyn Fn(
+        &<M as super::StateMachine>::State,
+        &<M as super::StateMachine>::Transition,
+        &<M as super::StateMachine>::State,
+    ) -> bool,
+>;
+
+/// Every state reachable from some start state, and every transition between them.
+pub struct StateGraph<M: FiniteStateMachine> {
+    /// Every state discovered, in the order they were discovered. The start state comes first.
+    pub states: Vec<M::State>,
+    /// Every transition explored, as `(from, transition, to)` where `from` and `to` are
+    /// indices into `states`.
+    pub edges: Vec<(usize, M::Transition, usize)>,
+    /// Whether exploration stopped early because the state limit was reached. When this is
+    /// true the graph is incomplete.
+    pub truncated: bool,
+    /// The edge by which each state was first discovered. `None` for the start state.
+    parents: Vec<Option<usize>>,
+    /// Look up a state's index
+    index: HashMap<M::State, usize>,
+}
+
+impl<M> StateGraph<M>
+where
+    M: FiniteStateMachine,
+    M::State: Clone + Eq + Hash,
+    M::Transition: Clone,
+{
+    /// Explore every state reachable from `start`, stopping once `limit` states are known.
+    ///
+    /// Panics if any transition panics. Use a `ModelChecker` to turn such panics into
+    /// counterexamples instead.
+    pub fn explore(start: &M::State, limit: usize) -> Self {
+        let search =
+            Self::search::<Infallible>(start, limit, |graph, from, _, outcome| match outcome {
+                Ok(_) => Ok(()),
+                Err(message) => panic!(
+                    "transition panicked after {} steps: {}",
+                    graph.path_to(from).len() + 1,
+                    message
+                ),
+            });
+        match search {
+            Ok(graph) => graph,
+            Err(never) => match never {},
+        }
+    }
+
+    /// The index of the given state, if it has been discovered.
+    pub fn index_of(&self, state: &M::State) -> Option<usize> {
+        self.index.get(state).copied()
+    }
+
+    /// The shortest sequence of transitions that leads from the start state to the state with
+    /// the given index.
+    pub fn path_to(&self, mut state: usize) -> Vec<M::Transition> {
+        let mut path = Vec::new();
+        while let Some(edge) = self.parents[state] {
+            let (from, ref t, _) = self.edges[edge];
+            path.push(t.clone());
+            state = from;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The breadth first search shared by exploration and model checking.
+    ///
+    /// `visit` is called for every transition tried, with the graph so far, the index of the
+    /// starting state, the transition, and either the resulting state or the message the
+    /// transition panicked with. Returning an error stops the search.
+    fn search<E>(
+        start: &M::State,
+        limit: usize,
+        mut visit: impl FnMut(&Self, usize, &M::Transition, Result<&M::State, &str>) -> Result<(), E>,
+    ) -> Result<Self, E> {
+        let mut graph = Self {
+            states: vec![start.clone()],
+            edges: Vec::new(),
+            truncated: false,
+            parents: vec![None],
+            index: HashMap::from([(start.clone(), 0)]),
+        };
+        let mut queue = VecDeque::from([0]);
+
+        while let Some(from) = queue.pop_front() {
+            for t in M::transitions(&graph.states[from]) {
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                    M::next_state(&graph.states[from], &t)
+                }));
+                let to = match outcome {
+                    Ok(to) => to,
+                    Err(payload) => {
+                        visit(&graph, from, &t, Err(&panic_message(payload)))?;
+                        continue;
+                    }
+                };
+                visit(&graph, from, &t, Ok(&to))?;
+
+                let to_index = match graph.index.get(&to) {
+                    Some(&i) => i,
+                    None if graph.states.len() >= limit => {
+                        graph.truncated = true;
+                        continue;
+                    }
+                    None => {
+                        let i = graph.states.len();
+                        graph.index.insert(to.clone(), i);
+                        graph.states.push(to);
+                        graph.parents.push(Some(graph.edges.len()));
+                        queue.push_back(i);
+                        i
+                    }
+                };
+                graph.edges.push((from, t, to_index));
+            }
+        }
+
+        Ok(graph)
+    }
+}
+
+/// Extract a readable message from the payload of a caught panic.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".into()
+    }
+}
+
+/// What went wrong in a counterexample
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Violation {
+    /// The named invariant did not hold
+    Invariant(String),
+    /// The final transition panicked with this message
+    Panic(String),
+}
+
+/// A shortest sequence of transitions from the start state that demonstrates a violation.
+pub struct Counterexample<M: FiniteStateMachine> {
+    /// What went wrong
+    pub violation: Violation,
+    /// The state the search started from
+    pub start: M::State,
+    /// The transitions that lead from the start state to the violation. For step invariants and
+    /// panics, the last transition is the offending one.
+    pub trace: Vec<M::Transition>,
+}
+
+impl<M> fmt::Debug for Counterexample<M>
+where
+    M: FiniteStateMachine,
+    M::State: fmt::Debug,
+    M::Transition: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Counterexample")
+            .field("violation", &self.violation)
+            .field("start", &self.start)
+            .field("trace", &self.trace)
+            .finish()
+    }
+}
+
+/// A summary of a successful check
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Report {
+    /// How many distinct states were explored
+    pub states: usize,
+    /// How many transitions were explored
+    pub transitions: usize,
+    /// Whether the state limit was hit before the search finished. When this is true, the
+    /// invariants only hold for the part of the state space that was explored.
+    pub truncated: bool,
+}
+
+/// Checks invariants against every reachable state of a finite state machine.
+pub struct ModelChecker<M: FiniteStateMachine> {
+    state_invariants: Vec<(String, StateInvariant<M>)>,
+    step_invariants: Vec<(String, StepInvariant<M>)>,
+    limit: usize,
+}
+
+impl<M> ModelChecker<M>
+where
+    M: FiniteStateMachine,
+    M::State: Clone + Eq + Hash,
+    M::Transition: Clone,
+{
+    /// A checker with no invariants. Even with no invariants, it will still catch panics.
+    pub fn new() -> Self {
+        Self {
+            state_invariants: Vec::new(),
+            step_invariants: Vec::new(),
+            limit: DEFAULT_STATE_LIMIT,
+        }
+    }
+
+    /// Require that the predicate holds in every reachable state.
+    pub fn state_invariant(
+        mut self,
+        name: &str,
+        predicate: impl Fn(&M::State) -> bool + 'static,
+    ) -> Self {
+        self.state_invariants
+            .push((name.into(), Box::new(predicate)));
+        self
+    }
+
+    /// Require that the predicate holds for every `(from, transition, to)` step that can be taken
+    /// from a reachable state.
+    pub fn step_invariant(
+        mut self,
+        name: &str,
+        predicate: impl Fn(&M::State, &M::Transition, &M::State) -> bool + 'static,
+    ) -> Self {
+        self.step_invariants
+            .push((name.into(), Box::new(predicate)));
+        self
+    }
+
+    /// Stop exploring after this many states.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Explore from the given start state, checking every invariant along the way.
+    pub fn check(&self, start: &M::State) -> Result<Report, Counterexample<M>> {
+        let counterexample = |violation, trace| Counterexample {
+            violation,
+            start: start.clone(),
+            trace,
+        };
+
+        if let Some((name, _)) = self.state_invariants.iter().find(|(_, p)| !p(start)) {
+            return Err(counterexample(
+                Violation::Invariant(name.clone()),
+                Vec::new(),
+            ));
+        }
+
+        let graph = StateGraph::<M>::search(start, self.limit, |graph, from, t, outcome| {
+            let trace = || {
+                let mut trace = graph.path_to(from);
+                trace.push(t.clone());
+                trace
+            };
+            let to = outcome
+                .map_err(|message| counterexample(Violation::Panic(message.into()), trace()))?;
+            let from = &graph.states[from];
+
+            for (name, predicate) in &self.step_invariants {
+                if !predicate(from, t, to) {
+                    return Err(counterexample(Violation::Invariant(name.clone()), trace()));
+                }
+            }
+            for (name, predicate) in &self.state_invariants {
+                if !predicate(to) {
+                    return Err(counterexample(Violation::Invariant(name.clone()), trace()));
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(Report {
+            states: graph.states.len(),
+            transitions: graph.edges.len(),
+            truncated: graph.truncated,
+        })
+    }
+}
+
+impl<M> Default for ModelChecker<M>
+where
+    M: FiniteStateMachine,
+    M::State: Clone + Eq + Hash,
+    M::Transition: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+use super::p1_switches::LightSwitch;
+#[cfg(test)]
+use super::p2_laundry_machine::{ClothesAction, ClothesMachine, ClothesState};
+
+#[test]
+fn model_check_light_switch_has_two_states() {
+    let graph = StateGraph::<LightSwitch>::explore(&false, DEFAULT_STATE_LIMIT);
+    assert_eq!(graph.states, vec![false, true]);
+    assert_eq!(graph.edges, vec![(0, (), 1), (1, (), 0)]);
+    assert!(!graph.truncated);
+}
+
+#[test]
+fn model_check_path_to_is_shortest() {
+    let graph = StateGraph::<ClothesMachine>::explore(&ClothesState::Clean(5), DEFAULT_STATE_LIMIT);
+    let wet = graph.index_of(&ClothesState::Wet(3)).unwrap();
+    assert_eq!(graph.path_to(wet), vec![ClothesAction::Wash]);
+}
+
+#[test]
+fn model_check_explore_respects_limit() {
+    let graph = StateGraph::<ClothesMachine>::explore(&ClothesState::Clean(50), 10);
+    assert_eq!(graph.states.len(), 10);
+    assert!(graph.truncated);
+}
+
+#[test]
+fn model_check_tattered_is_absorbing() {
+    let report = ModelChecker::<ClothesMachine>::new()
+        .step_invariant("tattered is absorbing", |from, _, to| {
+            *from != ClothesState::Tattered || *to == ClothesState::Tattered
+        })
+        .step_invariant("life never increases", |from, _, to| {
+            to.life().unwrap_or(0) <= from.life().unwrap_or(0)
+        })
+        .check(&ClothesState::Clean(10))
+        .unwrap();
+
+    // Not every life is reachable in every state. There is no way to reach `Wet(9)` for example.
+    assert_eq!(report.states, 51);
+    assert!(!report.truncated);
+}
+
+#[test]
+fn model_check_failing_state_invariant_gives_shortest_trace() {
+    let counterexample = ModelChecker::<ClothesMachine>::new()
+        .state_invariant("never tattered", |s| *s != ClothesState::Tattered)
+        .check(&ClothesState::Clean(3))
+        .unwrap_err();
+
+    assert_eq!(
+        counterexample.violation,
+        Violation::Invariant("never tattered".into())
+    );
+    // No single action tatters Clean(3), but many pairs do, two washes among them. Wear is tried
+    // first, so the trace found is wearing the clothes, then drying them while they are dirty.
+    assert_eq!(
+        counterexample.trace,
+        vec![ClothesAction::Wear, ClothesAction::Dry]
+    );
+}
+
+#[test]
+fn model_check_start_state_is_checked() {
+    let counterexample = ModelChecker::<LightSwitch>::new()
+        .state_invariant("always on", |s| *s)
+        .check(&false)
+        .unwrap_err();
+    assert!(counterexample.trace.is_empty());
+}
+
+#[test]
+fn model_check_panics_become_counterexamples() {
+    // A deliberately broken machine in the spirit of the original clothes underflow.
+    struct Countdown;
+    impl super::StateMachine for Countdown {
+        type State = u64;
+        type Transition = u64;
+        fn next_state(starting_state: &u64, t: &u64) -> u64 {
+            starting_state.checked_sub(*t).expect("counted below zero")
+        }
+    }
+    impl FiniteStateMachine for Countdown {
+        fn transitions(_: &u64) -> Vec<u64> {
+            vec![2]
+        }
+    }
+
+    let counterexample = ModelChecker::<Countdown>::new().check(&5).unwrap_err();
+    assert_eq!(
+        counterexample.violation,
+        Violation::Panic("counted below zero".into())
+    );
+    assert_eq!(counterexample.trace, vec![2, 2, 2]);
+}
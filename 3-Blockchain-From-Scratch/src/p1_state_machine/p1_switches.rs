@@ -1,8 +1,15 @@
 //! We begin our hands on exploration of state machines with two very simple examples.
 //! In these examples, we use actualy switch boards as the state machine. The state is,
 //! well, just the state of the switches.
+//!
+//! Both examples turn out to be special cases of a more general machine: a panel of any number
+//! of switches, wired together by rules that couple them.
 
-use super::StateMachine;
+use super::repl::ReplMachine;
+use super::trace::TraceCodec;
+use super::{FiniteStateMachine, ReversibleMachine, StateMachine};
+use std::marker::PhantomData;
+use std::sync::OnceLock;
 
 /// This state machine models a single light switch.
 /// The internal state, a bool, represents whether the switch is on or not.
@@ -14,8 +21,67 @@ impl StateMachine for LightSwitch {
     type State = bool;
     type Transition = ();
 
-    fn next_state(starting_state: &bool, t: &()) -> bool {
-        !*starting_state
+    fn next_state(starting_state: &bool, _: &()) -> bool {
+        Switchboard::<SingleSwitch>::next_state(&vec![*starting_state], &0)[0]
+    }
+
+    fn human_name() -> String {
+        "Light Switch".into()
+    }
+}
+
+impl FiniteStateMachine for LightSwitch {
+    fn transitions(_: &bool) -> Vec<()> {
+        vec![()]
+    }
+}
+
+/// Toggling is its own inverse, so there is nothing to remember.
+impl ReversibleMachine for LightSwitch {
+    type Undo = ();
+
+    fn apply(starting_state: &bool, t: &()) -> (bool, ()) {
+        (Self::next_state(starting_state, t), ())
+    }
+
+    fn revert(state: &bool, _: &()) -> bool {
+        !*state
+    }
+}
+
+impl ReplMachine for LightSwitch {
+    fn initial_state() -> bool {
+        false
+    }
+
+    fn parse_transition(input: &str) -> Result<(), String> {
+        match input {
+            "toggle" | "t" => Ok(()),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec!["toggle (t)   flip the switch"]
+    }
+}
+
+impl TraceCodec for LightSwitch {
+    fn encode_state(state: &bool) -> String {
+        state.to_string()
+    }
+
+    fn decode_state(text: &str) -> Result<bool, String> {
+        text.parse()
+            .map_err(|_| format!("not a switch position: {}", text))
+    }
+
+    fn encode_transition(_: &()) -> String {
+        "toggle".into()
+    }
+
+    fn decode_transition(text: &str) -> Result<(), String> {
+        Self::parse_transition(text)
     }
 }
 
@@ -24,13 +90,14 @@ impl StateMachine for LightSwitch {
 pub struct WeirdSwitchMachine;
 
 /// The state is now two switches instead of one so we use a struct.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TwoSwitches {
     first_switch: bool,
     second_switch: bool,
 }
 
 /// Now there are two switches so we need a proper type for the transition.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Toggle {
     FirstSwitch,
     SecondSwitch,
@@ -42,25 +109,415 @@ impl StateMachine for WeirdSwitchMachine {
     type Transition = Toggle;
 
     fn next_state(starting_state: &TwoSwitches, t: &Toggle) -> TwoSwitches {
+        let switches = vec![starting_state.first_switch, starting_state.second_switch];
+        let switch = match t {
+            Toggle::FirstSwitch => 0,
+            Toggle::SecondSwitch => 1,
+        };
+        let switches = Switchboard::<WeirdWiring>::next_state(&switches, &switch);
+        TwoSwitches {
+            first_switch: switches[0],
+            second_switch: switches[1],
+        }
+    }
+
+    fn human_name() -> String {
+        "Weird Switch Machine".into()
+    }
+}
+
+impl FiniteStateMachine for WeirdSwitchMachine {
+    fn transitions(_: &TwoSwitches) -> Vec<Toggle> {
+        vec![Toggle::FirstSwitch, Toggle::SecondSwitch]
+    }
+}
+
+/// Toggling the first switch might or might not take the second one with it, so the undo
+/// record lists every switch that changed.
+impl ReversibleMachine for WeirdSwitchMachine {
+    type Undo = Vec<Toggle>;
+
+    fn apply(starting_state: &TwoSwitches, t: &Toggle) -> (TwoSwitches, Vec<Toggle>) {
+        let next = Self::next_state(starting_state, t);
+        let mut changed = Vec::new();
+        if next.first_switch != starting_state.first_switch {
+            changed.push(Toggle::FirstSwitch);
+        }
+        if next.second_switch != starting_state.second_switch {
+            changed.push(Toggle::SecondSwitch);
+        }
+        (next, changed)
+    }
+
+    fn revert(state: &TwoSwitches, undo: &Vec<Toggle>) -> TwoSwitches {
+        let mut previous = state.clone();
+        for switch in undo {
+            match switch {
+                Toggle::FirstSwitch => previous.first_switch = !previous.first_switch,
+                Toggle::SecondSwitch => previous.second_switch = !previous.second_switch,
+            }
+        }
+        previous
+    }
+}
+
+impl ReplMachine for WeirdSwitchMachine {
+    fn initial_state() -> TwoSwitches {
+        TwoSwitches {
+            first_switch: false,
+            second_switch: false,
+        }
+    }
+
+    fn parse_transition(input: &str) -> Result<Toggle, String> {
+        match input {
+            "first" | "1" => Ok(Toggle::FirstSwitch),
+            "second" | "2" => Ok(Toggle::SecondSwitch),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "first (1)    toggle the first switch",
+            "second (2)   toggle the second switch",
+        ]
+    }
+}
+
+impl TraceCodec for WeirdSwitchMachine {
+    fn encode_state(state: &TwoSwitches) -> String {
+        format!("{},{}", state.first_switch, state.second_switch)
+    }
+
+    fn decode_state(text: &str) -> Result<TwoSwitches, String> {
+        let (first, second) = text
+            .split_once(',')
+            .ok_or_else(|| format!("expected two switch positions: {}", text))?;
+        Ok(TwoSwitches {
+            first_switch: LightSwitch::decode_state(first)?,
+            second_switch: LightSwitch::decode_state(second)?,
+        })
+    }
+
+    fn encode_transition(t: &Toggle) -> String {
         match t {
-            Toggle::FirstSwitch if starting_state.first_switch && starting_state.second_switch => {
-                TwoSwitches {
-                    first_switch: false,
-                    second_switch: false,
+            Toggle::FirstSwitch => "first".into(),
+            Toggle::SecondSwitch => "second".into(),
+        }
+    }
+
+    fn decode_transition(text: &str) -> Result<Toggle, String> {
+        Self::parse_transition(text)
+    }
+}
+
+/// A rule coupling one switch on a panel to another. Switches are identified by their index.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Coupling {
+    /// Whenever switch `when` goes off, switch `then` goes off too.
+    TurnsOff { when: usize, then: usize },
+    /// Switch `switch` can only be on while switch `on` is on. Turning `switch` on while `on` is
+    /// off does nothing, and turning `on` off turns `switch` off with it.
+    Requires { switch: usize, on: usize },
+    /// Whenever switch `switch` changes, switch `also` is toggled too.
+    Toggles { switch: usize, also: usize },
+}
+
+impl Coupling {
+    /// The switch whose change triggers this rule, and the switch the rule then changes.
+    fn edge(&self) -> (usize, usize) {
+        match *self {
+            Coupling::TurnsOff { when, then } => (when, then),
+            Coupling::Requires { switch, on } => (on, switch),
+            Coupling::Toggles { switch, also } => (switch, also),
+        }
+    }
+}
+
+/// Why a set of couplings does not describe a working panel
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PanelError {
+    /// A rule refers to a switch the panel doesn't have.
+    NoSuchSwitch(usize),
+    /// The rules form a cycle, so a single toggle could ripple around the panel forever. The
+    /// switches in the cycle are listed in order, starting and ending with the same switch.
+    Cycle(Vec<usize>),
+}
+
+/// A panel of switches and the rules that couple them. A panel can only be constructed from
+/// rules that refer to its own switches and that contain no cycles, so toggling a switch
+/// always settles.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Panel {
+    switches: usize,
+    rules: Vec<Coupling>,
+}
+
+impl Panel {
+    /// Build a panel with the given number of switches, checking that the rules make sense.
+    pub fn new(switches: usize, rules: Vec<Coupling>) -> Result<Self, PanelError> {
+        for rule in &rules {
+            let (from, to) = rule.edge();
+            if let Some(switch) = [from, to].into_iter().find(|s| *s >= switches) {
+                return Err(PanelError::NoSuchSwitch(switch));
+            }
+        }
+
+        let panel = Self { switches, rules };
+        // Depth first search for a back edge, remembering the path so we can report the cycle.
+        let mut done = vec![false; switches];
+        for root in 0..switches {
+            let mut path = Vec::new();
+            if let Some(cycle) = panel.find_cycle(root, &mut path, &mut done) {
+                return Err(PanelError::Cycle(cycle));
+            }
+        }
+        Ok(panel)
+    }
+
+    fn find_cycle(
+        &self,
+        switch: usize,
+        path: &mut Vec<usize>,
+        done: &mut [bool],
+    ) -> Option<Vec<usize>> {
+        if let Some(position) = path.iter().position(|s| *s == switch) {
+            let mut cycle = path[position..].to_vec();
+            cycle.push(switch);
+            return Some(cycle);
+        }
+        if done[switch] {
+            return None;
+        }
+        path.push(switch);
+        for (from, to) in self.rules.iter().map(Coupling::edge) {
+            if from == switch {
+                if let Some(cycle) = self.find_cycle(to, path, done) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        done[switch] = true;
+        None
+    }
+
+    /// How many switches are on the panel
+    pub fn switches(&self) -> usize {
+        self.switches
+    }
+
+    /// The rules coupling the switches
+    pub fn rules(&self) -> &[Coupling] {
+        &self.rules
+    }
+
+    /// Toggle one switch and let the change ripple through the rules. If the result would leave
+    /// a switch on without the switch it requires, the toggle has no effect at all.
+    pub fn toggle(&self, switches: &[bool], switch: usize) -> Vec<bool> {
+        if switch >= self.switches || switches.len() != self.switches {
+            return switches.to_vec();
+        }
+        let mut next = switches.to_vec();
+        let on = !next[switch];
+        self.set(&mut next, switch, on);
+
+        let allowed = self.rules.iter().all(|rule| match *rule {
+            Coupling::Requires { switch, on } => !next[switch] || next[on],
+            _ => true,
+        });
+        if allowed {
+            next
+        } else {
+            switches.to_vec()
+        }
+    }
+
+    /// Set a switch and apply every rule triggered by the change. This terminates because the
+    /// rules are acyclic.
+    fn set(&self, switches: &mut [bool], switch: usize, on: bool) {
+        if switches[switch] == on {
+            return;
+        }
+        switches[switch] = on;
+        for rule in &self.rules {
+            match *rule {
+                Coupling::TurnsOff { when, then } if when == switch && !on => {
+                    self.set(switches, then, false)
                 }
+                Coupling::Requires {
+                    switch: dependent,
+                    on: required,
+                } if required == switch && !on => self.set(switches, dependent, false),
+                Coupling::Toggles { switch: s, also } if s == switch => {
+                    self.set(switches, also, !switches[also])
+                }
+                _ => {}
             }
-            Toggle::FirstSwitch => TwoSwitches {
-                first_switch: !starting_state.first_switch,
-                ..*starting_state
-            },
-            Toggle::SecondSwitch => TwoSwitches {
-                second_switch: !starting_state.second_switch,
-                ..*starting_state
-            },
         }
     }
 }
 
+/// Describes how the switches on a `Switchboard` are wired together.
+///
+/// Like state machines themselves, wirings are described entirely by their types.
+pub trait Wiring {
+    /// A human-readable name for the panel
+    fn name() -> String;
+
+    /// The panel this wiring describes. The switchboard consults it on every transition, so it
+    /// should be built and validated only once, and kept in a `OnceLock`.
+    fn panel() -> &'static Panel;
+}
+
+/// A panel of switches wired together according to `W`. The state holds the position of every
+/// switch, and each transition toggles the switch with the given index.
+pub struct Switchboard<W>(PhantomData<W>);
+
+impl<W: Wiring> StateMachine for Switchboard<W> {
+    type State = Vec<bool>;
+    type Transition = usize;
+
+    fn next_state(starting_state: &Vec<bool>, t: &usize) -> Vec<bool> {
+        W::panel().toggle(starting_state, *t)
+    }
+
+    fn human_name() -> String {
+        W::name()
+    }
+}
+
+impl<W: Wiring> FiniteStateMachine for Switchboard<W> {
+    fn transitions(_: &Vec<bool>) -> Vec<usize> {
+        (0..W::panel().switches()).collect()
+    }
+}
+
+/// The undo record lists the index of every switch that changed, which is usually far fewer
+/// than the number of switches on the panel.
+impl<W: Wiring> ReversibleMachine for Switchboard<W> {
+    type Undo = Vec<usize>;
+
+    fn apply(starting_state: &Vec<bool>, t: &usize) -> (Vec<bool>, Vec<usize>) {
+        let next = Self::next_state(starting_state, t);
+        let changed = (0..next.len())
+            .filter(|i| starting_state.get(*i) != next.get(*i))
+            .collect();
+        (next, changed)
+    }
+
+    fn revert(state: &Vec<bool>, undo: &Vec<usize>) -> Vec<bool> {
+        let mut previous = state.clone();
+        for switch in undo {
+            previous[*switch] = !previous[*switch];
+        }
+        previous
+    }
+}
+
+impl<W: Wiring> ReplMachine for Switchboard<W> {
+    fn initial_state() -> Vec<bool> {
+        vec![false; W::panel().switches()]
+    }
+
+    fn parse_transition(input: &str) -> Result<usize, String> {
+        match input.parse::<usize>() {
+            Ok(switch) if (1..=W::panel().switches()).contains(&switch) => Ok(switch - 1),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec!["<n>          toggle switch n, counting from 1"]
+    }
+}
+
+impl<W: Wiring> TraceCodec for Switchboard<W> {
+    fn encode_state(state: &Vec<bool>) -> String {
+        state.iter().map(|on| if *on { '1' } else { '0' }).collect()
+    }
+
+    fn decode_state(text: &str) -> Result<Vec<bool>, String> {
+        text.chars()
+            .map(|c| match c {
+                '1' => Ok(true),
+                '0' => Ok(false),
+                _ => Err(format!("not a switch position: {}", c)),
+            })
+            .collect()
+    }
+
+    fn encode_transition(t: &usize) -> String {
+        (t + 1).to_string()
+    }
+
+    fn decode_transition(text: &str) -> Result<usize, String> {
+        Self::parse_transition(text)
+    }
+}
+
+/// A single switch with no rules at all. This is the `LightSwitch`.
+pub struct SingleSwitch;
+
+impl Wiring for SingleSwitch {
+    fn name() -> String {
+        "Light Switch".into()
+    }
+
+    fn panel() -> &'static Panel {
+        static PANEL: OnceLock<Panel> = OnceLock::new();
+        PANEL.get_or_init(|| Panel::new(1, vec![]).expect("a single switch has no rules to break"))
+    }
+}
+
+/// Two switches where turning the first off turns the second off. This is the
+/// `WeirdSwitchMachine`.
+pub struct WeirdWiring;
+
+impl Wiring for WeirdWiring {
+    fn name() -> String {
+        "Weird Switch Machine".into()
+    }
+
+    fn panel() -> &'static Panel {
+        static PANEL: OnceLock<Panel> = OnceLock::new();
+        PANEL.get_or_init(|| {
+            Panel::new(2, vec![Coupling::TurnsOff { when: 0, then: 1 }])
+                .expect("a single rule cannot form a cycle")
+        })
+    }
+}
+
+/// A panel showing off every kind of rule.
+/// * Switch 1 is the main power. Turning it off turns everything else off too.
+/// * Switch 2 can only be on while the main power is on.
+/// * Switch 3 is a two-way switch wired to switch 4, so toggling 3 also toggles 4.
+/// * Switch 4 can only be on while the main power is on.
+pub struct DemoPanel;
+
+impl Wiring for DemoPanel {
+    fn name() -> String {
+        "Switch Panel".into()
+    }
+
+    fn panel() -> &'static Panel {
+        static PANEL: OnceLock<Panel> = OnceLock::new();
+        PANEL.get_or_init(|| {
+            Panel::new(
+                4,
+                vec![
+                    Coupling::Requires { switch: 1, on: 0 },
+                    Coupling::TurnsOff { when: 0, then: 2 },
+                    Coupling::Toggles { switch: 2, also: 3 },
+                    Coupling::Requires { switch: 3, on: 0 },
+                ],
+            )
+            .expect("the demo panel is acyclic")
+        })
+    }
+}
+
 #[test]
 fn sm_1_light_switch_toggles_off() {
     assert!(!LightSwitch::next_state(&true, &()));
@@ -152,3 +609,246 @@ fn sm_1_two_switches_second_goes_off() {
         }
     );
 }
+
+#[test]
+fn sm_1_two_switches_first_off_means_second_off() {
+    use super::model_check::ModelChecker;
+
+    let report = ModelChecker::<WeirdSwitchMachine>::new()
+        .step_invariant(
+            "second switch is off after the first goes off",
+            |_, t, to| !matches!(t, Toggle::FirstSwitch) || to.first_switch || !to.second_switch,
+        )
+        .check(&WeirdSwitchMachine::initial_state())
+        .unwrap();
+
+    assert_eq!(report.states, 4);
+}
+
+#[test]
+fn sm_1_two_switches_trace_round_trip() {
+    let state = TwoSwitches {
+        first_switch: true,
+        second_switch: false,
+    };
+    let text = WeirdSwitchMachine::encode_state(&state);
+
+    assert_eq!(text, "true,false");
+    assert_eq!(WeirdSwitchMachine::decode_state(&text), Ok(state));
+}
+
+#[test]
+fn sm_1_panel_rejects_unknown_switches() {
+    assert_eq!(
+        Panel::new(2, vec![Coupling::Toggles { switch: 0, also: 2 }]),
+        Err(PanelError::NoSuchSwitch(2))
+    );
+}
+
+#[test]
+fn sm_1_panel_rejects_cycles() {
+    assert_eq!(
+        Panel::new(
+            3,
+            vec![
+                Coupling::Toggles { switch: 0, also: 1 },
+                Coupling::TurnsOff { when: 1, then: 2 },
+                Coupling::Requires { switch: 1, on: 2 },
+            ],
+        ),
+        Err(PanelError::Cycle(vec![1, 2, 1]))
+    );
+    assert_eq!(
+        Panel::new(1, vec![Coupling::TurnsOff { when: 0, then: 0 }]),
+        Err(PanelError::Cycle(vec![0, 0]))
+    );
+}
+
+#[test]
+fn sm_1_panel_allows_diamonds() {
+    // Two paths to the same switch are fine, as long as neither leads back again.
+    let panel = Panel::new(
+        4,
+        vec![
+            Coupling::Toggles { switch: 0, also: 1 },
+            Coupling::Toggles { switch: 0, also: 2 },
+            Coupling::Toggles { switch: 1, also: 3 },
+            Coupling::Toggles { switch: 2, also: 3 },
+        ],
+    )
+    .unwrap();
+
+    // Switch 4 is toggled twice, so it ends up where it started.
+    assert_eq!(panel.toggle(&[false; 4], 0), vec![true, true, true, false]);
+}
+
+#[test]
+fn sm_1_panel_requires_blocks_toggle() {
+    let panel = DemoPanel::panel();
+    assert_eq!(
+        panel.toggle(&[false, false, false, false], 1),
+        vec![false, false, false, false]
+    );
+    assert_eq!(
+        panel.toggle(&[true, false, false, false], 1),
+        vec![true, true, false, false]
+    );
+}
+
+#[test]
+fn sm_1_panel_requires_blocks_coupled_toggle() {
+    // Switch 3 drags switch 4 along with it, but 4 needs the main power.
+    let panel = DemoPanel::panel();
+    assert_eq!(
+        panel.toggle(&[false, false, false, false], 2),
+        vec![false, false, false, false]
+    );
+    assert_eq!(
+        panel.toggle(&[true, false, false, false], 2),
+        vec![true, false, true, true]
+    );
+}
+
+#[test]
+fn sm_1_panel_main_power_turns_everything_off() {
+    let panel = DemoPanel::panel();
+    assert_eq!(
+        panel.toggle(&[true, true, true, true], 0),
+        vec![false, false, false, false]
+    );
+}
+
+#[test]
+fn sm_1_panel_is_built_once() {
+    assert!(std::ptr::eq(DemoPanel::panel(), DemoPanel::panel()));
+}
+
+#[test]
+fn sm_1_panel_ignores_unknown_switch() {
+    assert_eq!(
+        Switchboard::<DemoPanel>::next_state(&vec![true, false, false, false], &7),
+        vec![true, false, false, false]
+    );
+}
+
+#[test]
+fn sm_1_demo_panel_invariants() {
+    use super::model_check::ModelChecker;
+
+    let report = ModelChecker::<Switchboard<DemoPanel>>::new()
+        .state_invariant("nothing is on without main power", |s| {
+            s[0] || s.iter().all(|on| !on)
+        })
+        .check(&Switchboard::<DemoPanel>::initial_state())
+        .unwrap();
+
+    // With the power off there is only one state. With it on, the other three switches can be in
+    // any position, because switch 4 can still be toggled by itself.
+    assert_eq!(report.states, 9);
+}
+
+#[test]
+fn sm_1_switchboards_match_special_cases() {
+    for on in [false, true] {
+        assert_eq!(
+            LightSwitch::next_state(&on, &()),
+            Switchboard::<SingleSwitch>::next_state(&vec![on], &0)[0]
+        );
+    }
+    assert_eq!(
+        Switchboard::<WeirdWiring>::next_state(&vec![true, true], &0),
+        vec![false, false]
+    );
+    assert_eq!(
+        Switchboard::<WeirdWiring>::human_name(),
+        WeirdSwitchMachine::human_name()
+    );
+}
+
+#[test]
+fn sm_1_switchboard_trace_round_trip() {
+    use super::trace::Trace;
+
+    let trace = Trace::<Switchboard<DemoPanel>>::run(
+        Switchboard::<DemoPanel>::initial_state(),
+        &[0, 2, 1, 0],
+    );
+    let text = trace.to_text();
+
+    assert_eq!(
+        text,
+        "# Switch Panel\n0000\n1\t1000\n3\t1011\n2\t1111\n1\t0000\n"
+    );
+    assert_eq!(Trace::<Switchboard<DemoPanel>>::from_text(&text), Ok(trace));
+}
+
+#[test]
+fn sm_1_student_weird_switch_equivalence() {
+    use super::equivalence::EquivalenceChecker;
+
+    // A student who forgot the weird wiring, and one who remembered it.
+    struct Forgetful;
+    impl StateMachine for Forgetful {
+        type State = TwoSwitches;
+        type Transition = Toggle;
+        fn next_state(s: &TwoSwitches, t: &Toggle) -> TwoSwitches {
+            match t {
+                Toggle::FirstSwitch => TwoSwitches {
+                    first_switch: !s.first_switch,
+                    ..s.clone()
+                },
+                Toggle::SecondSwitch => TwoSwitches {
+                    second_switch: !s.second_switch,
+                    ..s.clone()
+                },
+            }
+        }
+    }
+    impl FiniteStateMachine for Forgetful {
+        fn transitions(_: &TwoSwitches) -> Vec<Toggle> {
+            vec![Toggle::FirstSwitch, Toggle::SecondSwitch]
+        }
+    }
+    struct Careful;
+    impl StateMachine for Careful {
+        type State = (bool, bool);
+        type Transition = Toggle;
+        fn next_state(&(first, second): &(bool, bool), t: &Toggle) -> (bool, bool) {
+            match t {
+                Toggle::FirstSwitch => (!first, second && !first),
+                Toggle::SecondSwitch => (first, !second),
+            }
+        }
+    }
+    impl FiniteStateMachine for Careful {
+        fn transitions(_: &(bool, bool)) -> Vec<Toggle> {
+            vec![Toggle::FirstSwitch, Toggle::SecondSwitch]
+        }
+    }
+
+    let start = WeirdSwitchMachine::initial_state();
+    let distinction = EquivalenceChecker::<WeirdSwitchMachine, Forgetful, _>::new(
+        |s: &TwoSwitches| s.clone(),
+        |s: &TwoSwitches| s.clone(),
+    )
+    .check(&start, &start)
+    .unwrap_err();
+    assert_eq!(
+        distinction.trace,
+        vec![
+            Toggle::FirstSwitch,
+            Toggle::SecondSwitch,
+            Toggle::FirstSwitch
+        ]
+    );
+    assert!(!distinction.a.second_switch);
+    assert!(distinction.b.second_switch);
+
+    let report = EquivalenceChecker::<WeirdSwitchMachine, Careful, _>::new(
+        |s: &TwoSwitches| (s.first_switch, s.second_switch),
+        |s: &(bool, bool)| *s,
+    )
+    .check(&start, &(false, false))
+    .unwrap();
+    assert_eq!(report.states, 4);
+}
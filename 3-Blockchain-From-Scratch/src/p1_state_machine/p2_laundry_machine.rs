@@ -2,17 +2,41 @@
 //! ready to be worn again. Of course washing and wearing clothes takes its toll on the clothes, and
 //! eventually they get tattered.
 //!
-use super::StateMachine;
+//! Clean clothes can also be ironed and folded. Pressed or folded clothes are still clean, they
+//! are just ready to be worn smartly, or to be put away.
+//!
+//! Not all fabrics are equally tough. How much damage each action does is described by a
+//! `DamageProfile`, so the same rules can model delicates or denim.
+use super::repl::ReplMachine;
+use super::trace::TraceCodec;
+use super::{FiniteStateMachine, MealyMachine, ReversibleMachine, StateMachine, TryStateMachine};
+use std::marker::PhantomData;
 
 /// The rules are:
-/// TODO
-pub struct ClothesMachine;
+/// * Wearing clothes makes them dirty.
+/// * Washing clothes makes them wet.
+/// * Drying wet clothes makes them clean. Drying dirty clothes leaves them dirty, and pressed or
+///   folded clothes come out of the dryer merely clean.
+/// * Ironing clean clothes presses them. Ironing wet clothes dries them. Ironing dirty clothes
+///   just bakes the dirt in.
+/// * Folding clean clothes puts them away. Folding dirty or wet clothes leaves them dirty or wet.
+/// * Whether or not an action changes what state the clothes are in, it may still wear them out,
+///   as described by the damage profile `P`. Even folding dirty or wet clothes does damage. Clothes
+///   that cannot survive an action become tattered, and tattered clothes stay tattered forever.
+pub struct Garment<P>(PhantomData<P>);
+
+/// Everyday clothes
+pub type ClothesMachine = Garment<Standard>;
 
 /// Models a piece of clothing throughout its lifecycle.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ClothesState {
     /// Clean clothes ready to be worn. With some given life left.
     Clean(u64),
+    /// Clean clothes that have been ironed. With some given life left.
+    Pressed(u64),
+    /// Clean clothes that have been folded and put away. With some given life left.
+    Folded(u64),
     /// Dirty clothes. With some given life left.
     Dirty(u64),
     /// Wet clothes. With some given life left.
@@ -21,39 +45,363 @@ pub enum ClothesState {
     Tattered,
 }
 
+impl ClothesState {
+    /// How much life the clothes have left, or `None` if they are already tattered.
+    pub fn life(&self) -> Option<u64> {
+        match self {
+            ClothesState::Clean(life)
+            | ClothesState::Pressed(life)
+            | ClothesState::Folded(life)
+            | ClothesState::Dirty(life)
+            | ClothesState::Wet(life) => Some(*life),
+            ClothesState::Tattered => None,
+        }
+    }
+}
+
 /// Something you can do with clothes
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ClothesAction {
     Wear,
     Wash,
     Dry,
+    Iron,
+    Fold,
 }
 
-impl StateMachine for ClothesMachine {
-    type State = ClothesState;
-    type Transition = ClothesAction;
+/// How much life a fabric loses to each action. The state passed in is never `Tattered`.
+pub trait DamageProfile {
+    /// A human-readable name for clothes made of this fabric
+    fn name() -> String;
 
-    fn next_state(starting_state: &ClothesState, t: &ClothesAction) -> ClothesState {
-        match starting_state {
-            ClothesState::Clean(life) => match t {
-                ClothesAction::Wear => ClothesState::Dirty(life - 1),
-                ClothesAction::Wash => ClothesState::Wet(life - 2),
-                ClothesAction::Dry => ClothesState::Clean(life - 2),
+    /// The damage done by performing the action on clothes in the given state.
+    fn damage(state: &ClothesState, action: &ClothesAction) -> u64;
+}
+
+/// Ordinary clothes
+pub struct Standard;
+
+impl DamageProfile for Standard {
+    fn name() -> String {
+        "Clothes".into()
+    }
+
+    fn damage(state: &ClothesState, action: &ClothesAction) -> u64 {
+        match state {
+            ClothesState::Dirty(_) => match action {
+                ClothesAction::Wear => 2,
+                ClothesAction::Wash => 1,
+                ClothesAction::Dry => 3,
+                ClothesAction::Iron => 3,
+                ClothesAction::Fold => 1,
             },
-            ClothesState::Dirty(life) => match t {
-                ClothesAction::Wear => ClothesState::Dirty(life - 2),
-                ClothesAction::Wash => ClothesState::Wet(life - 1),
-                ClothesAction::Dry => ClothesState::Dirty(life - 3),
+            ClothesState::Wet(_) => match action {
+                ClothesAction::Wear => 2,
+                ClothesAction::Wash => 2,
+                ClothesAction::Dry => 1,
+                ClothesAction::Iron => 2,
+                ClothesAction::Fold => 2,
             },
-            ClothesState::Wet(life) => match t {
-                ClothesAction::Wear => ClothesState::Dirty(life - 2),
-                ClothesAction::Wash => ClothesState::Wet(life - 2),
-                ClothesAction::Dry => ClothesState::Clean(life - 1),
+            _ => match action {
+                ClothesAction::Wear => 1,
+                ClothesAction::Wash => 2,
+                ClothesAction::Dry => 2,
+                ClothesAction::Iron => 1,
+                ClothesAction::Fold => 0,
             },
-            ClothesState::Tattered => ClothesState::Tattered,
         }
     }
 }
 
+/// Delicate fabrics like silk. The dryer and the iron are especially hard on them.
+pub struct Delicates;
+
+impl DamageProfile for Delicates {
+    fn name() -> String {
+        "Delicate Clothes".into()
+    }
+
+    fn damage(state: &ClothesState, action: &ClothesAction) -> u64 {
+        match state {
+            ClothesState::Dirty(_) => match action {
+                ClothesAction::Wear => 2,
+                ClothesAction::Wash => 2,
+                ClothesAction::Dry => 5,
+                ClothesAction::Iron => 5,
+                ClothesAction::Fold => 1,
+            },
+            ClothesState::Wet(_) => match action {
+                ClothesAction::Wear => 3,
+                ClothesAction::Wash => 3,
+                ClothesAction::Dry => 3,
+                ClothesAction::Iron => 3,
+                ClothesAction::Fold => 2,
+            },
+            _ => match action {
+                ClothesAction::Wear => 1,
+                ClothesAction::Wash => 3,
+                ClothesAction::Dry => 4,
+                ClothesAction::Iron => 3,
+                ClothesAction::Fold => 0,
+            },
+        }
+    }
+}
+
+/// Hard wearing denim. Hardly anything hurts it.
+pub struct Denim;
+
+impl DamageProfile for Denim {
+    fn name() -> String {
+        "Denim Clothes".into()
+    }
+
+    fn damage(state: &ClothesState, action: &ClothesAction) -> u64 {
+        match state {
+            ClothesState::Dirty(_) => match action {
+                ClothesAction::Dry | ClothesAction::Iron => 2,
+                ClothesAction::Fold => 0,
+                _ => 1,
+            },
+            ClothesState::Wet(_) => 1,
+            _ => match action {
+                ClothesAction::Fold => 0,
+                _ => 1,
+            },
+        }
+    }
+}
+
+/// Which state the clothes end up in, before accounting for any damage.
+fn destination(state: &ClothesState, action: &ClothesAction) -> fn(u64) -> ClothesState {
+    match (state, action) {
+        (_, ClothesAction::Wear) => ClothesState::Dirty,
+        (_, ClothesAction::Wash) => ClothesState::Wet,
+        (
+            ClothesState::Dirty(_),
+            ClothesAction::Dry | ClothesAction::Iron | ClothesAction::Fold,
+        ) => ClothesState::Dirty,
+        (ClothesState::Wet(_), ClothesAction::Dry | ClothesAction::Iron) => ClothesState::Clean,
+        (ClothesState::Wet(_), ClothesAction::Fold) => ClothesState::Wet,
+        (_, ClothesAction::Dry) => ClothesState::Clean,
+        (_, ClothesAction::Iron) => ClothesState::Pressed,
+        (_, ClothesAction::Fold) => ClothesState::Folded,
+    }
+}
+
+/// Why an action could not be performed on a piece of clothing
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ClothesError {
+    /// The clothes are already tattered, so there is nothing left to do with them.
+    AlreadyTattered,
+    /// The action would do more damage than the clothes have life left. They fall apart.
+    WornOut {
+        /// The life the clothes had left before the action
+        life: u64,
+        /// The damage the action would have done
+        damage: u64,
+    },
+}
+
+impl<P: DamageProfile> TryStateMachine for Garment<P> {
+    type State = ClothesState;
+    type Transition = ClothesAction;
+    type Error = ClothesError;
+
+    fn try_next_state(
+        starting_state: &ClothesState,
+        t: &ClothesAction,
+    ) -> Result<ClothesState, ClothesError> {
+        let life = starting_state.life().ok_or(ClothesError::AlreadyTattered)?;
+        let damage = P::damage(starting_state, t);
+
+        // Make sure the clothes can survive the action.
+        life.checked_sub(damage)
+            .map(destination(starting_state, t))
+            .ok_or(ClothesError::WornOut { life, damage })
+    }
+
+    fn human_name() -> String {
+        P::name()
+    }
+}
+
+/// Something that happened to a piece of clothing
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ClothesEvent {
+    /// The clothes lost this much life
+    Damaged(u64),
+    /// The clothes could not survive the action and are now tattered
+    FellApart,
+}
+
+/// Clothes that cannot survive an action simply end up tattered.
+impl<P: DamageProfile> StateMachine for Garment<P> {
+    type State = ClothesState;
+    type Transition = ClothesAction;
+
+    fn next_state(starting_state: &ClothesState, t: &ClothesAction) -> ClothesState {
+        Self::next_state_with_events(starting_state, t).0
+    }
+
+    fn human_name() -> String {
+        P::name()
+    }
+}
+
+impl<P: DamageProfile> MealyMachine for Garment<P> {
+    type Event = ClothesEvent;
+
+    fn next_state_with_events(
+        starting_state: &ClothesState,
+        t: &ClothesAction,
+    ) -> (ClothesState, Vec<ClothesEvent>) {
+        match Self::try_next_state(starting_state, t) {
+            Ok(next) => {
+                let damage = starting_state.life().unwrap_or(0) - next.life().unwrap_or(0);
+                (next, vec![ClothesEvent::Damaged(damage)])
+            }
+            Err(ClothesError::WornOut { .. }) => {
+                (ClothesState::Tattered, vec![ClothesEvent::FellApart])
+            }
+            Err(ClothesError::AlreadyTattered) => (ClothesState::Tattered, Vec::new()),
+        }
+    }
+}
+
+/// Which state clothes were in, without their life
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ClothesKind {
+    Clean,
+    Pressed,
+    Folded,
+    Dirty,
+    Wet,
+    Tattered,
+}
+
+/// Just enough to undo one action on a piece of clothing
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ClothesUndo {
+    /// Which state the clothes were in before
+    pub was: ClothesKind,
+    /// How much life the action took. Tattered clothes count as having none left.
+    pub damage: u64,
+}
+
+/// Clothes forget where they came from. Clean clothes might have been wet or pressed, and
+/// tattered clothes have lost track of their life altogether. So the undo record remembers which
+/// state the clothes were in, and how much life they lost on the way out of it.
+impl<P: DamageProfile> ReversibleMachine for Garment<P> {
+    type Undo = ClothesUndo;
+
+    fn apply(starting_state: &ClothesState, t: &ClothesAction) -> (ClothesState, ClothesUndo) {
+        let next = Self::next_state(starting_state, t);
+        let was = match starting_state {
+            ClothesState::Clean(_) => ClothesKind::Clean,
+            ClothesState::Pressed(_) => ClothesKind::Pressed,
+            ClothesState::Folded(_) => ClothesKind::Folded,
+            ClothesState::Dirty(_) => ClothesKind::Dirty,
+            ClothesState::Wet(_) => ClothesKind::Wet,
+            ClothesState::Tattered => ClothesKind::Tattered,
+        };
+        let damage = starting_state.life().unwrap_or(0) - next.life().unwrap_or(0);
+        (next, ClothesUndo { was, damage })
+    }
+
+    fn revert(state: &ClothesState, undo: &ClothesUndo) -> ClothesState {
+        let life = state.life().unwrap_or(0) + undo.damage;
+        match undo.was {
+            ClothesKind::Clean => ClothesState::Clean(life),
+            ClothesKind::Pressed => ClothesState::Pressed(life),
+            ClothesKind::Folded => ClothesState::Folded(life),
+            ClothesKind::Dirty => ClothesState::Dirty(life),
+            ClothesKind::Wet => ClothesState::Wet(life),
+            ClothesKind::Tattered => ClothesState::Tattered,
+        }
+    }
+}
+
+impl<P: DamageProfile> FiniteStateMachine for Garment<P> {
+    fn transitions(_: &ClothesState) -> Vec<ClothesAction> {
+        vec![
+            ClothesAction::Wear,
+            ClothesAction::Wash,
+            ClothesAction::Dry,
+            ClothesAction::Iron,
+            ClothesAction::Fold,
+        ]
+    }
+}
+
+impl<P: DamageProfile> ReplMachine for Garment<P> {
+    fn initial_state() -> ClothesState {
+        ClothesState::Clean(10)
+    }
+
+    fn parse_transition(input: &str) -> Result<ClothesAction, String> {
+        match input {
+            "wear" => Ok(ClothesAction::Wear),
+            "wash" => Ok(ClothesAction::Wash),
+            "dry" => Ok(ClothesAction::Dry),
+            "iron" => Ok(ClothesAction::Iron),
+            "fold" => Ok(ClothesAction::Fold),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "wear         put the clothes on",
+            "wash         run them through the washer",
+            "dry          run them through the dryer",
+            "iron         press them with a hot iron",
+            "fold         fold them and put them away",
+        ]
+    }
+}
+
+impl<P: DamageProfile> TraceCodec for Garment<P> {
+    fn encode_state(state: &ClothesState) -> String {
+        format!("{:?}", state)
+    }
+
+    fn decode_state(text: &str) -> Result<ClothesState, String> {
+        if text == "Tattered" {
+            return Ok(ClothesState::Tattered);
+        }
+        let (name, life) = text
+            .strip_suffix(')')
+            .and_then(|t| t.split_once('('))
+            .ok_or_else(|| format!("not a clothes state: {}", text))?;
+        let life = life
+            .parse()
+            .map_err(|_| format!("not an amount of life: {}", life))?;
+        match name {
+            "Clean" => Ok(ClothesState::Clean(life)),
+            "Pressed" => Ok(ClothesState::Pressed(life)),
+            "Folded" => Ok(ClothesState::Folded(life)),
+            "Dirty" => Ok(ClothesState::Dirty(life)),
+            "Wet" => Ok(ClothesState::Wet(life)),
+            _ => Err(format!("not a clothes state: {}", text)),
+        }
+    }
+
+    fn encode_transition(t: &ClothesAction) -> String {
+        match t {
+            ClothesAction::Wear => "wear".into(),
+            ClothesAction::Wash => "wash".into(),
+            ClothesAction::Dry => "dry".into(),
+            ClothesAction::Iron => "iron".into(),
+            ClothesAction::Fold => "fold".into(),
+        }
+    }
+
+    fn decode_transition(text: &str) -> Result<ClothesAction, String> {
+        Self::parse_transition(text)
+    }
+}
+
 #[test]
 fn sm_2_wear_clean_clothes() {
     let starting_state = ClothesState::Clean(3);
@@ -161,3 +509,235 @@ fn sm_2_dry_tattered_clothes() {
         ClothesState::Tattered
     )
 }
+
+#[test]
+fn sm_2_wear_clothes_to_tatters() {
+    // Not enough life left to survive the wash. Previously this underflowed.
+    let starting_state = ClothesState::Clean(1);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Wash),
+        ClothesState::Tattered
+    )
+}
+
+#[test]
+fn sm_2_try_wash_worn_out_clothes() {
+    let starting_state = ClothesState::Clean(1);
+    assert_eq!(
+        ClothesMachine::try_next_state(&starting_state, &ClothesAction::Wash),
+        Err(ClothesError::WornOut { life: 1, damage: 2 })
+    )
+}
+
+#[test]
+fn sm_2_try_wear_tattered_clothes() {
+    let starting_state = ClothesState::Tattered;
+    assert_eq!(
+        ClothesMachine::try_next_state(&starting_state, &ClothesAction::Wear),
+        Err(ClothesError::AlreadyTattered)
+    )
+}
+
+#[test]
+fn sm_2_try_dry_wet_clothes() {
+    let starting_state = ClothesState::Wet(1);
+    assert_eq!(
+        ClothesMachine::try_next_state(&starting_state, &ClothesAction::Dry),
+        Ok(ClothesState::Clean(0))
+    )
+}
+
+#[test]
+fn sm_2_wash_dirty_clothes_emits_damage() {
+    let starting_state = ClothesState::Dirty(5);
+    assert_eq!(
+        ClothesMachine::next_state_with_events(&starting_state, &ClothesAction::Wash),
+        (ClothesState::Wet(4), vec![ClothesEvent::Damaged(1)])
+    )
+}
+
+#[test]
+fn sm_2_wear_out_clothes_emits_fell_apart() {
+    let starting_state = ClothesState::Dirty(2);
+    assert_eq!(
+        ClothesMachine::next_state_with_events(&starting_state, &ClothesAction::Dry),
+        (ClothesState::Tattered, vec![ClothesEvent::FellApart])
+    )
+}
+
+#[test]
+fn sm_2_tattered_clothes_emit_nothing() {
+    let starting_state = ClothesState::Tattered;
+    assert_eq!(
+        ClothesMachine::next_state_with_events(&starting_state, &ClothesAction::Wear),
+        (ClothesState::Tattered, Vec::new())
+    )
+}
+
+#[test]
+fn sm_2_decode_clothes_states() {
+    assert_eq!(
+        ClothesMachine::decode_state("Wet(7)"),
+        Ok(ClothesState::Wet(7))
+    );
+    assert_eq!(
+        ClothesMachine::decode_state("Tattered"),
+        Ok(ClothesState::Tattered)
+    );
+    assert!(ClothesMachine::decode_state("Soggy(7)").is_err());
+    assert!(ClothesMachine::decode_state("Clean(-1)").is_err());
+}
+
+#[test]
+fn sm_2_iron_clean_clothes() {
+    let starting_state = ClothesState::Clean(5);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Iron),
+        ClothesState::Pressed(4)
+    )
+}
+
+#[test]
+fn sm_2_iron_wet_clothes() {
+    let starting_state = ClothesState::Wet(5);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Iron),
+        ClothesState::Clean(3)
+    )
+}
+
+#[test]
+fn sm_2_iron_dirty_clothes() {
+    let starting_state = ClothesState::Dirty(5);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Iron),
+        ClothesState::Dirty(2)
+    )
+}
+
+#[test]
+fn sm_2_fold_pressed_clothes() {
+    let starting_state = ClothesState::Pressed(5);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Fold),
+        ClothesState::Folded(5)
+    )
+}
+
+#[test]
+fn sm_2_fold_wet_clothes() {
+    let starting_state = ClothesState::Wet(5);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Fold),
+        ClothesState::Wet(3)
+    )
+}
+
+#[test]
+fn sm_2_wear_folded_clothes() {
+    let starting_state = ClothesState::Folded(5);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Wear),
+        ClothesState::Dirty(4)
+    )
+}
+
+#[test]
+fn sm_2_dry_pressed_clothes() {
+    let starting_state = ClothesState::Pressed(5);
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Dry),
+        ClothesState::Clean(3)
+    )
+}
+
+#[test]
+fn sm_2_iron_tattered_clothes() {
+    let starting_state = ClothesState::Tattered;
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Iron),
+        ClothesState::Tattered
+    )
+}
+
+#[test]
+fn sm_2_every_low_life_state_saturates() {
+    use super::model_check::ModelChecker;
+
+    // No state or action can ever underflow, whatever the fabric.
+    fn check<P: DamageProfile>() {
+        for life in 0..4 {
+            for start in [
+                ClothesState::Clean(life),
+                ClothesState::Pressed(life),
+                ClothesState::Folded(life),
+                ClothesState::Dirty(life),
+                ClothesState::Wet(life),
+            ] {
+                ModelChecker::<Garment<P>>::new()
+                    .step_invariant("tattered is absorbing", |from, _, to| {
+                        *from != ClothesState::Tattered || *to == ClothesState::Tattered
+                    })
+                    .check(&start)
+                    .unwrap();
+            }
+        }
+    }
+    check::<Standard>();
+    check::<Delicates>();
+    check::<Denim>();
+}
+
+#[test]
+fn sm_2_delicates_tumble_dry() {
+    let starting_state = ClothesState::Wet(5);
+    assert_eq!(
+        Garment::<Delicates>::next_state(&starting_state, &ClothesAction::Dry),
+        ClothesState::Clean(2)
+    )
+}
+
+#[test]
+fn sm_2_delicates_fall_apart_in_dryer() {
+    let starting_state = ClothesState::Clean(3);
+    assert_eq!(
+        Garment::<Delicates>::next_state_with_events(&starting_state, &ClothesAction::Dry),
+        (ClothesState::Tattered, vec![ClothesEvent::FellApart])
+    )
+}
+
+#[test]
+fn sm_2_denim_survives_dryer() {
+    let starting_state = ClothesState::Dirty(2);
+    assert_eq!(
+        Garment::<Denim>::next_state(&starting_state, &ClothesAction::Dry),
+        ClothesState::Dirty(0)
+    );
+    // The same abuse destroys ordinary clothes
+    assert_eq!(
+        ClothesMachine::next_state(&starting_state, &ClothesAction::Dry),
+        ClothesState::Tattered
+    )
+}
+
+#[test]
+fn sm_2_denim_outlasts_delicates() {
+    use super::trace::Trace;
+
+    let week = [
+        ClothesAction::Wear,
+        ClothesAction::Wash,
+        ClothesAction::Dry,
+        ClothesAction::Iron,
+        ClothesAction::Fold,
+    ];
+    let mut routine = Vec::new();
+    for _ in 0..3 {
+        routine.extend_from_slice(&week);
+    }
+
+    let denim = Trace::<Garment<Denim>>::run(ClothesState::Clean(20), &routine);
+    let delicates = Trace::<Garment<Delicates>>::run(ClothesState::Clean(20), &routine);
+    assert_eq!(denim.current(), &ClothesState::Folded(8));
+    assert_eq!(delicates.current(), &ClothesState::Tattered);
+}
@@ -2,19 +2,70 @@
 //! The atm may fail to give you cash if it is empty or you haven't swiped your card, or you have
 //! entered the wrong pin.
 
-use super::StateMachine;
+use super::repl::ReplMachine;
+use super::{FiniteStateMachine, MealyMachine, StateMachine, TryStateMachine};
+use crate::hash;
+use std::collections::BTreeMap;
 
 /// The keys on the ATM keypad
-#[derive(Hash)]
-pub enum Key{
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    Zero,
     One,
     Two,
     Three,
     Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
     Enter,
 }
 
+impl Key {
+    /// The digit printed on this key, or `None` for `Enter`.
+    pub fn digit(&self) -> Option<u64> {
+        match self {
+            Key::Zero => Some(0),
+            Key::One => Some(1),
+            Key::Two => Some(2),
+            Key::Three => Some(3),
+            Key::Four => Some(4),
+            Key::Five => Some(5),
+            Key::Six => Some(6),
+            Key::Seven => Some(7),
+            Key::Eight => Some(8),
+            Key::Nine => Some(9),
+            Key::Enter => None,
+        }
+    }
+
+    /// The key with the given digit printed on it.
+    pub fn from_digit(digit: char) -> Option<Key> {
+        match digit {
+            '0' => Some(Key::Zero),
+            '1' => Some(Key::One),
+            '2' => Some(Key::Two),
+            '3' => Some(Key::Three),
+            '4' => Some(Key::Four),
+            '5' => Some(Key::Five),
+            '6' => Some(Key::Six),
+            '7' => Some(Key::Seven),
+            '8' => Some(Key::Eight),
+            '9' => Some(Key::Nine),
+            _ => None,
+        }
+    }
+}
+
+/// The hash the ATM expects to see for a pin keyed in as this sequence of keys.
+pub fn pin_hash(keys: &[Key]) -> u64 {
+    hash(&keys)
+}
+
 /// Something you can do to the ATM
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Action {
     /// Swipe your card at the ATM. The attached value is the hash of the pin
     /// that should be keyed in on the keypad next.
@@ -23,30 +74,533 @@ pub enum Action {
     PressKey(Key),
 }
 
+/// What the ATM is waiting for
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Phase {
+    /// Showing the main menu, waiting for a card to be swiped.
+    Idle,
+    /// A card has been swiped. The ATM waits for the pin whose hash is attached.
+    AwaitingPin(u64),
+    /// The pin was correct. The ATM waits for the amount to withdraw.
+    /// The hash of the card's pin is kept so the card can be identified.
+    AwaitingAmount(u64),
+}
+
 /// The ATM. When a card is swiped, the ATM learns the correct pin's hash.
 /// It waits for you to key in your pin. You can press as many numeric keys as
 /// you like followed by enter. If the pin is incorrect, your card is returned
 /// and the ATM automatically goes back to the main menu. If your pin is correct,
 /// the ATM waits for you to key in an amount of money to withdraw. Withdraws
 /// are bounded only by the cash in the machine (there is no account balance).
+///
+/// If the requested amount is more than the cash inside, your card is returned without
+/// any cash. Pressing enter without keying in an amount cancels the withdrawal.
+///
+/// Each card is identified by its pin hash. After too many wrong pins in a row, the ATM keeps
+/// the card and refuses it from then on. A correct pin resets the count.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Atm {
     /// How much money is in the ATM
     cash_inside: u64,
-    /// The hash of a user's expected pin. The ATM will not expect any particular pin
+    /// What the ATM is waiting for. The ATM will not expect any particular pin
     /// before the user swipes their card.
-    expected_pin_hash: Option<u64>,
+    phase: Phase,
     /// All the keys that have been pressed since the last `Enter`
     keystroke_register: Vec<Key>,
+    /// How many wrong pins in a row a card may have before the ATM keeps it
+    max_pin_attempts: u32,
+    /// Wrong pins in a row for each card that has had at least one
+    failed_pin_attempts: BTreeMap<u64, u32>,
+}
+
+impl Atm {
+    /// An idle ATM holding the given cash, which keeps cards after `max_pin_attempts`
+    /// wrong pins in a row.
+    ///
+    /// Panics if `max_pin_attempts` is zero, since such an ATM would refuse every card.
+    pub fn new(cash_inside: u64, max_pin_attempts: u32) -> Self {
+        assert!(
+            max_pin_attempts >= 1,
+            "an ATM must allow at least one pin attempt"
+        );
+        Self {
+            cash_inside,
+            phase: Phase::Idle,
+            keystroke_register: Vec::new(),
+            max_pin_attempts,
+            failed_pin_attempts: BTreeMap::new(),
+        }
+    }
+
+    /// How much money is in the ATM
+    pub fn cash_inside(&self) -> u64 {
+        self.cash_inside
+    }
+
+    /// What the ATM is waiting for
+    pub fn phase(&self) -> &Phase {
+        &self.phase
+    }
+
+    /// Whether the card with the given pin hash has been locked out
+    pub fn is_locked(&self, card: u64) -> bool {
+        self.failed_pin_attempts.get(&card).copied().unwrap_or(0) >= self.max_pin_attempts
+    }
+
+    /// Finish a session, returning to the main menu.
+    fn reset(&mut self) {
+        self.phase = Phase::Idle;
+        self.keystroke_register.clear();
+    }
 }
 
+/// Things the ATM does in the physical world
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AtmEvent {
+    /// Cash comes out of the slot
+    DispenseCash(u64),
+    /// The card comes back out of the reader
+    ReturnCard,
+    /// The card is kept by the machine because of too many wrong pins
+    RetainCard,
+}
+
+/// Reasons an action at the ATM does not go as the user hoped
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AtmError {
+    /// A key was pressed, but no card has been swiped
+    NoCardInserted,
+    /// A card was swiped, but there is already a card in the machine
+    CardAlreadyInserted,
+    /// The card was swiped, but it has been locked out
+    CardLocked,
+    /// The pin was wrong. When no attempts are left, the card has been kept.
+    WrongPin { attempts_left: u32 },
+    /// The amount requested is more than the cash inside
+    InsufficientCash { requested: u64, available: u64 },
+}
+
+/// The complete result of one action: where the ATM ends up, what it did, and what went wrong.
+/// The state machine traits below each present part of this.
+struct Outcome {
+    state: Atm,
+    events: Vec<AtmEvent>,
+    error: Option<AtmError>,
+}
+
+impl Outcome {
+    fn ok(state: Atm, events: Vec<AtmEvent>) -> Self {
+        Self {
+            state,
+            events,
+            error: None,
+        }
+    }
+
+    fn err(state: Atm, events: Vec<AtmEvent>, error: AtmError) -> Self {
+        Self {
+            state,
+            events,
+            error: Some(error),
+        }
+    }
+}
+
+/// The heart of the ATM. Everything else is built on this.
+fn step(starting_state: &Atm, t: &Action) -> Outcome {
+    let mut atm = starting_state.clone();
+
+    let card = match (&starting_state.phase, t) {
+        // Swiping a card starts a new session, unless the card is locked.
+        (Phase::Idle, Action::SwipeCard(card)) => {
+            if atm.is_locked(*card) {
+                return Outcome::err(atm, vec![AtmEvent::ReturnCard], AtmError::CardLocked);
+            }
+            atm.phase = Phase::AwaitingPin(*card);
+            return Outcome::ok(atm, Vec::new());
+        }
+        (_, Action::SwipeCard(_)) => {
+            return Outcome::err(atm, Vec::new(), AtmError::CardAlreadyInserted)
+        }
+        (Phase::Idle, Action::PressKey(_)) => {
+            return Outcome::err(atm, Vec::new(), AtmError::NoCardInserted)
+        }
+        // Numeric keys simply accumulate until enter is pressed.
+        (_, Action::PressKey(key)) if *key != Key::Enter => {
+            atm.keystroke_register.push(*key);
+            return Outcome::ok(atm, Vec::new());
+        }
+        (Phase::AwaitingPin(card), _) | (Phase::AwaitingAmount(card), _) => *card,
+    };
+
+    // Enter was pressed, so whatever is in the register gets used up.
+    let keys = std::mem::take(&mut atm.keystroke_register);
+
+    match starting_state.phase {
+        Phase::AwaitingPin(_) if pin_hash(&keys) == card => {
+            atm.failed_pin_attempts.remove(&card);
+            atm.phase = Phase::AwaitingAmount(card);
+            Outcome::ok(atm, Vec::new())
+        }
+        Phase::AwaitingPin(_) => {
+            let failures = atm.failed_pin_attempts.entry(card).or_insert(0);
+            *failures += 1;
+            let attempts_left = atm.max_pin_attempts.saturating_sub(*failures);
+            let event = if attempts_left == 0 {
+                AtmEvent::RetainCard
+            } else {
+                AtmEvent::ReturnCard
+            };
+            atm.reset();
+            Outcome::err(atm, vec![event], AtmError::WrongPin { attempts_left })
+        }
+        _ if keys.is_empty() => {
+            // Nothing keyed in. The user changed their mind.
+            atm.reset();
+            Outcome::ok(atm, vec![AtmEvent::ReturnCard])
+        }
+        _ => {
+            let amount = keys
+                .iter()
+                .filter_map(Key::digit)
+                .fold(0, |amount, digit| amount * 10 + digit);
+            atm.reset();
+            if amount <= atm.cash_inside {
+                atm.cash_inside -= amount;
+                Outcome::ok(
+                    atm,
+                    vec![AtmEvent::DispenseCash(amount), AtmEvent::ReturnCard],
+                )
+            } else {
+                let available = atm.cash_inside;
+                Outcome::err(
+                    atm,
+                    vec![AtmEvent::ReturnCard],
+                    AtmError::InsufficientCash {
+                        requested: amount,
+                        available,
+                    },
+                )
+            }
+        }
+    }
+}
+
+/// The ATM always responds to an action, even one that doesn't go as the user hoped.
+/// A wrong pin still returns the card, for example.
 impl StateMachine for Atm {
+    type State = Atm;
+    type Transition = Action;
+
+    fn next_state(starting_state: &Atm, t: &Action) -> Atm {
+        step(starting_state, t).state
+    }
+
+    fn human_name() -> String {
+        "ATM".into()
+    }
+}
+
+impl MealyMachine for Atm {
+    type Event = AtmEvent;
+
+    fn next_state_with_events(starting_state: &Atm, t: &Action) -> (Atm, Vec<AtmEvent>) {
+        let outcome = step(starting_state, t);
+        (outcome.state, outcome.events)
+    }
+}
+
+/// The fallible view of the ATM reports what went wrong instead of how the ATM recovered.
+impl TryStateMachine for Atm {
+    type State = Atm;
+    type Transition = Action;
+    type Error = AtmError;
+
+    fn try_next_state(starting_state: &Atm, t: &Action) -> Result<Atm, AtmError> {
+        let outcome = step(starting_state, t);
+        match outcome.error {
+            Some(error) => Err(error),
+            None => Ok(outcome.state),
+        }
+    }
+
+    fn human_name() -> String {
+        "ATM".into()
+    }
+}
+
+/// Exploring every possible card, pin, and amount is hopeless. Instead we explore a single
+/// demo card whose pin is `1 2`, and a keypad with only the keys needed to use it.
+///
+/// The register holds any number of keys, so there is no end to the states. Three keys are
+/// already enough for a pin that is too long, so numeric keys are not worth trying beyond that.
+impl FiniteStateMachine for Atm {
+    fn transitions(atm: &Atm) -> Vec<Action> {
+        let mut transitions = vec![Action::SwipeCard(pin_hash(&[Key::One, Key::Two]))];
+        if atm.keystroke_register.len() < 3 {
+            transitions.push(Action::PressKey(Key::One));
+            transitions.push(Action::PressKey(Key::Two));
+        }
+        transitions.push(Action::PressKey(Key::Enter));
+        transitions
+    }
+}
+
+impl ReplMachine for Atm {
+    fn initial_state() -> Atm {
+        Atm::new(1000, 3)
+    }
 
-    // Exercise: Fill in these associated types.
-    type State = ();
-    type Transition = ();
+    fn parse_transition(input: &str) -> Result<Action, String> {
+        if let Some(pin) = input.strip_prefix("swipe ") {
+            let keys = pin
+                .trim()
+                .chars()
+                .map(|c| Key::from_digit(c).ok_or_else(|| format!("not a digit: {}", c)))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Action::SwipeCard(pin_hash(&keys)));
+        }
+        if input == "enter" {
+            return Ok(Action::PressKey(Key::Enter));
+        }
+        let mut chars = input.chars();
+        match (chars.next().and_then(Key::from_digit), chars.next()) {
+            (Some(key), None) => Ok(Action::PressKey(key)),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "swipe <pin>  swipe a card whose pin is the given digits",
+            "0 - 9        press a numeric key",
+            "enter        press the enter key",
+        ]
+    }
+}
 
-    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> () {
-        todo!()
+#[cfg(test)]
+fn press(atm: &Atm, keys: &[Key]) -> (Atm, Vec<AtmEvent>) {
+    let mut events = Vec::new();
+    let mut atm = atm.clone();
+    for key in keys {
+        let (next, mut new_events) = Atm::next_state_with_events(&atm, &Action::PressKey(*key));
+        atm = next;
+        events.append(&mut new_events);
     }
+    (atm, events)
+}
+
+#[cfg(test)]
+const PIN: [Key; 4] = [Key::One, Key::Two, Key::Three, Key::Four];
+
+#[cfg(test)]
+fn swiped(atm: &Atm) -> Atm {
+    Atm::next_state(atm, &Action::SwipeCard(pin_hash(&PIN)))
+}
+
+#[cfg(test)]
+fn authenticated(atm: &Atm) -> Atm {
+    let mut keys = PIN.to_vec();
+    keys.push(Key::Enter);
+    press(&swiped(atm), &keys).0
+}
+
+#[test]
+fn sm_3_swipe_card_awaits_pin() {
+    let atm = swiped(&Atm::new(100, 3));
+    assert_eq!(atm.phase(), &Phase::AwaitingPin(pin_hash(&PIN)));
+    assert!(atm.keystroke_register.is_empty());
+}
+
+#[test]
+fn sm_3_swipe_card_twice() {
+    let atm = swiped(&Atm::new(100, 3));
+    assert_eq!(
+        Atm::try_next_state(&atm, &Action::SwipeCard(7)),
+        Err(AtmError::CardAlreadyInserted)
+    );
+    assert_eq!(Atm::next_state(&atm, &Action::SwipeCard(7)), atm);
+}
+
+#[test]
+fn sm_3_press_key_without_card() {
+    let atm = Atm::new(100, 3);
+    assert_eq!(
+        Atm::try_next_state(&atm, &Action::PressKey(Key::One)),
+        Err(AtmError::NoCardInserted)
+    );
+    assert_eq!(Atm::next_state(&atm, &Action::PressKey(Key::One)), atm);
+}
+
+#[test]
+fn sm_3_keys_accumulate_in_register() {
+    let atm = swiped(&Atm::new(100, 3));
+    let (atm, events) = press(&atm, &[Key::Four, Key::Two]);
+    assert_eq!(atm.keystroke_register, vec![Key::Four, Key::Two]);
+    assert!(events.is_empty());
+}
+
+#[test]
+fn sm_3_register_takes_any_number_of_keys() {
+    let atm = swiped(&Atm::new(100, 3));
+    let (atm, _) = press(&atm, &[Key::One; 20]);
+    assert_eq!(atm.keystroke_register, vec![Key::One; 20]);
+}
+
+#[test]
+#[should_panic(expected = "at least one pin attempt")]
+fn sm_3_atm_needs_a_pin_attempt() {
+    Atm::new(100, 0);
+}
+
+#[test]
+fn sm_3_correct_pin_awaits_amount() {
+    let atm = authenticated(&Atm::new(100, 3));
+    assert_eq!(atm.phase(), &Phase::AwaitingAmount(pin_hash(&PIN)));
+    assert!(atm.keystroke_register.is_empty());
+}
+
+#[test]
+fn sm_3_wrong_pin_returns_card() {
+    let atm = swiped(&Atm::new(100, 3));
+    let atm = press(&atm, &[Key::Four, Key::Three]).0;
 
-}
\ No newline at end of file
+    assert_eq!(
+        Atm::try_next_state(&atm, &Action::PressKey(Key::Enter)),
+        Err(AtmError::WrongPin { attempts_left: 2 })
+    );
+    let (atm, events) = Atm::next_state_with_events(&atm, &Action::PressKey(Key::Enter));
+    assert_eq!(events, vec![AtmEvent::ReturnCard]);
+    assert_eq!(atm.phase(), &Phase::Idle);
+    assert!(atm.keystroke_register.is_empty());
+}
+
+#[test]
+fn sm_3_too_many_wrong_pins_retains_card() {
+    let atm = Atm::new(100, 2);
+    let (atm, events) = press(&swiped(&atm), &[Key::Enter]);
+    assert_eq!(events, vec![AtmEvent::ReturnCard]);
+    assert!(!atm.is_locked(pin_hash(&PIN)));
+
+    let atm = press(&swiped(&atm), &[Key::One]).0;
+    assert_eq!(
+        Atm::try_next_state(&atm, &Action::PressKey(Key::Enter)),
+        Err(AtmError::WrongPin { attempts_left: 0 })
+    );
+    let (atm, events) = press(&atm, &[Key::Enter]);
+    assert_eq!(events, vec![AtmEvent::RetainCard]);
+    assert!(atm.is_locked(pin_hash(&PIN)));
+}
+
+#[test]
+fn sm_3_locked_card_is_refused() {
+    let atm = Atm::new(100, 1);
+    let atm = press(&swiped(&atm), &[Key::Enter]).0;
+    assert!(atm.is_locked(pin_hash(&PIN)));
+
+    let swipe = Action::SwipeCard(pin_hash(&PIN));
+    assert_eq!(Atm::try_next_state(&atm, &swipe), Err(AtmError::CardLocked));
+    assert_eq!(
+        Atm::next_state_with_events(&atm, &swipe),
+        (atm.clone(), vec![AtmEvent::ReturnCard])
+    );
+    // Other cards are still welcome
+    assert_eq!(
+        Atm::next_state(&atm, &Action::SwipeCard(7)).phase(),
+        &Phase::AwaitingPin(7)
+    );
+}
+
+#[test]
+fn sm_3_correct_pin_resets_failures() {
+    let atm = Atm::new(100, 2);
+    let atm = press(&swiped(&atm), &[Key::Enter]).0;
+    let atm = authenticated(&atm);
+    let atm = press(&atm, &[Key::Enter]).0;
+
+    // Back to two attempts
+    let atm = press(&swiped(&atm), &[Key::Enter]).0;
+    assert!(!atm.is_locked(pin_hash(&PIN)));
+}
+
+#[test]
+fn sm_3_withdraw_cash() {
+    let atm = authenticated(&Atm::new(100, 3));
+    let (atm, events) = press(&atm, &[Key::Four, Key::Zero, Key::Enter]);
+
+    assert_eq!(
+        events,
+        vec![AtmEvent::DispenseCash(40), AtmEvent::ReturnCard]
+    );
+    assert_eq!(atm.cash_inside(), 60);
+    assert_eq!(atm.phase(), &Phase::Idle);
+}
+
+#[test]
+fn sm_3_withdraw_all_cash() {
+    let atm = authenticated(&Atm::new(100, 3));
+    let (atm, events) = press(&atm, &[Key::One, Key::Zero, Key::Zero, Key::Enter]);
+
+    assert_eq!(
+        events,
+        vec![AtmEvent::DispenseCash(100), AtmEvent::ReturnCard]
+    );
+    assert_eq!(atm.cash_inside(), 0);
+}
+
+#[test]
+fn sm_3_withdraw_too_much_cash() {
+    let atm = authenticated(&Atm::new(100, 3));
+    let atm = press(&atm, &[Key::One, Key::Zero, Key::One]).0;
+
+    assert_eq!(
+        Atm::try_next_state(&atm, &Action::PressKey(Key::Enter)),
+        Err(AtmError::InsufficientCash {
+            requested: 101,
+            available: 100
+        })
+    );
+    let (atm, events) = Atm::next_state_with_events(&atm, &Action::PressKey(Key::Enter));
+    assert_eq!(events, vec![AtmEvent::ReturnCard]);
+    assert_eq!(atm.cash_inside(), 100);
+    assert_eq!(atm.phase(), &Phase::Idle);
+}
+
+#[test]
+fn sm_3_enter_without_amount_cancels() {
+    let atm = authenticated(&Atm::new(100, 3));
+    let (atm, events) = press(&atm, &[Key::Enter]);
+
+    assert_eq!(events, vec![AtmEvent::ReturnCard]);
+    assert_eq!(atm.cash_inside(), 100);
+    assert_eq!(atm.phase(), &Phase::Idle);
+}
+
+#[test]
+fn sm_3_parse_repl_commands() {
+    assert_eq!(
+        Atm::parse_transition("swipe 1234"),
+        Ok(Action::SwipeCard(pin_hash(&PIN)))
+    );
+    assert_eq!(Atm::parse_transition("7"), Ok(Action::PressKey(Key::Seven)));
+    assert_eq!(
+        Atm::parse_transition("enter"),
+        Ok(Action::PressKey(Key::Enter))
+    );
+    assert!(Atm::parse_transition("77").is_err());
+    assert!(Atm::parse_transition("swipe 12a4").is_err());
+}
+
+#[test]
+fn sm_3_cash_never_increases() {
+    use super::model_check::ModelChecker;
+
+    let report = ModelChecker::<Atm>::new()
+        .step_invariant("cash never increases", |from, _, to| {
+            to.cash_inside() <= from.cash_inside()
+        })
+        .check(&Atm::new(30, 2))
+        .unwrap();
+    assert!(!report.truncated);
+}
@@ -0,0 +1,689 @@
+//! Checkers, also known as English draughts. Two sides take turns moving pieces diagonally across
+//! the dark squares of an eight by eight board. Men move one square forwards. A man that reaches
+//! the far side is crowned and becomes a king, which can also move backwards.
+//!
+//! Capturing is compulsory. A piece captures by jumping over an enemy piece next to it onto the
+//! empty square beyond, and must keep jumping for as long as it can. The whole chain of jumps is a
+//! single move. When several captures are possible the player may choose any of them. A man that
+//! is crowned during a capture stops there, even if it could jump on as a king.
+//!
+//! A side with no legal moves loses. The game is drawn when the same position comes up for the
+//! third time with the same side to move, or after forty moves by each side without a capture or a
+//! man moving.
+//!
+//! The dark squares are numbered 1 to 32 in the standard notation, starting from black's side,
+//! so black begins on 1 to 12 and white on 21 to 32. Moves are written as the squares visited,
+//! like `11-15` for a step or `22x15x6` for a double jump, and positions in the FEN used by
+//! Portable Draughts Notation, like `B:W18,K24:B12,16`.
+
+use crate::p1_state_machine::repl::ReplMachine;
+use crate::p1_state_machine::{FiniteStateMachine, RejectsInPlace, TryStateMachine};
+use std::fmt;
+
+/// The position at the start of every game
+pub const STARTING_FEN: &str = "B:W21-32:B1-12";
+
+/// Moves by each side without a capture or a man moving before the game is drawn
+pub const MOVE_LIMIT: u32 = 40;
+
+/// A game of checkers.
+pub struct Checkers;
+
+/// A dark square, from 0 to 31. This is one less than its number in the standard notation.
+pub type Square = u8;
+
+/// The row and column of a square on the full board. Row 0 is black's back row.
+fn coords(square: Square) -> (i8, i8) {
+    let row = (square / 4) as i8;
+    let column = (square % 4) as i8 * 2 + if row % 2 == 0 { 1 } else { 0 };
+    (row, column)
+}
+
+/// The square the given number of rows and columns away, if it is still on the board.
+fn offset(square: Square, rows: i8, columns: i8) -> Option<Square> {
+    let (row, column) = coords(square);
+    let (row, column) = (row + rows, column + columns);
+    ((0..8).contains(&row) && (0..8).contains(&column)).then(|| (row * 4 + column / 2) as Square)
+}
+
+/// Read a square number like `15`.
+fn parse_square(text: &str) -> Result<Square, String> {
+    match text.parse::<u8>() {
+        Ok(n @ 1..=32) => Ok(n - 1),
+        _ => Err(format!("not a square: {}", text)),
+    }
+}
+
+/// One of the two sides
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Color {
+    Black,
+    White,
+}
+
+impl Color {
+    /// The opposing side
+    pub fn other(&self) -> Color {
+        match self {
+            Color::Black => Color::White,
+            Color::White => Color::Black,
+        }
+    }
+
+    /// The direction this side's men move in, as a number of rows
+    fn forward(&self) -> i8 {
+        match self {
+            Color::Black => 1,
+            Color::White => -1,
+        }
+    }
+
+    /// The row where this side's men are crowned
+    fn crowning_row(&self) -> i8 {
+        match self {
+            Color::Black => 7,
+            Color::White => 0,
+        }
+    }
+
+    /// The letter used for this side in FEN
+    fn letter(&self) -> char {
+        match self {
+            Color::Black => 'B',
+            Color::White => 'W',
+        }
+    }
+}
+
+/// A piece on the board
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Piece {
+    pub color: Color,
+    pub king: bool,
+}
+
+impl Piece {
+    /// The directions this piece may move in, as rows and columns
+    fn directions(&self) -> Vec<(i8, i8)> {
+        let forward = self.color.forward();
+        if self.king {
+            vec![(forward, -1), (forward, 1), (-forward, -1), (-forward, 1)]
+        } else {
+            vec![(forward, -1), (forward, 1)]
+        }
+    }
+}
+
+/// A move, given as every square the piece visits from start to finish. A capture jumps two
+/// rows with every step and a simple move steps a single row.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CheckersMove {
+    pub path: Vec<Square>,
+    pub capture: bool,
+}
+
+impl CheckersMove {
+    /// Read a move in standard notation, like `11-15` or `22x15x6`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let capture = text.contains('x');
+        let path = text
+            .split(if capture { 'x' } else { '-' })
+            .map(parse_square)
+            .collect::<Result<Vec<_>, _>>()?;
+        if path.len() < 2 || (!capture && path.len() != 2) {
+            return Err(format!("not a move: {}", text));
+        }
+        Ok(Self { path, capture })
+    }
+
+    /// Write the move in standard notation.
+    pub fn to_notation(&self) -> String {
+        let squares: Vec<String> = self.path.iter().map(|s| (s + 1).to_string()).collect();
+        squares.join(if self.capture { "x" } else { "-" })
+    }
+
+    /// The square the move starts from
+    pub fn from(&self) -> Square {
+        self.path[0]
+    }
+
+    /// The square the move ends on
+    pub fn to(&self) -> Square {
+        self.path[self.path.len() - 1]
+    }
+}
+
+/// How a game stands
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    /// The side to move has at least one legal move
+    InProgress,
+    /// The side to move has no legal moves, so the given side won
+    Win(Color),
+    /// The same position has come up three times
+    Repetition,
+    /// Too long without a capture or a man moving
+    MoveLimit,
+}
+
+/// Why a move was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MoveError {
+    /// The game is already over
+    GameOver(Outcome),
+    /// There is no piece on the starting square
+    NoPiece(Square),
+    /// The piece on the starting square belongs to the other side
+    NotYourPiece(Square),
+    /// A capture is available, so a simple move is not allowed
+    CaptureRequired,
+    /// The piece can jump again and must do so
+    IncompleteCapture,
+    /// Only the start and finish of the capture were given, and more than one capture fits
+    Ambiguous,
+    /// The piece cannot move like this at all
+    Illegal,
+}
+
+/// The state of a game: the position, and what is needed to detect a draw
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Game {
+    /// The piece on each dark square, if any
+    board: [Option<Piece>; 32],
+    /// The side to move
+    to_move: Color,
+    /// Moves by either side since the last capture or man move
+    quiet_moves: u32,
+    /// Every position since the last capture or man move, including this one. Those moves can
+    /// never be undone, so no earlier position can come up again.
+    history: Vec<([Option<Piece>; 32], Color)>,
+}
+
+impl Game {
+    /// The position at the start of a game.
+    pub fn starting() -> Self {
+        Self::from_fen(STARTING_FEN).expect("the starting position is valid")
+    }
+
+    /// Read a position in FEN. The game is taken to have just reached this position.
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        let fen = fen.trim().trim_end_matches('.');
+        let mut fields = fen.split(':');
+        let to_move = match fields.next() {
+            Some("B") => Color::Black,
+            Some("W") => Color::White,
+            _ => return Err(format!("not a side to move: {}", fen)),
+        };
+
+        let mut board = [None; 32];
+        for field in fields {
+            let color = match field.chars().next() {
+                Some('B') => Color::Black,
+                Some('W') => Color::White,
+                _ => return Err(format!("not a list of pieces: {}", field)),
+            };
+            for entry in field[1..].split(',').filter(|e| !e.is_empty()) {
+                let (king, squares) = match entry.strip_prefix('K') {
+                    Some(squares) => (true, squares),
+                    None => (false, entry),
+                };
+                let squares = match squares.split_once('-') {
+                    Some((first, last)) => parse_square(first)?..=parse_square(last)?,
+                    None => parse_square(squares)?..=parse_square(squares)?,
+                };
+                for square in squares {
+                    if board[square as usize].is_some() {
+                        return Err(format!("two pieces on square {}", square + 1));
+                    }
+                    board[square as usize] = Some(Piece { color, king });
+                }
+            }
+        }
+
+        Ok(Self {
+            board,
+            to_move,
+            quiet_moves: 0,
+            history: vec![(board, to_move)],
+        })
+    }
+
+    /// Write the position in FEN.
+    pub fn to_fen(&self) -> String {
+        let pieces = |color: Color| {
+            let squares: Vec<String> = (0..32)
+                .filter_map(|square| match self.board[square] {
+                    Some(p) if p.color == color && p.king => Some(format!("K{}", square + 1)),
+                    Some(p) if p.color == color => Some(format!("{}", square + 1)),
+                    _ => None,
+                })
+                .collect();
+            format!("{}{}", color.letter(), squares.join(","))
+        };
+        format!(
+            "{}:{}:{}",
+            self.to_move.letter(),
+            pieces(Color::White),
+            pieces(Color::Black)
+        )
+    }
+
+    /// The piece on a square, if any.
+    pub fn piece(&self, square: Square) -> Option<Piece> {
+        self.board[square as usize]
+    }
+
+    /// The side to move.
+    pub fn to_move(&self) -> Color {
+        self.to_move
+    }
+
+    /// Every capture the side to move could make, followed to the end of each chain of jumps.
+    fn captures(&self) -> Vec<CheckersMove> {
+        let mut moves = Vec::new();
+        for from in 0..32 {
+            if let Some(piece) = self.piece(from).filter(|p| p.color == self.to_move) {
+                self.extend_jumps(piece, &mut vec![from], &mut Vec::new(), &mut moves);
+            }
+        }
+        moves
+    }
+
+    /// Find every way to continue a chain of jumps. Captured pieces stay on the board until the
+    /// move is over, so they block the way and cannot be jumped twice.
+    fn extend_jumps(
+        &self,
+        piece: Piece,
+        path: &mut Vec<Square>,
+        captured: &mut Vec<Square>,
+        moves: &mut Vec<CheckersMove>,
+    ) {
+        let at = *path.last().expect("a path always has a start");
+        let crowned = !piece.king && path.len() > 1 && coords(at).0 == piece.color.crowning_row();
+        let mut extended = false;
+        if !crowned {
+            for (rows, columns) in piece.directions() {
+                let (Some(over), Some(to)) =
+                    (offset(at, rows, columns), offset(at, rows * 2, columns * 2))
+                else {
+                    continue;
+                };
+                let enemy = self
+                    .piece(over)
+                    .is_some_and(|p| p.color != piece.color && !captured.contains(&over));
+                // The square the piece started from is empty once it has moved away.
+                let empty = self.piece(to).is_none() || to == path[0];
+                if enemy && empty {
+                    path.push(to);
+                    captured.push(over);
+                    self.extend_jumps(piece, path, captured, moves);
+                    path.pop();
+                    captured.pop();
+                    extended = true;
+                }
+            }
+        }
+        if !extended && path.len() > 1 {
+            moves.push(CheckersMove {
+                path: path.clone(),
+                capture: true,
+            });
+        }
+    }
+
+    /// Every simple move the side to move could make, ignoring captures.
+    fn steps(&self) -> Vec<CheckersMove> {
+        let mut moves = Vec::new();
+        for from in 0..32 {
+            if let Some(piece) = self.piece(from).filter(|p| p.color == self.to_move) {
+                for (rows, columns) in piece.directions() {
+                    if let Some(to) =
+                        offset(from, rows, columns).filter(|&to| self.piece(to).is_none())
+                    {
+                        moves.push(CheckersMove {
+                            path: vec![from, to],
+                            capture: false,
+                        });
+                    }
+                }
+            }
+        }
+        moves
+    }
+
+    /// Every legal move for the side to move. If any capture is possible, only captures are.
+    pub fn legal_moves(&self) -> Vec<CheckersMove> {
+        let captures = self.captures();
+        if captures.is_empty() {
+            self.steps()
+        } else {
+            captures
+        }
+    }
+
+    /// How the game stands.
+    pub fn outcome(&self) -> Outcome {
+        let position = (self.board, self.to_move);
+        if self.legal_moves().is_empty() {
+            Outcome::Win(self.to_move.other())
+        } else if self.history.iter().filter(|&p| *p == position).count() >= 3 {
+            Outcome::Repetition
+        } else if self.quiet_moves >= MOVE_LIMIT * 2 {
+            Outcome::MoveLimit
+        } else {
+            Outcome::InProgress
+        }
+    }
+
+    /// Play a move without checking that it is legal.
+    fn play(&self, m: &CheckersMove) -> Game {
+        let mut next = self.clone();
+        let mut piece = next.board[m.from() as usize]
+            .take()
+            .expect("only legal moves are played");
+        if m.capture {
+            for step in m.path.windows(2) {
+                let (from, to) = (coords(step[0]), coords(step[1]));
+                let over = ((from.0 + to.0) / 2 * 4 + (from.1 + to.1) / 2 / 2) as usize;
+                next.board[over] = None;
+            }
+        }
+        let man_moved = !piece.king;
+        if coords(m.to()).0 == piece.color.crowning_row() {
+            piece.king = true;
+        }
+        next.board[m.to() as usize] = Some(piece);
+        next.to_move = self.to_move.other();
+
+        if m.capture || man_moved {
+            next.quiet_moves = 0;
+            next.history.clear();
+        } else {
+            next.quiet_moves += 1;
+        }
+        next.history.push((next.board, next.to_move));
+        next
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::starting()
+    }
+}
+
+/// A game is shown as the FEN of its position.
+impl fmt::Debug for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Game({})", self.to_fen())
+    }
+}
+
+/// Count the leaf nodes of the legal move tree to the given depth.
+pub fn perft(game: &Game, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = game.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves.iter().map(|m| perft(&game.play(m), depth - 1)).sum()
+}
+
+impl TryStateMachine for Checkers {
+    type State = Game;
+    type Transition = CheckersMove;
+    type Error = MoveError;
+
+    fn try_next_state(starting_state: &Game, t: &CheckersMove) -> Result<Game, MoveError> {
+        let outcome = starting_state.outcome();
+        if outcome != Outcome::InProgress {
+            return Err(MoveError::GameOver(outcome));
+        }
+        // Moves built by hand rather than parsed might not name real squares at all.
+        let on_board = |s: &Square| (*s as usize) < starting_state.board.len();
+        if t.path.is_empty() || !t.path.iter().all(on_board) {
+            return Err(MoveError::Illegal);
+        }
+        match starting_state.piece(t.from()) {
+            None => return Err(MoveError::NoPiece(t.from())),
+            Some(p) if p.color != starting_state.to_move => {
+                return Err(MoveError::NotYourPiece(t.from()))
+            }
+            Some(_) => {}
+        }
+
+        let legal = starting_state.legal_moves();
+        if legal.contains(t) {
+            return Ok(starting_state.play(t));
+        }
+        if !t.capture {
+            return Err(if legal.iter().any(|m| m.capture) {
+                MoveError::CaptureRequired
+            } else {
+                MoveError::Illegal
+            });
+        }
+        if legal.iter().any(|m| m.path.starts_with(&t.path)) {
+            return Err(MoveError::IncompleteCapture);
+        }
+        // A capture may be written as just its start and finish, if only one capture fits.
+        let fitting: Vec<&CheckersMove> = legal
+            .iter()
+            .filter(|m| t.path.len() == 2 && m.from() == t.from() && m.to() == t.to())
+            .collect();
+        match fitting[..] {
+            [m] => Ok(starting_state.play(m)),
+            [] => Err(MoveError::Illegal),
+            _ => Err(MoveError::Ambiguous),
+        }
+    }
+
+    fn human_name() -> String {
+        "Checkers".into()
+    }
+}
+
+impl RejectsInPlace for Checkers {}
+
+impl FiniteStateMachine for Checkers {
+    fn transitions(state: &Game) -> Vec<CheckersMove> {
+        match state.outcome() {
+            Outcome::InProgress => state.legal_moves(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ReplMachine for Checkers {
+    fn initial_state() -> Game {
+        Game::starting()
+    }
+
+    fn parse_transition(input: &str) -> Result<CheckersMove, String> {
+        CheckersMove::parse(input)
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "<from>-<to>         a simple move, like 11-15",
+            "<from>x<to>x...     a capture, like 22x15x6, or 22x6 if only one capture fits",
+        ]
+    }
+}
+
+#[cfg(test)]
+fn play(fen: &str, moves: &[&str]) -> Game {
+    moves.iter().fold(Game::from_fen(fen).unwrap(), |g, m| {
+        Checkers::try_next_state(&g, &CheckersMove::parse(m).unwrap())
+            .unwrap_or_else(|e| panic!("{} was rejected: {:?}", m, e))
+    })
+}
+
+#[cfg(test)]
+fn try_move(game: &Game, m: &str) -> Result<Game, MoveError> {
+    Checkers::try_next_state(game, &CheckersMove::parse(m).unwrap())
+}
+
+#[test]
+fn checkers_perft_starting_position() {
+    let game = Game::starting();
+    let counts: Vec<u64> = (1..=6).map(|depth| perft(&game, depth)).collect();
+    assert_eq!(counts, vec![7, 49, 302, 1469, 7361, 36768]);
+}
+
+#[test]
+fn checkers_fen_round_trip() {
+    let game = Game::starting();
+    assert_eq!(
+        game.to_fen(),
+        "B:W21,22,23,24,25,26,27,28,29,30,31,32:B1,2,3,4,5,6,7,8,9,10,11,12"
+    );
+    for fen in ["W:W18,K24,27:BK3,12,16", "B:W:B1"] {
+        assert_eq!(Game::from_fen(fen).unwrap().to_fen(), fen);
+    }
+    assert!(Game::from_fen("X:W1:B2").is_err());
+    assert!(Game::from_fen("B:W1:B1").is_err());
+    assert!(Game::from_fen("B:W33:B2").is_err());
+}
+
+#[test]
+fn checkers_notation() {
+    let m = CheckersMove::parse("22x15x6").unwrap();
+    assert_eq!(m.path, vec![21, 14, 5]);
+    assert!(m.capture);
+    assert_eq!(m.to_notation(), "22x15x6");
+    assert_eq!(CheckersMove::parse("11-15").unwrap().to_notation(), "11-15");
+    assert!(CheckersMove::parse("11-15-19").is_err());
+    assert!(CheckersMove::parse("11").is_err());
+    assert!(CheckersMove::parse("0-4").is_err());
+}
+
+#[test]
+fn checkers_men_only_move_forwards() {
+    let game = play(STARTING_FEN, &["11-15", "22-17"]);
+    assert_eq!(try_move(&game, "15-11"), Err(MoveError::Illegal));
+    assert_eq!(try_move(&game, "17-13"), Err(MoveError::NotYourPiece(16)));
+    assert_eq!(try_move(&game, "14-18"), Err(MoveError::NoPiece(13)));
+    assert!(try_move(&game, "15-19").is_ok());
+}
+
+#[test]
+fn checkers_capture_is_compulsory() {
+    let game = play(STARTING_FEN, &["11-15", "22-18"]);
+    assert_eq!(try_move(&game, "9-13"), Err(MoveError::CaptureRequired));
+
+    let game = play(STARTING_FEN, &["11-15", "22-18", "15x22"]);
+    assert_eq!(game.piece(17), None);
+    // White may recapture either way, but must recapture.
+    let mut captures: Vec<String> = game.legal_moves().iter().map(|m| m.to_notation()).collect();
+    captures.sort();
+    assert_eq!(captures, vec!["25x18", "26x17"]);
+}
+
+#[test]
+fn checkers_multiple_jumps_are_one_move() {
+    let game = Game::from_fen("B:W14,23,24:B9").unwrap();
+    assert_eq!(try_move(&game, "9x18"), Err(MoveError::IncompleteCapture));
+
+    let after = play("B:W14,23,24:B9", &["9x18x27"]);
+    assert_eq!(after.to_fen(), "W:W24:B27");
+    // Only the start and finish are needed when only one capture fits.
+    assert_eq!(try_move(&game, "9x27"), Ok(after));
+}
+
+#[test]
+fn checkers_ambiguous_capture() {
+    // A king on 10 can take either piece first and finish on 10 both ways round.
+    let game = Game::from_fen("B:W14,15,22,23:BK10").unwrap();
+    assert_eq!(game.legal_moves().len(), 2);
+    assert_eq!(try_move(&game, "10x10"), Err(MoveError::Ambiguous));
+    let after = play("B:W14,15,22,23:BK10", &["10x19x26x17x10"]);
+    assert_eq!(after.to_fen(), "W:W:BK10");
+    assert_eq!(after.outcome(), Outcome::Win(Color::Black));
+}
+
+#[test]
+fn checkers_crowning_ends_the_move() {
+    let game = play("B:W26,27:B22", &["22x31"]);
+    assert_eq!(
+        game.piece(30),
+        Some(Piece {
+            color: Color::Black,
+            king: true
+        })
+    );
+    assert_eq!(game.to_fen(), "W:W27:BK31");
+
+    // Kings can move backwards.
+    let game = play("W:W27:BK31", &["27-24", "31-26", "24-19", "26-22"]);
+    assert_eq!(game.to_fen(), "W:W19:BK22");
+}
+
+#[test]
+fn checkers_no_pieces_left_loses() {
+    let game = play("B:W14:B9", &["9x18"]);
+    assert_eq!(game.outcome(), Outcome::Win(Color::Black));
+    assert_eq!(
+        try_move(&game, "18-22"),
+        Err(MoveError::GameOver(Outcome::Win(Color::Black)))
+    );
+}
+
+#[test]
+fn checkers_blocked_side_loses() {
+    // White's only man is hemmed in, with nowhere to land after a jump.
+    let game = Game::from_fen("W:W32:B23,27,28").unwrap();
+    assert!(game.legal_moves().is_empty());
+    assert_eq!(game.outcome(), Outcome::Win(Color::Black));
+}
+
+#[test]
+fn checkers_draw_by_repetition() {
+    let game = play(
+        "W:WK32:BK1",
+        &["32-28", "1-5", "28-32", "5-1", "32-28", "1-5", "28-32"],
+    );
+    assert_eq!(game.outcome(), Outcome::InProgress);
+    let game = play(&game.to_fen(), &["5-1"]);
+    // A fresh game from the same position has no history to repeat.
+    assert_eq!(game.outcome(), Outcome::InProgress);
+
+    let game = play(
+        "W:WK32:BK1",
+        &[
+            "32-28", "1-5", "28-32", "5-1", "32-28", "1-5", "28-32", "5-1",
+        ],
+    );
+    assert_eq!(game.outcome(), Outcome::Repetition);
+}
+
+#[test]
+fn checkers_draw_by_move_limit() {
+    let mut game = Game::from_fen("W:WK32,20:BK1").unwrap();
+    game.quiet_moves = MOVE_LIMIT * 2 - 1;
+    let after = Checkers::try_next_state(&game, &CheckersMove::parse("32-28").unwrap()).unwrap();
+    assert_eq!(after.outcome(), Outcome::MoveLimit);
+    // Moving a man resets the count.
+    let after = Checkers::try_next_state(&game, &CheckersMove::parse("20-16").unwrap()).unwrap();
+    assert_eq!(after.outcome(), Outcome::InProgress);
+}
+
+#[test]
+fn checkers_rejects_malformed_moves() {
+    let game = Checkers::initial_state();
+    for path in [vec![], vec![8, 32], vec![200]] {
+        assert_eq!(
+            Checkers::try_next_state(
+                &game,
+                &CheckersMove {
+                    path,
+                    capture: false
+                }
+            ),
+            Err(MoveError::Illegal)
+        );
+    }
+}
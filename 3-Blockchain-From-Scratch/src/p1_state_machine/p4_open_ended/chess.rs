@@ -0,0 +1,1062 @@
+//! Chess. The state is a complete position: where every piece stands, whose turn it is, which
+//! castling moves are still allowed, where an en passant capture could happen, and how long it
+//! has been since the last capture or pawn move. The transitions are moves.
+//!
+//! Positions can be read and written in Forsyth-Edwards Notation (FEN), and moves in the
+//! coordinate notation used by chess engines, for example `e2e4` or `e7e8q`.
+//!
+//! Move generation is the hard part of any chess program, and the easiest to get subtly wrong.
+//! The standard way to check it is `perft`: count every sequence of legal moves to some depth,
+//! and compare against numbers that many other programs agree on.
+
+use crate::p1_state_machine::repl::ReplMachine;
+use crate::p1_state_machine::trace::TraceCodec;
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{FiniteStateMachine, RejectsInPlace, TryStateMachine};
+use std::fmt;
+
+/// The position at the start of every game
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// A game of chess.
+pub struct Chess;
+
+/// A square on the board, from 0 for a1 to 63 for h8. Files run fastest, so b1 is 1 and a2 is 8.
+pub type Square = u8;
+
+/// The file of a square, from 0 for the a-file to 7 for the h-file
+fn file(square: Square) -> i8 {
+    (square % 8) as i8
+}
+
+/// The rank of a square, from 0 for the first rank to 7 for the eighth
+fn rank(square: Square) -> i8 {
+    (square / 8) as i8
+}
+
+/// The square a given number of files and ranks away, if it is still on the board.
+fn offset(square: Square, files: i8, ranks: i8) -> Option<Square> {
+    let (f, r) = (file(square) + files, rank(square) + ranks);
+    ((0..8).contains(&f) && (0..8).contains(&r)).then(|| (r * 8 + f) as Square)
+}
+
+/// The name of a square, like `e4`.
+pub fn square_name(square: Square) -> String {
+    format!("{}{}", (b'a' + square % 8) as char, square / 8 + 1)
+}
+
+/// Read a square name like `e4`.
+pub fn parse_square(name: &str) -> Result<Square, String> {
+    match name.as_bytes() {
+        [f @ b'a'..=b'h', r @ b'1'..=b'8'] => Ok((r - b'1') * 8 + (f - b'a')),
+        _ => Err(format!("not a square: {}", name)),
+    }
+}
+
+const KNIGHT_JUMPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const ORTHOGONALS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const DIAGONALS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+/// One of the two sides
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    /// The opposing side
+    pub fn other(&self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+
+    /// The direction this side's pawns move in, as a number of ranks
+    fn forward(&self) -> i8 {
+        match self {
+            Color::White => 1,
+            Color::Black => -1,
+        }
+    }
+
+    /// The rank this side's pieces start on
+    fn back_rank(&self) -> i8 {
+        match self {
+            Color::White => 0,
+            Color::Black => 7,
+        }
+    }
+}
+
+/// The different kinds of piece
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PieceKind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+/// A piece on the board
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Piece {
+    pub color: Color,
+    pub kind: PieceKind,
+}
+
+impl Piece {
+    /// The letter used for this piece in FEN. White pieces are upper case.
+    pub fn to_char(&self) -> char {
+        let c = match self.kind {
+            PieceKind::Pawn => 'p',
+            PieceKind::Knight => 'n',
+            PieceKind::Bishop => 'b',
+            PieceKind::Rook => 'r',
+            PieceKind::Queen => 'q',
+            PieceKind::King => 'k',
+        };
+        match self.color {
+            Color::White => c.to_ascii_uppercase(),
+            Color::Black => c,
+        }
+    }
+
+    /// Read a piece letter as used in FEN.
+    pub fn from_char(c: char) -> Option<Piece> {
+        let kind = match c.to_ascii_lowercase() {
+            'p' => PieceKind::Pawn,
+            'n' => PieceKind::Knight,
+            'b' => PieceKind::Bishop,
+            'r' => PieceKind::Rook,
+            'q' => PieceKind::Queen,
+            'k' => PieceKind::King,
+            _ => return None,
+        };
+        let color = if c.is_ascii_uppercase() {
+            Color::White
+        } else {
+            Color::Black
+        };
+        Some(Piece { color, kind })
+    }
+}
+
+/// Which castling moves are still allowed. A right is lost for good once the king or the
+/// relevant rook moves, or the rook is captured.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+
+/// A move of a piece from one square to another. Castling is written as the king moving two
+/// squares, and `promotion` says what a pawn reaching the last rank becomes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChessMove {
+    pub from: Square,
+    pub to: Square,
+    pub promotion: Option<PieceKind>,
+}
+
+impl ChessMove {
+    /// Read a move in coordinate notation, like `e2e4` or `e7e8q`.
+    pub fn from_uci(text: &str) -> Result<Self, String> {
+        if !text.is_ascii() || !(4..=5).contains(&text.len()) {
+            return Err(format!("not a move: {}", text));
+        }
+        let promotion = match text.get(4..) {
+            None | Some("") => None,
+            Some("q") => Some(PieceKind::Queen),
+            Some("r") => Some(PieceKind::Rook),
+            Some("b") => Some(PieceKind::Bishop),
+            Some("n") => Some(PieceKind::Knight),
+            Some(p) => return Err(format!("cannot promote to {}", p)),
+        };
+        Ok(Self {
+            from: parse_square(&text[0..2])?,
+            to: parse_square(&text[2..4])?,
+            promotion,
+        })
+    }
+
+    /// Write the move in coordinate notation.
+    pub fn to_uci(&self) -> String {
+        let promotion = self
+            .promotion
+            .map(|kind| {
+                Piece {
+                    color: Color::Black,
+                    kind,
+                }
+                .to_char()
+                .to_string()
+            })
+            .unwrap_or_default();
+        format!(
+            "{}{}{}",
+            square_name(self.from),
+            square_name(self.to),
+            promotion
+        )
+    }
+}
+
+/// How a game stands
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    /// The side to move has at least one legal move
+    InProgress,
+    /// The side to move is in check and cannot escape. The given side won.
+    Checkmate(Color),
+    /// The side to move is not in check, but has no legal moves
+    Stalemate,
+    /// Fifty moves by each side without a capture or pawn move
+    FiftyMoveRule,
+}
+
+/// Why a move was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MoveError {
+    /// The game is already over
+    GameOver(Outcome),
+    /// There is no piece on the starting square
+    NoPiece(Square),
+    /// The piece on the starting square belongs to the other side
+    NotYourPiece(Square),
+    /// The piece could move like this, but it would leave its own king in check
+    LeavesKingInCheck,
+    /// The piece cannot move like this at all
+    Illegal,
+}
+
+/// A complete chess position
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Position {
+    /// The piece on each square, if any
+    board: [Option<Piece>; 64],
+    /// The side to move
+    to_move: Color,
+    /// The castling moves still allowed
+    castling: CastlingRights,
+    /// The square a pawn skipped over with a double step on the previous move
+    en_passant: Option<Square>,
+    /// Half moves since the last capture or pawn move
+    halfmove_clock: u32,
+    /// The number of the current full move, starting at 1 and increasing after black moves
+    fullmove_number: u32,
+}
+
+impl Position {
+    /// The position at the start of a game.
+    pub fn starting() -> Self {
+        Self::from_fen(STARTING_FEN).expect("the starting position is valid")
+    }
+
+    /// The piece on a square, if any.
+    pub fn piece(&self, square: Square) -> Option<Piece> {
+        self.board.get(square as usize).copied().flatten()
+    }
+
+    /// The side to move.
+    pub fn to_move(&self) -> Color {
+        self.to_move
+    }
+
+    /// The castling moves still allowed.
+    pub fn castling(&self) -> CastlingRights {
+        self.castling
+    }
+
+    /// The square a pawn could be captured on en passant, if any.
+    pub fn en_passant(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    /// Half moves since the last capture or pawn move.
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    /// Read a position in Forsyth-Edwards Notation.
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let [placement, side, castling, en_passant, halfmove, fullmove] = fields[..] else {
+            return Err(format!("expected 6 fields in FEN, found {}", fields.len()));
+        };
+
+        let mut board = [None; 64];
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("expected 8 ranks, found {}", ranks.len()));
+        }
+        for (i, row) in ranks.iter().enumerate() {
+            let rank = 7 - i;
+            let mut file = 0;
+            for c in row.chars() {
+                if let Some(skip) = c.to_digit(10).filter(|d| (1..=8).contains(d)) {
+                    file += skip as usize;
+                } else {
+                    let piece = Piece::from_char(c).ok_or_else(|| format!("not a piece: {}", c))?;
+                    if file < 8 {
+                        board[rank * 8 + file] = Some(piece);
+                    }
+                    file += 1;
+                }
+            }
+            if file != 8 {
+                return Err(format!("rank {} does not have 8 squares", rank + 1));
+            }
+        }
+        for color in [Color::White, Color::Black] {
+            let kings = board
+                .iter()
+                .filter(|p| {
+                    **p == Some(Piece {
+                        color,
+                        kind: PieceKind::King,
+                    })
+                })
+                .count();
+            if kings != 1 {
+                return Err(format!("{:?} must have exactly one king", color));
+            }
+        }
+
+        let to_move = match side {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(format!("not a side to move: {}", side)),
+        };
+
+        if castling != "-" && !castling.chars().all(|c| "KQkq".contains(c)) {
+            return Err(format!("not castling rights: {}", castling));
+        }
+        let castling = CastlingRights {
+            white_kingside: castling.contains('K'),
+            white_queenside: castling.contains('Q'),
+            black_kingside: castling.contains('k'),
+            black_queenside: castling.contains('q'),
+        };
+
+        let en_passant = match en_passant {
+            "-" => None,
+            square => Some(parse_square(square)?),
+        };
+
+        Ok(Self {
+            board,
+            to_move,
+            castling,
+            en_passant,
+            halfmove_clock: halfmove
+                .parse()
+                .map_err(|_| format!("not a halfmove clock: {}", halfmove))?,
+            fullmove_number: fullmove
+                .parse()
+                .map_err(|_| format!("not a move number: {}", fullmove))?,
+        })
+    }
+
+    /// Write the position in Forsyth-Edwards Notation.
+    pub fn to_fen(&self) -> String {
+        let mut placement = Vec::new();
+        for rank in (0..8).rev() {
+            let mut row = String::new();
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.board[rank * 8 + file] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        row.push(piece.to_char());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            placement.push(row);
+        }
+
+        let side = match self.to_move {
+            Color::White => "w",
+            Color::Black => "b",
+        };
+
+        let rights = [
+            (self.castling.white_kingside, 'K'),
+            (self.castling.white_queenside, 'Q'),
+            (self.castling.black_kingside, 'k'),
+            (self.castling.black_queenside, 'q'),
+        ];
+        let mut castling: String = rights
+            .iter()
+            .filter(|(allowed, _)| *allowed)
+            .map(|(_, c)| c)
+            .collect();
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement.join("/"),
+            side,
+            castling,
+            self.en_passant.map_or("-".into(), square_name),
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+
+    /// Where the given side's king stands.
+    fn king(&self, color: Color) -> Option<Square> {
+        (0..64).find(|s| {
+            self.piece(*s)
+                == Some(Piece {
+                    color,
+                    kind: PieceKind::King,
+                })
+        })
+    }
+
+    /// Whether any piece of the given side attacks the square.
+    pub fn is_attacked(&self, square: Square, by: Color) -> bool {
+        let is = |s: Option<Square>, kinds: &[PieceKind]| {
+            s.and_then(|s| self.piece(s))
+                .is_some_and(|p| p.color == by && kinds.contains(&p.kind))
+        };
+
+        // Look backwards from the square, the way an attacking pawn would come.
+        let pawns = [-1, 1]
+            .iter()
+            .any(|f| is(offset(square, *f, -by.forward()), &[PieceKind::Pawn]));
+        let knights = KNIGHT_JUMPS
+            .iter()
+            .any(|(f, r)| is(offset(square, *f, *r), &[PieceKind::Knight]));
+        let king = KING_STEPS
+            .iter()
+            .any(|(f, r)| is(offset(square, *f, *r), &[PieceKind::King]));
+        let slider = |directions: &[(i8, i8)], kinds: &[PieceKind]| {
+            directions.iter().any(|(f, r)| {
+                let mut current = offset(square, *f, *r);
+                while let Some(s) = current {
+                    if self.piece(s).is_some() {
+                        return is(Some(s), kinds);
+                    }
+                    current = offset(s, *f, *r);
+                }
+                false
+            })
+        };
+
+        pawns
+            || knights
+            || king
+            || slider(&ORTHOGONALS, &[PieceKind::Rook, PieceKind::Queen])
+            || slider(&DIAGONALS, &[PieceKind::Bishop, PieceKind::Queen])
+    }
+
+    /// Whether the side to move is in check.
+    pub fn in_check(&self) -> bool {
+        self.king(self.to_move)
+            .is_some_and(|k| self.is_attacked(k, self.to_move.other()))
+    }
+
+    /// Every move the pieces could make, ignoring whether it leaves the king in check.
+    fn pseudo_legal_moves(&self) -> Vec<ChessMove> {
+        let mut moves = Vec::new();
+        let us = self.to_move;
+        let mut push = |from: Square, to: Square| {
+            moves.push(ChessMove {
+                from,
+                to,
+                promotion: None,
+            })
+        };
+
+        for from in 0..64 {
+            let Some(piece) = self.piece(from).filter(|p| p.color == us) else {
+                continue;
+            };
+            let open = |to: Square| self.piece(to).is_none_or(|p| p.color != us);
+            match piece.kind {
+                PieceKind::Pawn => {
+                    let forward = us.forward();
+                    if let Some(one) = offset(from, 0, forward).filter(|s| self.piece(*s).is_none())
+                    {
+                        push(from, one);
+                        let start = us.back_rank() + forward;
+                        if rank(from) == start {
+                            if let Some(two) =
+                                offset(from, 0, 2 * forward).filter(|s| self.piece(*s).is_none())
+                            {
+                                push(from, two);
+                            }
+                        }
+                    }
+                    for side in [-1, 1] {
+                        if let Some(to) = offset(from, side, forward) {
+                            let enemy = self.piece(to).is_some_and(|p| p.color != us);
+                            if enemy || self.en_passant == Some(to) {
+                                push(from, to);
+                            }
+                        }
+                    }
+                }
+                PieceKind::Knight | PieceKind::King => {
+                    let steps = if piece.kind == PieceKind::Knight {
+                        &KNIGHT_JUMPS
+                    } else {
+                        &KING_STEPS
+                    };
+                    for (f, r) in steps {
+                        if let Some(to) = offset(from, *f, *r).filter(|s| open(*s)) {
+                            push(from, to);
+                        }
+                    }
+                }
+                PieceKind::Bishop | PieceKind::Rook | PieceKind::Queen => {
+                    let directions: &[(i8, i8)] = match piece.kind {
+                        PieceKind::Bishop => &DIAGONALS,
+                        PieceKind::Rook => &ORTHOGONALS,
+                        _ => &KING_STEPS,
+                    };
+                    for (f, r) in directions {
+                        let mut current = offset(from, *f, *r);
+                        while let Some(to) = current {
+                            if open(to) {
+                                push(from, to);
+                            }
+                            if self.piece(to).is_some() {
+                                break;
+                            }
+                            current = offset(to, *f, *r);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Castling. The king may not castle out of, through, or into check.
+        let back = (us.back_rank() * 8) as Square;
+        let (kingside, queenside) = match us {
+            Color::White => (self.castling.white_kingside, self.castling.white_queenside),
+            Color::Black => (self.castling.black_kingside, self.castling.black_queenside),
+        };
+        let king = Piece {
+            color: us,
+            kind: PieceKind::King,
+        };
+        let rook = Piece {
+            color: us,
+            kind: PieceKind::Rook,
+        };
+        let empty = |files: &[Square]| files.iter().all(|f| self.piece(back + f).is_none());
+        let safe = |files: &[Square]| {
+            files
+                .iter()
+                .all(|f| !self.is_attacked(back + f, us.other()))
+        };
+        if self.piece(back + 4) == Some(king) {
+            if kingside && self.piece(back + 7) == Some(rook) && empty(&[5, 6]) && safe(&[4, 5, 6])
+            {
+                push(back + 4, back + 6);
+            }
+            if queenside && self.piece(back) == Some(rook) && empty(&[1, 2, 3]) && safe(&[4, 3, 2])
+            {
+                push(back + 4, back + 2);
+            }
+        }
+
+        // A pawn reaching the last rank must promote, and may become any of four pieces.
+        let last_rank = us.other().back_rank();
+        moves
+            .into_iter()
+            .flat_map(|m| {
+                let promotes = self.piece(m.from).map(|p| p.kind) == Some(PieceKind::Pawn)
+                    && rank(m.to) == last_rank;
+                if promotes {
+                    [
+                        PieceKind::Queen,
+                        PieceKind::Rook,
+                        PieceKind::Bishop,
+                        PieceKind::Knight,
+                    ]
+                    .iter()
+                    .map(|p| ChessMove {
+                        promotion: Some(*p),
+                        ..m
+                    })
+                    .collect()
+                } else {
+                    vec![m]
+                }
+            })
+            .collect()
+    }
+
+    /// Play a move without checking that it is legal.
+    fn play(&self, m: &ChessMove) -> Position {
+        let mut next = self.clone();
+        let Some(piece) = next.board[m.from as usize].take() else {
+            return next;
+        };
+        let mut capture = next.board[m.to as usize].is_some();
+
+        if piece.kind == PieceKind::Pawn && Some(m.to) == self.en_passant && !capture {
+            // The captured pawn is beside the moving pawn, not on the square it moves to.
+            let captured = (rank(m.from) * 8 + file(m.to)) as usize;
+            next.board[captured] = None;
+            capture = true;
+        }
+
+        next.board[m.to as usize] = Some(match m.promotion {
+            Some(kind) => Piece {
+                color: piece.color,
+                kind,
+            },
+            None => piece,
+        });
+
+        if piece.kind == PieceKind::King && (file(m.to) - file(m.from)).abs() == 2 {
+            let back = m.from - 4;
+            let (rook_from, rook_to) = if file(m.to) == 6 {
+                (back + 7, back + 5)
+            } else {
+                (back, back + 3)
+            };
+            next.board[rook_to as usize] = next.board[rook_from as usize].take();
+        }
+
+        // Moving a king or rook, or capturing a rook, loses the matching castling rights.
+        for square in [m.from, m.to] {
+            match square {
+                0 => next.castling.white_queenside = false,
+                4 => {
+                    next.castling.white_kingside = false;
+                    next.castling.white_queenside = false;
+                }
+                7 => next.castling.white_kingside = false,
+                56 => next.castling.black_queenside = false,
+                60 => {
+                    next.castling.black_kingside = false;
+                    next.castling.black_queenside = false;
+                }
+                63 => next.castling.black_kingside = false,
+                _ => {}
+            }
+        }
+
+        let double_step = piece.kind == PieceKind::Pawn && (rank(m.to) - rank(m.from)).abs() == 2;
+        next.en_passant = double_step.then(|| (m.from + m.to) / 2);
+
+        if piece.kind == PieceKind::Pawn || capture {
+            next.halfmove_clock = 0;
+        } else {
+            next.halfmove_clock += 1;
+        }
+        if piece.color == Color::Black {
+            next.fullmove_number = next.fullmove_number.saturating_add(1);
+        }
+        next.to_move = piece.color.other();
+        next
+    }
+
+    /// Every legal move for the side to move.
+    pub fn legal_moves(&self) -> Vec<ChessMove> {
+        let us = self.to_move;
+        self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|m| {
+                let next = self.play(m);
+                next.king(us)
+                    .is_none_or(|k| !next.is_attacked(k, us.other()))
+            })
+            .collect()
+    }
+
+    /// Whether the game is still going, and if not, how it ended.
+    pub fn outcome(&self) -> Outcome {
+        if self.legal_moves().is_empty() {
+            if self.in_check() {
+                Outcome::Checkmate(self.to_move.other())
+            } else {
+                Outcome::Stalemate
+            }
+        } else if self.halfmove_clock >= 100 {
+            Outcome::FiftyMoveRule
+        } else {
+            Outcome::InProgress
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::starting()
+    }
+}
+
+/// A position is shown as its FEN, which is far easier to read than 64 squares.
+impl fmt::Debug for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Position({})", self.to_fen())
+    }
+}
+
+/// Count the leaf nodes of the legal move tree to the given depth.
+pub fn perft(position: &Position, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = position.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .iter()
+        .map(|m| perft(&position.play(m), depth - 1))
+        .sum()
+}
+
+impl TryStateMachine for Chess {
+    type State = Position;
+    type Transition = ChessMove;
+    type Error = MoveError;
+
+    fn try_next_state(starting_state: &Position, t: &ChessMove) -> Result<Position, MoveError> {
+        let outcome = starting_state.outcome();
+        if outcome != Outcome::InProgress {
+            return Err(MoveError::GameOver(outcome));
+        }
+        match starting_state.piece(t.from) {
+            None => return Err(MoveError::NoPiece(t.from)),
+            Some(p) if p.color != starting_state.to_move => {
+                return Err(MoveError::NotYourPiece(t.from))
+            }
+            Some(_) => {}
+        }
+        if starting_state.legal_moves().contains(t) {
+            Ok(starting_state.play(t))
+        } else if starting_state.pseudo_legal_moves().contains(t) {
+            Err(MoveError::LeavesKingInCheck)
+        } else {
+            Err(MoveError::Illegal)
+        }
+    }
+
+    fn human_name() -> String {
+        "Chess".into()
+    }
+}
+
+impl RejectsInPlace for Chess {}
+
+impl FiniteStateMachine for Chess {
+    fn transitions(state: &Position) -> Vec<ChessMove> {
+        match state.outcome() {
+            Outcome::InProgress => state.legal_moves(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ReplMachine for Chess {
+    fn initial_state() -> Position {
+        Position::starting()
+    }
+
+    fn parse_transition(input: &str) -> Result<ChessMove, String> {
+        ChessMove::from_uci(input)
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec!["<move>       a move like e2e4, e1g1 to castle, or e7e8q to promote"]
+    }
+}
+
+impl TraceCodec for Chess {
+    fn encode_state(state: &Position) -> String {
+        state.to_fen()
+    }
+
+    fn decode_state(text: &str) -> Result<Position, String> {
+        Position::from_fen(text)
+    }
+
+    fn encode_transition(t: &ChessMove) -> String {
+        t.to_uci()
+    }
+
+    fn decode_transition(text: &str) -> Result<ChessMove, String> {
+        ChessMove::from_uci(text)
+    }
+}
+
+#[cfg(test)]
+fn play(fen: &str, moves: &[&str]) -> Position {
+    moves.iter().fold(Position::from_fen(fen).unwrap(), |p, m| {
+        Chess::try_next_state(&p, &ChessMove::from_uci(m).unwrap()).unwrap()
+    })
+}
+
+#[test]
+fn chess_perft_starting_position() {
+    let position = Position::starting();
+    assert_eq!(perft(&position, 1), 20);
+    assert_eq!(perft(&position, 2), 400);
+    assert_eq!(perft(&position, 3), 8_902);
+    assert_eq!(perft(&position, 4), 197_281);
+}
+
+#[test]
+fn chess_perft_kiwipete() {
+    // Castling, en passant, promotions, and pins all in one position.
+    let position =
+        Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    assert_eq!(perft(&position, 1), 48);
+    assert_eq!(perft(&position, 2), 2_039);
+    assert_eq!(perft(&position, 3), 97_862);
+}
+
+#[test]
+fn chess_perft_endgame() {
+    // Checks discovered by en passant captures
+    let position = Position::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+    assert_eq!(perft(&position, 1), 14);
+    assert_eq!(perft(&position, 2), 191);
+    assert_eq!(perft(&position, 3), 2_812);
+    assert_eq!(perft(&position, 4), 43_238);
+}
+
+#[test]
+fn chess_perft_promotions() {
+    let position =
+        Position::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1")
+            .unwrap();
+    assert_eq!(perft(&position, 1), 6);
+    assert_eq!(perft(&position, 2), 264);
+    assert_eq!(perft(&position, 3), 9_467);
+
+    let position =
+        Position::from_fen("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8").unwrap();
+    assert_eq!(perft(&position, 1), 44);
+    assert_eq!(perft(&position, 2), 1_486);
+    assert_eq!(perft(&position, 3), 62_379);
+}
+
+#[test]
+fn chess_fen_round_trip() {
+    for fen in [
+        STARTING_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+        "8/8/8/8/8/8/8/K6k b - - 99 150",
+    ] {
+        assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+    }
+}
+
+#[test]
+fn chess_move_number_stops_at_its_limit() {
+    let fen = format!("8/8/8/8/8/8/8/K6k b - - 0 {}", u32::MAX);
+    let position = play(&fen, &["h1h2"]);
+    assert_eq!(
+        position.to_fen(),
+        format!("8/8/8/8/8/8/7k/K7 w - - 1 {}", u32::MAX)
+    );
+}
+
+#[test]
+fn chess_fen_rejects_nonsense() {
+    assert!(Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1").is_err());
+    assert!(
+        Position::from_fen("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").is_err()
+    );
+    assert!(
+        Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1").is_err()
+    );
+    assert!(
+        Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1").is_err()
+    );
+    assert!(Position::from_fen("8/8/8/8/8/8/8/K7 w - - 0 1").is_err());
+    assert!(Position::from_fen(STARTING_FEN.trim_end_matches(" 0 1")).is_err());
+}
+
+#[test]
+fn chess_moves_update_clocks_and_en_passant() {
+    let position = play(STARTING_FEN, &["e2e4"]);
+    assert_eq!(
+        position.to_fen(),
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+    );
+    let position = play(STARTING_FEN, &["g1f3", "g8f6", "f3g1"]);
+    assert_eq!(position.halfmove_clock(), 3);
+    assert_eq!(position.en_passant(), None);
+}
+
+#[test]
+fn chess_en_passant_capture() {
+    let position = play(STARTING_FEN, &["e2e4", "a7a6", "e4e5", "d7d5", "e5d6"]);
+    assert_eq!(position.piece(parse_square("d5").unwrap()), None);
+    assert_eq!(
+        position.piece(parse_square("d6").unwrap()),
+        Piece::from_char('P')
+    );
+    assert_eq!(position.halfmove_clock(), 0);
+}
+
+#[test]
+fn chess_castling() {
+    let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+    let position = play(fen, &["e1g1"]);
+    assert_eq!(position.to_fen(), "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1");
+    let position = play(fen, &["e1g1", "e8c8"]);
+    assert_eq!(position.to_fen(), "2kr3r/8/8/8/8/8/8/R4RK1 w - - 2 2");
+
+    // Once white castles queenside, the rook on d1 stops black doing the same.
+    let position = play(fen, &["e1c1"]);
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e8c8").unwrap()),
+        Err(MoveError::Illegal)
+    );
+
+    // Capturing a rook takes away the right to castle with it.
+    let position = play(fen, &["a1a8"]);
+    assert_eq!(position.to_fen(), "R3k2r/8/8/8/8/8/8/4K2R b Kk - 0 1");
+}
+
+#[test]
+fn chess_cannot_castle_through_check() {
+    // The black rook covers f1, but not the squares on the queenside.
+    let position = Position::from_fen("4k3/8/8/8/8/8/5r2/R3K2R w KQ - 0 1").unwrap();
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e1g1").unwrap()),
+        Err(MoveError::Illegal)
+    );
+    assert!(Chess::try_next_state(&position, &ChessMove::from_uci("e1c1").unwrap()).is_ok());
+}
+
+#[test]
+fn chess_pinned_piece_cannot_move() {
+    let position = Position::from_fen("4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1").unwrap();
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e2c3").unwrap()),
+        Err(MoveError::LeavesKingInCheck)
+    );
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e2e4").unwrap()),
+        Err(MoveError::Illegal)
+    );
+}
+
+#[test]
+fn chess_rejects_wrong_pieces() {
+    let position = Position::starting();
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e3e4").unwrap()),
+        Err(MoveError::NoPiece(parse_square("e3").unwrap()))
+    );
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e7e5").unwrap()),
+        Err(MoveError::NotYourPiece(parse_square("e7").unwrap()))
+    );
+    // Ignored entirely by the infallible machine
+    assert_eq!(
+        Chess::next_state(&position, &ChessMove::from_uci("e7e5").unwrap()),
+        position
+    );
+}
+
+#[test]
+fn chess_promotion() {
+    let fen = "8/4P3/8/8/8/8/8/k3K3 w - - 0 1";
+    let position = play(fen, &["e7e8n"]);
+    assert_eq!(
+        position.piece(parse_square("e8").unwrap()),
+        Piece::from_char('N')
+    );
+
+    // A pawn on the last rank must say what it becomes.
+    let position = Position::from_fen(fen).unwrap();
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e7e8").unwrap()),
+        Err(MoveError::Illegal)
+    );
+}
+
+#[test]
+fn chess_fools_mate() {
+    let position = play(STARTING_FEN, &["f2f3", "e7e5", "g2g4", "d8h4"]);
+    assert!(position.in_check());
+    assert_eq!(position.outcome(), Outcome::Checkmate(Color::Black));
+    assert!(Chess::transitions(&position).is_empty());
+    assert_eq!(
+        Chess::try_next_state(&position, &ChessMove::from_uci("e1f2").unwrap()),
+        Err(MoveError::GameOver(Outcome::Checkmate(Color::Black)))
+    );
+}
+
+#[test]
+fn chess_stalemate() {
+    let position = Position::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+    assert!(!position.in_check());
+    assert_eq!(position.outcome(), Outcome::Stalemate);
+}
+
+#[test]
+fn chess_fifty_move_rule() {
+    let position = play("8/8/8/8/8/8/R7/K6k w - - 99 80", &["a2b2"]);
+    assert_eq!(position.outcome(), Outcome::FiftyMoveRule);
+    // A pawn move or capture would have reset the clock.
+    let position = play("8/8/8/8/8/8/P7/K6k w - - 99 80", &["a2a3"]);
+    assert_eq!(position.outcome(), Outcome::InProgress);
+}
+
+#[test]
+fn chess_trace_round_trip() {
+    use crate::p1_state_machine::trace::Trace;
+
+    let moves: Vec<ChessMove> = ["e2e4", "e7e5", "g1f3"]
+        .iter()
+        .map(|m| ChessMove::from_uci(m).unwrap())
+        .collect();
+    let trace = Trace::<Chess>::run(Position::starting(), &moves);
+    let text = trace.to_text();
+
+    assert!(
+        text.ends_with("g1f3\trnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2\n")
+    );
+    assert_eq!(Trace::<Chess>::from_text(&text), Ok(trace));
+}
@@ -0,0 +1,669 @@
+//! A Department of Motor Vehicles, which licenses drivers and registers vehicles.
+//!
+//! Would-be drivers start by applying for a learner's permit. After holding it for long enough
+//! they may take the road test, and passing it gets them a licence. Licensed drivers collect
+//! points for traffic violations. Too many points and the licence is suspended for a while, after
+//! which it comes back with a clean slate. A driver caught driving while suspended, or suspended
+//! too many times, loses their licence for good.
+//!
+//! Vehicles are registered to a driver and the registration must be renewed every year. A
+//! registration that is not renewed in time lapses, and a lapsed vehicle cannot be sold until it
+//! has been renewed.
+//!
+//! Each driver and each vehicle is a small state machine of its own. The office keeps every one
+//! of them in a `Many` collection keyed by its id, routes each action to the right one, and only
+//! checks the rules that involve more than one of them, like a vehicle's new owner existing.
+//! Time passes one day per tick, and every tick visits every driver and vehicle.
+
+use crate::p1_state_machine::combinators::{Instance, InstanceError, Many};
+use crate::p1_state_machine::repl::ReplMachine;
+#[cfg(test)]
+use crate::p1_state_machine::repl::{apply_commands, try_command};
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+
+/// Identifies a driver
+pub type DriverId = String;
+/// Identifies a vehicle by its number plate
+pub type Plate = String;
+
+/// How many days a permit must be held before taking the road test
+pub const PERMIT_DAYS: u64 = 30;
+/// How many points it takes to be suspended
+pub const SUSPENSION_POINTS: u32 = 12;
+/// How many days a suspension lasts
+pub const SUSPENSION_DAYS: u64 = 90;
+/// How many suspensions it takes to lose a licence for good
+pub const REVOKE_AFTER_SUSPENSIONS: u32 = 3;
+/// How many days a registration lasts
+pub const REGISTRATION_DAYS: u64 = 365;
+/// How many days before it runs out a registration may be renewed
+pub const RENEWAL_WINDOW: u64 = 30;
+
+/// Where a driver stands
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum LicenceStatus {
+    /// Learning, with a permit issued on the given day
+    Permit { issued: u64 },
+    /// Fully licensed
+    Licensed,
+    /// Not allowed to drive until the given day
+    Suspended { until: u64 },
+    /// Not allowed to drive ever again
+    Revoked,
+}
+
+/// A driver's record
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Driver {
+    pub status: LicenceStatus,
+    /// Points from violations since the last suspension
+    pub points: u32,
+    pub suspensions: u32,
+    pub failed_tests: u32,
+}
+
+impl Driver {
+    /// A learner whose permit was issued on the given day.
+    pub fn learner(today: u64) -> Self {
+        Self {
+            status: LicenceStatus::Permit { issued: today },
+            points: 0,
+            suspensions: 0,
+            failed_tests: 0,
+        }
+    }
+}
+
+/// Things that happen to a single driver
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum DriverAction {
+    /// Take the road test.
+    RoadTest { passed: bool, today: u64 },
+    /// Be caught breaking the traffic laws.
+    Violation { points: u32, today: u64 },
+    /// A day passes.
+    Tick { today: u64 },
+}
+
+/// Why something cannot happen to a driver
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LicenceError {
+    /// Only permit holders can take the road test
+    NoPermit,
+    /// The permit has not been held for long enough. The test can be taken from the given day.
+    PermitTooNew { eligible: u64 },
+    /// Permit holders drive under supervision, so violations are the supervisor's
+    NotLicensed,
+    /// The licence has been revoked
+    Revoked,
+}
+
+/// The life of a single driver's licence
+pub struct Licensing;
+
+impl TryStateMachine for Licensing {
+    type State = Driver;
+    type Transition = DriverAction;
+    type Error = LicenceError;
+
+    fn try_next_state(starting_state: &Driver, t: &DriverAction) -> Result<Driver, LicenceError> {
+        let mut driver = starting_state.clone();
+        match (driver.status, *t) {
+            (_, DriverAction::Tick { today }) => {
+                if let LicenceStatus::Suspended { until } = driver.status {
+                    if today >= until {
+                        driver.status = LicenceStatus::Licensed;
+                    }
+                }
+            }
+            (LicenceStatus::Revoked, _) => return Err(LicenceError::Revoked),
+            (LicenceStatus::Permit { issued }, DriverAction::RoadTest { passed, today }) => {
+                if today < issued + PERMIT_DAYS {
+                    return Err(LicenceError::PermitTooNew {
+                        eligible: issued + PERMIT_DAYS,
+                    });
+                }
+                if passed {
+                    driver.status = LicenceStatus::Licensed;
+                } else {
+                    driver.failed_tests += 1;
+                }
+            }
+            (_, DriverAction::RoadTest { .. }) => return Err(LicenceError::NoPermit),
+            (LicenceStatus::Permit { .. }, DriverAction::Violation { .. }) => {
+                return Err(LicenceError::NotLicensed)
+            }
+            (LicenceStatus::Suspended { .. }, DriverAction::Violation { .. }) => {
+                driver.status = LicenceStatus::Revoked;
+            }
+            (LicenceStatus::Licensed, DriverAction::Violation { points, today }) => {
+                driver.points = driver.points.saturating_add(points);
+                if driver.points >= SUSPENSION_POINTS {
+                    driver.points = 0;
+                    driver.suspensions += 1;
+                    driver.status = if driver.suspensions >= REVOKE_AFTER_SUSPENSIONS {
+                        LicenceStatus::Revoked
+                    } else {
+                        LicenceStatus::Suspended {
+                            until: today + SUSPENSION_DAYS,
+                        }
+                    };
+                }
+            }
+        }
+        Ok(driver)
+    }
+
+    fn human_name() -> String {
+        "Driver's Licence".into()
+    }
+}
+
+/// A vehicle's registration
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Vehicle {
+    pub owner: DriverId,
+    /// The first day the registration is no longer valid
+    pub expires: u64,
+    pub lapsed: bool,
+}
+
+/// Things that happen to a single vehicle
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum VehicleAction {
+    /// Renew the registration for another year.
+    Renew { today: u64 },
+    /// Sell the vehicle to someone else.
+    Transfer { to: DriverId },
+    /// A day passes.
+    Tick { today: u64 },
+}
+
+/// Why something cannot happen to a vehicle
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RegistrationError {
+    /// Renewals are only accepted close to the expiry date. They open on the given day.
+    TooEarly { window_opens: u64 },
+    /// The registration has lapsed and must be renewed first
+    Lapsed,
+    /// The vehicle already belongs to them
+    SameOwner,
+}
+
+/// The life of a single vehicle's registration
+pub struct Registration;
+
+impl TryStateMachine for Registration {
+    type State = Vehicle;
+    type Transition = VehicleAction;
+    type Error = RegistrationError;
+
+    fn try_next_state(
+        starting_state: &Vehicle,
+        t: &VehicleAction,
+    ) -> Result<Vehicle, RegistrationError> {
+        let mut vehicle = starting_state.clone();
+        match t {
+            VehicleAction::Renew { today } => {
+                let window_opens = vehicle.expires.saturating_sub(RENEWAL_WINDOW);
+                if *today < window_opens {
+                    return Err(RegistrationError::TooEarly { window_opens });
+                }
+                // A lapsed registration runs for a full year from the renewal, not from when it
+                // lapsed, so the days without one are not paid for.
+                vehicle.expires = vehicle.expires.max(*today) + REGISTRATION_DAYS;
+                vehicle.lapsed = false;
+            }
+            VehicleAction::Transfer { to } => {
+                if vehicle.lapsed {
+                    return Err(RegistrationError::Lapsed);
+                }
+                if vehicle.owner == *to {
+                    return Err(RegistrationError::SameOwner);
+                }
+                vehicle.owner = to.clone();
+            }
+            VehicleAction::Tick { today } => {
+                if *today >= vehicle.expires {
+                    vehicle.lapsed = true;
+                }
+            }
+        }
+        Ok(vehicle)
+    }
+
+    fn human_name() -> String {
+        "Vehicle Registration".into()
+    }
+}
+
+/// Every driver's record, keyed by driver
+pub type Drivers = Many<Licensing, DriverId>;
+/// Every vehicle's registration, keyed by number plate
+pub type Vehicles = Many<Registration, Plate>;
+
+/// The Department of Motor Vehicles
+pub struct Dmv;
+
+/// The state of the whole office
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Office {
+    /// The current day
+    today: u64,
+    drivers: <Drivers as TryStateMachine>::State,
+    vehicles: <Vehicles as TryStateMachine>::State,
+}
+
+impl Office {
+    /// The current day.
+    pub fn today(&self) -> u64 {
+        self.today
+    }
+
+    /// A driver's record, if they have ever applied for a permit.
+    pub fn driver(&self, driver: &str) -> Option<&Driver> {
+        self.drivers.get(driver)
+    }
+
+    /// A vehicle's registration, if it has been registered.
+    pub fn vehicle(&self, plate: &str) -> Option<&Vehicle> {
+        self.vehicles.get(plate)
+    }
+
+    /// The number plates of every vehicle owned by a driver.
+    pub fn vehicles_owned_by(&self, owner: &str) -> Vec<&Plate> {
+        self.vehicles
+            .iter()
+            .filter(|(_, v)| v.owner == owner)
+            .map(|(plate, _)| plate)
+            .collect()
+    }
+
+    /// Whether a driver may currently drive unsupervised.
+    pub fn may_drive(&self, driver: &str) -> bool {
+        self.driver(driver)
+            .is_some_and(|d| d.status == LicenceStatus::Licensed)
+    }
+
+    fn update_drivers(
+        &mut self,
+        t: <Drivers as TryStateMachine>::Transition,
+    ) -> Result<(), DmvError> {
+        self.drivers = Drivers::try_next_state(&self.drivers, &t).map_err(|e| match e {
+            InstanceError::NoSuchInstance(driver) => DmvError::NoSuchDriver(driver),
+            InstanceError::Rejected(_, e) => DmvError::Licence(e),
+        })?;
+        Ok(())
+    }
+
+    fn update_vehicles(
+        &mut self,
+        t: <Vehicles as TryStateMachine>::Transition,
+    ) -> Result<(), DmvError> {
+        self.vehicles = Vehicles::try_next_state(&self.vehicles, &t).map_err(|e| match e {
+            InstanceError::NoSuchInstance(plate) => DmvError::NoSuchVehicle(plate),
+            InstanceError::Rejected(_, e) => DmvError::Registration(e),
+        })?;
+        Ok(())
+    }
+
+    fn apply_to_driver(&mut self, driver: &str, t: DriverAction) -> Result<(), DmvError> {
+        self.update_drivers(Instance::Apply(driver.into(), t))
+    }
+
+    fn apply_to_vehicle(&mut self, plate: &str, t: VehicleAction) -> Result<(), DmvError> {
+        self.update_vehicles(Instance::Apply(plate.into(), t))
+    }
+
+    fn check_driver(&self, driver: &str) -> Result<(), DmvError> {
+        match self.drivers.contains_key(driver) {
+            true => Ok(()),
+            false => Err(DmvError::NoSuchDriver(driver.into())),
+        }
+    }
+}
+
+/// Things that happen at the DMV
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DmvAction {
+    /// Apply for a learner's permit. This is how every driver's record starts.
+    ApplyForPermit { driver: DriverId },
+    /// Take the road test.
+    RoadTest { driver: DriverId, passed: bool },
+    /// Record a traffic violation.
+    Violation { driver: DriverId, points: u32 },
+    /// Register a new vehicle to a driver.
+    Register { plate: Plate, owner: DriverId },
+    /// Renew a vehicle's registration.
+    Renew { plate: Plate },
+    /// Transfer a vehicle to a new owner.
+    Transfer { plate: Plate, to: DriverId },
+    /// A day passes.
+    Tick,
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DmvError {
+    /// This driver already has a record
+    DriverExists,
+    /// A vehicle with this plate is already registered
+    PlateExists,
+    /// There is no record of this driver
+    NoSuchDriver(DriverId),
+    /// There is no vehicle with this plate
+    NoSuchVehicle(Plate),
+    /// The driver's record does not allow it
+    Licence(LicenceError),
+    /// The vehicle's registration does not allow it
+    Registration(RegistrationError),
+}
+
+impl TryStateMachine for Dmv {
+    type State = Office;
+    type Transition = DmvAction;
+    type Error = DmvError;
+
+    fn try_next_state(starting_state: &Office, t: &DmvAction) -> Result<Office, DmvError> {
+        let mut state = starting_state.clone();
+        let today = state.today;
+        match t {
+            DmvAction::ApplyForPermit { driver } => {
+                if state.drivers.contains_key(driver) {
+                    return Err(DmvError::DriverExists);
+                }
+                state.update_drivers(Instance::Add(driver.clone(), Driver::learner(today)))?;
+            }
+            DmvAction::RoadTest { driver, passed } => {
+                let passed = *passed;
+                state.apply_to_driver(driver, DriverAction::RoadTest { passed, today })?;
+            }
+            DmvAction::Violation { driver, points } => {
+                let points = *points;
+                state.apply_to_driver(driver, DriverAction::Violation { points, today })?;
+            }
+            DmvAction::Register { plate, owner } => {
+                state.check_driver(owner)?;
+                if state.vehicles.contains_key(plate) {
+                    return Err(DmvError::PlateExists);
+                }
+                let vehicle = Vehicle {
+                    owner: owner.clone(),
+                    expires: today + REGISTRATION_DAYS,
+                    lapsed: false,
+                };
+                state.update_vehicles(Instance::Add(plate.clone(), vehicle))?;
+            }
+            DmvAction::Renew { plate } => {
+                state.apply_to_vehicle(plate, VehicleAction::Renew { today })?;
+            }
+            DmvAction::Transfer { plate, to } => {
+                state.check_driver(to)?;
+                state.apply_to_vehicle(plate, VehicleAction::Transfer { to: to.clone() })?;
+            }
+            DmvAction::Tick => {
+                state.today += 1;
+                let today = state.today;
+                let drivers: Vec<DriverId> = state.drivers.keys().cloned().collect();
+                for driver in drivers {
+                    state.apply_to_driver(&driver, DriverAction::Tick { today })?;
+                }
+                let plates: Vec<Plate> = state.vehicles.keys().cloned().collect();
+                for plate in plates {
+                    state.apply_to_vehicle(&plate, VehicleAction::Tick { today })?;
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "DMV".into()
+    }
+}
+
+impl RejectsInPlace for Dmv {}
+
+impl ReplMachine for Dmv {
+    fn initial_state() -> Office {
+        Office::default()
+    }
+
+    fn parse_transition(input: &str) -> Result<DmvAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let word = |index: usize, what: &str| -> Result<String, String> {
+            words
+                .get(index)
+                .map(|w| w.to_string())
+                .ok_or_else(|| format!("missing {}", what))
+        };
+        match words.first() {
+            Some(&"permit") => Ok(DmvAction::ApplyForPermit {
+                driver: word(1, "driver")?,
+            }),
+            Some(&"test") => Ok(DmvAction::RoadTest {
+                driver: word(1, "driver")?,
+                passed: match words.get(2) {
+                    Some(&"pass") => true,
+                    Some(&"fail") => false,
+                    _ => return Err("the test is either pass or fail".into()),
+                },
+            }),
+            Some(&"violation") => Ok(DmvAction::Violation {
+                driver: word(1, "driver")?,
+                points: word(2, "points")?
+                    .parse()
+                    .map_err(|_| "points must be a number".to_string())?,
+            }),
+            Some(&"register") => Ok(DmvAction::Register {
+                plate: word(1, "plate")?,
+                owner: word(2, "owner")?,
+            }),
+            Some(&"renew") => Ok(DmvAction::Renew {
+                plate: word(1, "plate")?,
+            }),
+            Some(&"transfer") => Ok(DmvAction::Transfer {
+                plate: word(1, "plate")?,
+                to: word(2, "new owner")?,
+            }),
+            Some(&"tick") => Ok(DmvAction::Tick),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "permit <driver>              apply for a learner's permit",
+            "test <driver> pass|fail      take the road test",
+            "violation <driver> <points>  record a traffic violation",
+            "register <plate> <owner>     register a vehicle",
+            "renew <plate>                renew a registration",
+            "transfer <plate> <owner>     sell a vehicle",
+            "tick                         a day passes",
+        ]
+    }
+}
+
+#[cfg(test)]
+fn wait(state: &Office, days: u64) -> Office {
+    (0..days).fold(state.clone(), |state, _| {
+        Dmv::next_state(&state, &DmvAction::Tick)
+    })
+}
+
+/// Alice has just been licensed, and bob holds a permit.
+#[cfg(test)]
+fn office() -> Office {
+    let state = apply_commands::<Dmv>(&Office::default(), &["permit alice", "permit bob"]);
+    apply_commands::<Dmv>(&wait(&state, PERMIT_DAYS), &["test alice pass"])
+}
+
+#[test]
+fn dmv_getting_licensed() {
+    let state = apply_commands::<Dmv>(&Office::default(), &["permit alice"]);
+    assert_eq!(
+        try_command::<Dmv>(&state, "permit alice"),
+        Err(DmvError::DriverExists)
+    );
+    assert_eq!(
+        try_command::<Dmv>(&wait(&state, PERMIT_DAYS - 1), "test alice pass"),
+        Err(DmvError::Licence(LicenceError::PermitTooNew {
+            eligible: PERMIT_DAYS
+        }))
+    );
+
+    let state = apply_commands::<Dmv>(
+        &wait(&state, PERMIT_DAYS),
+        &["test alice fail", "test alice pass"],
+    );
+    assert!(state.may_drive("alice"));
+    assert_eq!(state.driver("alice").unwrap().failed_tests, 1);
+    assert_eq!(
+        try_command::<Dmv>(&state, "test alice pass"),
+        Err(DmvError::Licence(LicenceError::NoPermit))
+    );
+    assert_eq!(
+        try_command::<Dmv>(&state, "test carol pass"),
+        Err(DmvError::NoSuchDriver("carol".into()))
+    );
+}
+
+#[test]
+fn dmv_points_lead_to_suspension() {
+    let state = office();
+    assert_eq!(
+        try_command::<Dmv>(&state, "violation bob 3"),
+        Err(DmvError::Licence(LicenceError::NotLicensed))
+    );
+
+    let state = apply_commands::<Dmv>(&state, &["violation alice 6", "violation alice 5"]);
+    assert!(state.may_drive("alice"));
+    assert_eq!(state.driver("alice").unwrap().points, 11);
+
+    let state = apply_commands::<Dmv>(&state, &["violation alice 2"]);
+    let until = state.today() + SUSPENSION_DAYS;
+    assert_eq!(
+        state.driver("alice").unwrap().status,
+        LicenceStatus::Suspended { until }
+    );
+    assert_eq!(state.driver("alice").unwrap().points, 0);
+
+    assert!(!wait(&state, SUSPENSION_DAYS - 1).may_drive("alice"));
+    assert!(wait(&state, SUSPENSION_DAYS).may_drive("alice"));
+}
+
+#[test]
+fn dmv_huge_violations_suspend_rather_than_overflow() {
+    let state = apply_commands::<Dmv>(
+        &office(),
+        &["violation alice 1", "violation alice 4294967295"],
+    );
+    assert_eq!(
+        state.driver("alice").unwrap().status,
+        LicenceStatus::Suspended {
+            until: state.today() + SUSPENSION_DAYS
+        }
+    );
+}
+
+#[test]
+fn dmv_licences_are_revoked() {
+    // Driving while suspended
+    let state = apply_commands::<Dmv>(&office(), &["violation alice 12", "violation alice 1"]);
+    assert_eq!(
+        state.driver("alice").unwrap().status,
+        LicenceStatus::Revoked
+    );
+    assert!(!wait(&state, SUSPENSION_DAYS).may_drive("alice"));
+    assert_eq!(
+        try_command::<Dmv>(&state, "violation alice 1"),
+        Err(DmvError::Licence(LicenceError::Revoked))
+    );
+
+    // Too many suspensions
+    let mut state = office();
+    for _ in 1..REVOKE_AFTER_SUSPENSIONS {
+        state = wait(
+            &apply_commands::<Dmv>(&state, &["violation alice 12"]),
+            SUSPENSION_DAYS,
+        );
+        assert!(state.may_drive("alice"));
+    }
+    let state = apply_commands::<Dmv>(&state, &["violation alice 12"]);
+    assert_eq!(
+        state.driver("alice").unwrap().status,
+        LicenceStatus::Revoked
+    );
+}
+
+#[test]
+fn dmv_registrations_lapse_and_renew() {
+    let state = apply_commands::<Dmv>(&office(), &["register car1 alice"]);
+    let expires = state.today() + REGISTRATION_DAYS;
+    assert_eq!(state.vehicle("car1").unwrap().expires, expires);
+    assert_eq!(
+        try_command::<Dmv>(&state, "renew car1"),
+        Err(DmvError::Registration(RegistrationError::TooEarly {
+            window_opens: expires - RENEWAL_WINDOW
+        }))
+    );
+
+    // Renewing early extends the registration from when it would have run out.
+    let renewed = apply_commands::<Dmv>(
+        &wait(&state, REGISTRATION_DAYS - RENEWAL_WINDOW),
+        &["renew car1"],
+    );
+    assert_eq!(
+        renewed.vehicle("car1").unwrap().expires,
+        expires + REGISTRATION_DAYS
+    );
+
+    let lapsed = wait(&state, REGISTRATION_DAYS);
+    assert!(lapsed.vehicle("car1").unwrap().lapsed);
+    assert_eq!(
+        try_command::<Dmv>(&lapsed, "transfer car1 bob"),
+        Err(DmvError::Registration(RegistrationError::Lapsed))
+    );
+    // Renewing late runs a full year from the renewal.
+    let late = apply_commands::<Dmv>(&wait(&lapsed, 10), &["renew car1"]);
+    assert_eq!(
+        late.vehicle("car1").unwrap(),
+        &Vehicle {
+            owner: "alice".into(),
+            expires: late.today() + REGISTRATION_DAYS,
+            lapsed: false
+        }
+    );
+}
+
+#[test]
+fn dmv_transfers() {
+    let state = apply_commands::<Dmv>(&office(), &["register car1 alice"]);
+    assert_eq!(
+        try_command::<Dmv>(&state, "register car1 bob"),
+        Err(DmvError::PlateExists)
+    );
+    assert_eq!(
+        try_command::<Dmv>(&state, "register car2 carol"),
+        Err(DmvError::NoSuchDriver("carol".into()))
+    );
+    assert_eq!(
+        try_command::<Dmv>(&state, "transfer car1 carol"),
+        Err(DmvError::NoSuchDriver("carol".into()))
+    );
+    assert_eq!(
+        try_command::<Dmv>(&state, "transfer car2 bob"),
+        Err(DmvError::NoSuchVehicle("car2".into()))
+    );
+    assert_eq!(
+        try_command::<Dmv>(&state, "transfer car1 alice"),
+        Err(DmvError::Registration(RegistrationError::SameOwner))
+    );
+
+    // Learners can own vehicles, even though they cannot drive them alone.
+    let state = apply_commands::<Dmv>(&state, &["transfer car1 bob"]);
+    assert_eq!(state.vehicles_owned_by("bob"), vec!["car1"]);
+    assert!(state.vehicles_owned_by("alice").is_empty());
+}
@@ -0,0 +1,740 @@
+//! A game of nerve. Players pay into a prize, and once the game starts a sponsor makes the prize
+//! grow with every tick. Any player can stop the game at any time. Whoever stops it takes a large
+//! share of the prize for themselves and the rest is split among everyone else in proportion to
+//! what they paid in. If nobody stops the game before the deadline, the whole prize is split in
+//! proportion to what everyone paid in.
+//!
+//! Waiting makes the prize bigger, but every tick gives somebody else the chance to stop first.
+//! The simulator at the bottom of this module plays bots with different strategies against each
+//! other so we can see how that tension plays out. Since the game has a deadline, stopping one tick
+//! before your opponent always pays off. That argument repeats all the way back to the start, so
+//! the only equilibrium is to stop immediately.
+
+#[cfg(test)]
+use crate::p1_state_machine::repl::{apply_commands, try_command};
+use crate::p1_state_machine::repl::{parse_number, ReplMachine};
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::collections::BTreeMap;
+
+/// The growing prize game
+pub struct GrowingPrize;
+
+/// Identifies a player
+pub type AccountId = String;
+
+/// The rules a game is played by
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Rules {
+    /// How much the prize grows each tick, as a percentage of its size, rounded down
+    growth_pct: u64,
+    /// The share of the prize taken by whoever stops the game, as a percentage
+    stopper_pct: u64,
+    /// The tick at which the game ends if nobody has stopped it
+    deadline: u64,
+}
+
+impl Rules {
+    /// Rules for a game, as long as the stopper's share is no more than the whole prize.
+    pub fn new(growth_pct: u64, stopper_pct: u64, deadline: u64) -> Result<Self, PrizeError> {
+        if stopper_pct > 100 {
+            return Err(PrizeError::StopperShareTooLarge);
+        }
+        Ok(Self {
+            growth_pct,
+            stopper_pct,
+            deadline,
+        })
+    }
+
+    /// How much the prize grows each tick, as a percentage of its size
+    pub fn growth_pct(&self) -> u64 {
+        self.growth_pct
+    }
+
+    /// The share of the prize taken by whoever stops the game, as a percentage
+    pub fn stopper_pct(&self) -> u64 {
+        self.stopper_pct
+    }
+
+    /// The tick at which the game ends if nobody has stopped it
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            growth_pct: 10,
+            stopper_pct: 60,
+            deadline: 20,
+        }
+    }
+}
+
+/// How far the game has got
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Phase {
+    /// Players are joining and paying in
+    #[default]
+    Open,
+    /// The prize is growing and anybody may stop the game
+    Running,
+    /// The prize has been paid out, to a stopper or because the deadline passed
+    Finished { stopper: Option<AccountId> },
+}
+
+/// The state of the game
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Game {
+    rules: Rules,
+    phase: Phase,
+    /// How many ticks the game has been running for
+    tick: u64,
+    balances: BTreeMap<AccountId, u64>,
+    /// What each player paid in
+    stakes: BTreeMap<AccountId, u64>,
+    prize: u64,
+    /// What the sponsor has left to grow the prize with
+    reserve: u64,
+}
+
+impl Game {
+    /// A game with the given rules, sponsor reserve, and balances, that nobody has joined yet.
+    pub fn new(rules: Rules, reserve: u64, balances: &[(&str, u64)]) -> Self {
+        Self {
+            rules,
+            reserve,
+            balances: balances
+                .iter()
+                .map(|(account, amount)| (account.to_string(), *amount))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The rules the game is played by.
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// How far the game has got.
+    pub fn phase(&self) -> &Phase {
+        &self.phase
+    }
+
+    /// How many ticks the game has been running for.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The size of the prize.
+    pub fn prize(&self) -> u64 {
+        self.prize
+    }
+
+    /// What the sponsor has left to grow the prize with.
+    pub fn reserve(&self) -> u64 {
+        self.reserve
+    }
+
+    /// The balance of an account.
+    pub fn balance(&self, account: &str) -> u64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    /// What a player paid into the prize, or zero if they never joined.
+    pub fn stake(&self, account: &str) -> u64 {
+        self.stakes.get(account).copied().unwrap_or(0)
+    }
+
+    /// Every player who has joined, in order.
+    pub fn players(&self) -> impl Iterator<Item = &AccountId> {
+        self.stakes.keys()
+    }
+
+    /// All tokens in the game. This never changes.
+    pub fn total_tokens(&self) -> u64 {
+        self.balances.values().sum::<u64>() + self.prize + self.reserve
+    }
+
+    /// Take tokens from an account.
+    fn withdraw(&mut self, account: &str, amount: u64) -> Result<(), PrizeError> {
+        let balance = self.balance(account);
+        if balance < amount {
+            return Err(PrizeError::InsufficientBalance {
+                needed: amount,
+                available: balance,
+            });
+        }
+        self.balances.insert(account.to_string(), balance - amount);
+        Ok(())
+    }
+
+    /// Give tokens to an account.
+    fn deposit(&mut self, account: &str, amount: u64) {
+        *self.balances.entry(account.to_string()).or_insert(0) += amount;
+    }
+
+    /// Pay out the prize, taking the stopper's share first. The rest is shared among the other
+    /// players by stake, rounding down. Whatever is left after rounding goes to the stopper, or
+    /// back to the sponsor if nobody stopped the game.
+    fn pay_out(&mut self, stopper: Option<&AccountId>) {
+        let mut prize = std::mem::take(&mut self.prize);
+        let others: Vec<(AccountId, u64)> = self
+            .stakes
+            .iter()
+            .filter(|(player, _)| Some(*player) != stopper)
+            .map(|(player, stake)| (player.clone(), *stake))
+            .collect();
+        let total_stake: u64 = others.iter().map(|(_, stake)| stake).sum();
+
+        let cut = match stopper {
+            Some(_) if total_stake == 0 => 0,
+            Some(_) => (prize as u128 * self.rules.stopper_pct as u128 / 100) as u64,
+            None => 0,
+        };
+        let shared = prize - cut;
+        for (player, stake) in others {
+            let share = (shared as u128 * stake as u128 / total_stake.max(1) as u128) as u64;
+            self.deposit(&player, share);
+            prize -= share;
+        }
+        match stopper {
+            Some(stopper) => self.deposit(stopper, prize),
+            None => self.reserve += prize,
+        }
+        self.phase = Phase::Finished {
+            stopper: stopper.cloned(),
+        };
+    }
+}
+
+/// Things that happen in the game
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PrizeAction {
+    /// Join the game, paying into the prize.
+    Join { account: AccountId, amount: u64 },
+    /// Pay more into the prize before the game starts.
+    Deposit { account: AccountId, amount: u64 },
+    /// Time passes. The first tick starts the game, and every later one grows the prize.
+    Tick,
+    /// Stop the game and take the stopper's share of the prize.
+    Stop { account: AccountId },
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PrizeError {
+    /// Players can only join or pay in before the game starts
+    AlreadyStarted,
+    /// The game is not running yet
+    NotStarted,
+    /// The prize has already been paid out
+    Finished,
+    /// This account has already joined
+    AlreadyJoined,
+    /// Only players can do this
+    NotPlayer,
+    /// A game needs at least two players to start
+    NotEnoughPlayers,
+    /// Nothing was paid in
+    ZeroAmount,
+    /// The account does not have enough tokens
+    InsufficientBalance { needed: u64, available: u64 },
+    /// The stopper can't take more than the whole prize
+    StopperShareTooLarge,
+}
+
+impl TryStateMachine for GrowingPrize {
+    type State = Game;
+    type Transition = PrizeAction;
+    type Error = PrizeError;
+
+    fn try_next_state(starting_state: &Game, t: &PrizeAction) -> Result<Game, PrizeError> {
+        let mut state = starting_state.clone();
+        match &state.phase {
+            Phase::Finished { .. } => return Err(PrizeError::Finished),
+            Phase::Running
+                if matches!(t, PrizeAction::Join { .. } | PrizeAction::Deposit { .. }) =>
+            {
+                return Err(PrizeError::AlreadyStarted)
+            }
+            Phase::Open if matches!(t, PrizeAction::Stop { .. }) => {
+                return Err(PrizeError::NotStarted)
+            }
+            _ => {}
+        }
+
+        match t {
+            PrizeAction::Join { account, amount } | PrizeAction::Deposit { account, amount } => {
+                let joined = state.stakes.contains_key(account);
+                if matches!(t, PrizeAction::Join { .. }) && joined {
+                    return Err(PrizeError::AlreadyJoined);
+                }
+                if matches!(t, PrizeAction::Deposit { .. }) && !joined {
+                    return Err(PrizeError::NotPlayer);
+                }
+                if *amount == 0 {
+                    return Err(PrizeError::ZeroAmount);
+                }
+                state.withdraw(account, *amount)?;
+                *state.stakes.entry(account.clone()).or_insert(0) += amount;
+                state.prize += amount;
+            }
+            PrizeAction::Tick if state.phase == Phase::Open => {
+                if state.stakes.len() < 2 {
+                    return Err(PrizeError::NotEnoughPlayers);
+                }
+                state.phase = Phase::Running;
+            }
+            PrizeAction::Tick => {
+                let growth = (state.prize as u128 * state.rules.growth_pct as u128 / 100) as u64;
+                let growth = growth.min(state.reserve);
+                state.reserve -= growth;
+                state.prize += growth;
+                state.tick += 1;
+                if state.tick >= state.rules.deadline {
+                    state.pay_out(None);
+                }
+            }
+            PrizeAction::Stop { account } => {
+                if !state.stakes.contains_key(account) {
+                    return Err(PrizeError::NotPlayer);
+                }
+                state.pay_out(Some(account));
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Growing Prize".into()
+    }
+}
+
+impl RejectsInPlace for GrowingPrize {}
+
+impl ReplMachine for GrowingPrize {
+    fn initial_state() -> Game {
+        Game::new(
+            Rules::default(),
+            10_000,
+            &[("alice", 1_000), ("bob", 1_000), ("carol", 1_000)],
+        )
+    }
+
+    fn parse_transition(input: &str) -> Result<PrizeAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let account = || -> Result<AccountId, String> {
+            Ok(words.get(1).ok_or("missing account")?.to_string())
+        };
+        match words.first() {
+            Some(&"join") => Ok(PrizeAction::Join {
+                account: account()?,
+                amount: parse_number(&words, 2, "amount")?,
+            }),
+            Some(&"deposit") => Ok(PrizeAction::Deposit {
+                account: account()?,
+                amount: parse_number(&words, 2, "amount")?,
+            }),
+            Some(&"tick") => Ok(PrizeAction::Tick),
+            Some(&"stop") => Ok(PrizeAction::Stop {
+                account: account()?,
+            }),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "join <who> <amount>     join the game, paying into the prize",
+            "deposit <who> <amount>  pay more in before the game starts",
+            "tick                    start the game, or grow the prize",
+            "stop <who>              stop the game and take most of the prize",
+        ]
+    }
+}
+
+/// A way of deciding when to stop the game
+pub trait Strategy {
+    /// Whether to stop the game now. Called once every tick while the game is running.
+    fn stop(&mut self, game: &Game, me: &str, rng: &mut StdRng) -> bool;
+}
+
+/// Stop as soon as the game has been running for this many ticks
+pub struct StopAtTick(pub u64);
+
+impl Strategy for StopAtTick {
+    fn stop(&mut self, game: &Game, _: &str, _: &mut StdRng) -> bool {
+        game.tick() >= self.0
+    }
+}
+
+/// Stop as soon as the prize is at least this big
+pub struct StopAtPrize(pub u64);
+
+impl Strategy for StopAtPrize {
+    fn stop(&mut self, game: &Game, _: &str, _: &mut StdRng) -> bool {
+        game.prize() >= self.0
+    }
+}
+
+/// Never stop, and wait for the deadline or for someone else to stop
+pub struct NeverStop;
+
+impl Strategy for NeverStop {
+    fn stop(&mut self, _: &Game, _: &str, _: &mut StdRng) -> bool {
+        false
+    }
+}
+
+/// Stop at random, with the given percentage chance each tick
+pub struct StopAtRandom(pub u32);
+
+impl Strategy for StopAtRandom {
+    fn stop(&mut self, _: &Game, _: &str, rng: &mut StdRng) -> bool {
+        rng.gen_ratio(self.0.min(100), 100)
+    }
+}
+
+/// A player controlled by a strategy
+pub struct Bot {
+    pub account: AccountId,
+    pub strategy: Box<dyn Strategy>,
+}
+
+impl Bot {
+    pub fn new(account: &str, strategy: impl Strategy + 'static) -> Self {
+        Self {
+            account: account.into(),
+            strategy: Box::new(strategy),
+        }
+    }
+}
+
+/// Play a game that has already started through to the end. Every tick each bot in turn gets the
+/// chance to stop, in a random order since players race to get their stop in first.
+///
+/// Every bot must be a player, since nobody else may stop the game.
+pub fn simulate(start: &Game, bots: &mut [Bot], rng: &mut StdRng) -> Result<Game, PrizeError> {
+    if bots
+        .iter()
+        .any(|bot| !start.stakes.contains_key(&bot.account))
+    {
+        return Err(PrizeError::NotPlayer);
+    }
+    let mut game = start.clone();
+    while game.phase == Phase::Running {
+        bots.shuffle(rng);
+        let mut action = PrizeAction::Tick;
+        for bot in bots.iter_mut() {
+            if bot.strategy.stop(&game, &bot.account, rng) {
+                action = PrizeAction::Stop {
+                    account: bot.account.clone(),
+                };
+                break;
+            }
+        }
+        game = GrowingPrize::try_next_state(&game, &action)?;
+    }
+    Ok(game)
+}
+
+/// The average profit of each bot over many games. Each bot joins with the given stake and the
+/// bots are made afresh for every game, so strategies with memory start over each time.
+///
+/// Fails if the bots can't all join, for example because two of them share an account.
+pub fn average_profits(
+    rules: Rules,
+    stake: u64,
+    make_bots: impl Fn() -> Vec<Bot>,
+    games: u64,
+    seed: u64,
+) -> Result<BTreeMap<AccountId, i64>, PrizeError> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut totals: BTreeMap<AccountId, i64> = BTreeMap::new();
+    for _ in 0..games {
+        let mut bots = make_bots();
+        let balances: Vec<(&str, u64)> = bots.iter().map(|b| (b.account.as_str(), stake)).collect();
+        let mut game = Game::new(rules, u64::MAX / 2, &balances);
+        for bot in &bots {
+            game = GrowingPrize::try_next_state(
+                &game,
+                &PrizeAction::Join {
+                    account: bot.account.clone(),
+                    amount: stake,
+                },
+            )?;
+        }
+        let game = GrowingPrize::try_next_state(&game, &PrizeAction::Tick)?;
+        let end = simulate(&game, &mut bots, &mut rng)?;
+        for bot in &bots {
+            *totals.entry(bot.account.clone()).or_insert(0) +=
+                end.balance(&bot.account) as i64 - stake as i64;
+        }
+    }
+    Ok(totals
+        .into_iter()
+        .map(|(account, total)| (account, total / games as i64))
+        .collect())
+}
+
+/// Alice pays in 100 and bob 300, then the game starts.
+#[cfg(test)]
+fn started() -> Game {
+    apply_commands::<GrowingPrize>(
+        &GrowingPrize::initial_state(),
+        &["join alice 100", "join bob 200", "deposit bob 100", "tick"],
+    )
+}
+
+#[test]
+fn growing_prize_joining() {
+    let state = started();
+    assert_eq!(state.phase(), &Phase::Running);
+    assert_eq!(state.prize(), 400);
+    assert_eq!(state.stake("bob"), 300);
+    assert_eq!(state.balance("alice"), 900);
+
+    let open = GrowingPrize::initial_state();
+    assert_eq!(
+        try_command::<GrowingPrize>(&open, "deposit alice 5"),
+        Err(PrizeError::NotPlayer)
+    );
+    assert_eq!(
+        try_command::<GrowingPrize>(&open, "join alice 0"),
+        Err(PrizeError::ZeroAmount)
+    );
+    assert_eq!(
+        try_command::<GrowingPrize>(&open, "join alice 1001"),
+        Err(PrizeError::InsufficientBalance {
+            needed: 1001,
+            available: 1000
+        })
+    );
+    let open = apply_commands::<GrowingPrize>(&open, &["join alice 5"]);
+    assert_eq!(
+        try_command::<GrowingPrize>(&open, "join alice 5"),
+        Err(PrizeError::AlreadyJoined)
+    );
+    assert_eq!(
+        try_command::<GrowingPrize>(&open, "tick"),
+        Err(PrizeError::NotEnoughPlayers)
+    );
+    assert_eq!(
+        try_command::<GrowingPrize>(&open, "stop alice"),
+        Err(PrizeError::NotStarted)
+    );
+    assert_eq!(
+        try_command::<GrowingPrize>(&state, "stop carol"),
+        Err(PrizeError::NotPlayer)
+    );
+}
+
+#[test]
+fn growing_prize_grows_each_tick() {
+    let state = apply_commands::<GrowingPrize>(&started(), &["tick", "tick"]);
+    assert_eq!(state.tick(), 2);
+    assert_eq!(state.prize(), 484);
+    assert_eq!(state.reserve(), 10_000 - 84);
+    assert_eq!(
+        try_command::<GrowingPrize>(&state, "join carol 5"),
+        Err(PrizeError::AlreadyStarted)
+    );
+}
+
+#[test]
+fn growing_prize_growth_limited_by_reserve() {
+    let mut state = Game::new(Rules::default(), 15, &[("alice", 100), ("bob", 100)]);
+    state = apply_commands::<GrowingPrize>(
+        &state,
+        &["join alice 100", "join bob 100", "tick", "tick", "tick"],
+    );
+    assert_eq!(state.prize(), 215);
+    assert_eq!(state.reserve(), 0);
+}
+
+#[test]
+fn growing_prize_stopper_takes_their_share() {
+    let state = apply_commands::<GrowingPrize>(&started(), &["tick", "stop alice"]);
+    assert_eq!(
+        state.phase(),
+        &Phase::Finished {
+            stopper: Some("alice".into())
+        }
+    );
+    // The prize of 440 splits into 264 for alice and 176 for bob.
+    assert_eq!(state.balance("alice"), 900 + 264);
+    assert_eq!(state.balance("bob"), 700 + 176);
+    assert_eq!(state.prize(), 0);
+    assert_eq!(
+        try_command::<GrowingPrize>(&state, "tick"),
+        Err(PrizeError::Finished)
+    );
+    assert_eq!(
+        try_command::<GrowingPrize>(&state, "stop bob"),
+        Err(PrizeError::Finished)
+    );
+}
+
+#[test]
+fn growing_prize_others_split_by_stake() {
+    let state = apply_commands::<GrowingPrize>(
+        &GrowingPrize::initial_state(),
+        &[
+            "join alice 10",
+            "join bob 10",
+            "join carol 20",
+            "tick",
+            "stop carol",
+        ],
+    );
+    // Carol takes 24 of the 40, alice and bob take 8 each
+    assert_eq!(state.balance("carol"), 980 + 24);
+    assert_eq!(state.balance("alice"), 990 + 8);
+    assert_eq!(state.balance("bob"), 990 + 8);
+}
+
+#[test]
+fn growing_prize_rounding_dust_goes_to_stopper() {
+    let state = apply_commands::<GrowingPrize>(
+        &GrowingPrize::initial_state(),
+        &[
+            "join alice 2",
+            "join bob 3",
+            "join carol 2",
+            "tick",
+            "stop alice",
+        ],
+    );
+    // Alice takes 4 of the 7, bob and carol take 1 each, and alice keeps the odd one left over.
+    assert_eq!(state.balance("alice"), 998 + 4 + 1);
+    assert_eq!(state.balance("bob"), 997 + 1);
+    assert_eq!(state.balance("carol"), 998 + 1);
+}
+
+#[test]
+fn growing_prize_deadline_splits_everything() {
+    let rules = Rules::new(10, 60, 2).unwrap();
+    let state = Game::new(rules, 1000, &[("alice", 100), ("bob", 100), ("carol", 100)]);
+    let state = apply_commands::<GrowingPrize>(
+        &state,
+        &[
+            "join alice 10",
+            "join bob 10",
+            "join carol 13",
+            "tick",
+            "tick",
+            "tick",
+        ],
+    );
+    assert_eq!(state.phase(), &Phase::Finished { stopper: None });
+    // A prize of 39 is split 11, 11, 15, and the leftover 2 goes back to the sponsor.
+    assert_eq!(state.balance("alice"), 101);
+    assert_eq!(state.balance("bob"), 101);
+    assert_eq!(state.balance("carol"), 102);
+    assert_eq!(state.reserve(), 1000 - 6 + 2);
+}
+
+#[test]
+fn growing_prize_simulated_games_conserve_tokens() {
+    let mut rng = StdRng::seed_from_u64(20);
+    let start = started();
+    for _ in 0..50 {
+        let mut bots = vec![
+            Bot::new("alice", StopAtRandom(10)),
+            Bot::new("bob", StopAtPrize(600)),
+        ];
+        let end = simulate(&start, &mut bots, &mut rng).unwrap();
+        assert!(matches!(end.phase(), Phase::Finished { .. }));
+        assert_eq!(end.total_tokens(), start.total_tokens());
+    }
+}
+
+#[test]
+fn growing_prize_patience_is_exploited() {
+    let profits = average_profits(
+        Rules::default(),
+        100,
+        || vec![Bot::new("alice", StopAtTick(5)), Bot::new("bob", NeverStop)],
+        10,
+        20,
+    )
+    .unwrap();
+    // The prize has grown to 321 by the time alice stops, every time.
+    assert_eq!(profits["alice"], 192 - 100);
+    assert_eq!(profits["bob"], 129 - 100);
+}
+
+#[test]
+fn growing_prize_best_response_unravels_to_stopping_at_once() {
+    let rules = Rules::new(10, 60, 8).unwrap();
+    // The tick at which it pays best to stop, when the opponent stops at the given tick
+    let best_response = |theirs: u64| {
+        (0..=rules.deadline())
+            .max_by_key(|&mine| {
+                let profits = average_profits(
+                    rules,
+                    1_000,
+                    || {
+                        vec![
+                            Bot::new("me", StopAtTick(mine)),
+                            Bot::new("them", StopAtTick(theirs)),
+                        ]
+                    },
+                    50,
+                    mine,
+                )
+                .unwrap();
+                // Prefer the earlier tick when two pay the same
+                (profits["me"], std::cmp::Reverse(mine))
+            })
+            .unwrap()
+    };
+
+    assert_eq!(best_response(5), 4);
+    let mut tick = rules.deadline();
+    for _ in 0..rules.deadline() {
+        tick = best_response(tick);
+    }
+    assert_eq!(tick, 0);
+}
+
+#[test]
+fn growing_prize_rejects_bad_rules() {
+    assert_eq!(
+        Rules::new(10, 101, 20),
+        Err(PrizeError::StopperShareTooLarge)
+    );
+    // Taking the whole prize is allowed, and leaves nothing for anybody else.
+    let rules = Rules::new(10, 100, 20).unwrap();
+    let state = Game::new(rules, 1000, &[("alice", 100), ("bob", 100)]);
+    let state = apply_commands::<GrowingPrize>(
+        &state,
+        &["join alice 10", "join bob 30", "tick", "stop alice"],
+    );
+    assert_eq!(state.balance("alice"), 90 + 40);
+    assert_eq!(state.balance("bob"), 70);
+}
+
+#[test]
+fn growing_prize_simulation_rejects_outsiders() {
+    let mut rng = StdRng::seed_from_u64(20);
+    let mut bots = vec![Bot::new("alice", NeverStop), Bot::new("zed", StopAtTick(0))];
+    assert_eq!(
+        simulate(&started(), &mut bots, &mut rng),
+        Err(PrizeError::NotPlayer)
+    );
+    assert_eq!(
+        average_profits(
+            Rules::default(),
+            100,
+            || vec![Bot::new("alice", NeverStop), Bot::new("alice", NeverStop)],
+            10,
+            20,
+        ),
+        Err(PrizeError::AlreadyJoined)
+    );
+}
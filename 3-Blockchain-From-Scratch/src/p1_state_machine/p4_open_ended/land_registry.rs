@@ -0,0 +1,979 @@
+//! A land registry, the kind of bureaucracy that keeps track of who owns which piece of land.
+//!
+//! Land is divided into parcels, each a rectangle on a grid, and no two parcels may overlap.
+//! Unclaimed land can be registered by anyone. A sale needs both parties: the owner offers the
+//! parcel to a buyer at a price, and the sale only goes through once the buyer accepts at that
+//! same price. Owners can pledge a parcel as security for a loan by placing a lien on it. While a
+//! parcel has liens it cannot be sold, subdivided, or merged, and only the lien holder can
+//! discharge the lien.
+//!
+//! Parcels can be split in two or merged with a neighbour. Either way the old parcels are
+//! retired and new ones take their place, remembering which parcels they came from. Every change
+//! is recorded, so the full history of any parcel can be traced back through its predecessors.
+//!
+//! The registry only records sales. Paying for them is left to some other machine.
+
+#[cfg(test)]
+use crate::p1_state_machine::repl::{apply_commands, try_command};
+use crate::p1_state_machine::repl::{parse_number, ReplMachine};
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A land registry
+pub struct LandRegistry;
+
+/// Identifies a person who can own land or hold a lien
+pub type AccountId = String;
+
+/// Identifies a parcel. Retired parcels keep their id and it is never reused.
+pub type ParcelId = u64;
+
+/// Identifies a lien
+pub type LienId = u64;
+
+/// The edges of a parcel on the grid. The west and south edges are inside the parcel while the
+/// east and north edges are just outside it, so neighbouring parcels share edge coordinates.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Boundary {
+    pub west: u64,
+    pub south: u64,
+    pub east: u64,
+    pub north: u64,
+}
+
+impl Boundary {
+    /// The area enclosed by the boundary, or `None` if it is too large to count.
+    pub fn area(&self) -> Option<u64> {
+        self.east
+            .saturating_sub(self.west)
+            .checked_mul(self.north.saturating_sub(self.south))
+    }
+
+    /// Whether two boundaries enclose any land in common.
+    pub fn overlaps(&self, other: &Boundary) -> bool {
+        self.west < other.east
+            && other.west < self.east
+            && self.south < other.north
+            && other.south < self.north
+    }
+
+    /// The single rectangle covering both boundaries, if they share a whole edge.
+    pub fn union(&self, other: &Boundary) -> Option<Boundary> {
+        let (a, b) = (self, other);
+        let same_rows = a.south == b.south && a.north == b.north;
+        let same_columns = a.west == b.west && a.east == b.east;
+        if same_rows && (a.east == b.west || b.east == a.west) {
+            Some(Boundary {
+                west: a.west.min(b.west),
+                east: a.east.max(b.east),
+                ..*a
+            })
+        } else if same_columns && (a.north == b.south || b.north == a.south) {
+            Some(Boundary {
+                south: a.south.min(b.south),
+                north: a.north.max(b.north),
+                ..*a
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The two halves of the boundary either side of a split line, if the line crosses it.
+    pub fn split(&self, split: Split) -> Option<(Boundary, Boundary)> {
+        match split {
+            Split::AtX(x) if self.west < x && x < self.east => {
+                Some((Boundary { east: x, ..*self }, Boundary { west: x, ..*self }))
+            }
+            Split::AtY(y) if self.south < y && y < self.north => Some((
+                Boundary { north: y, ..*self },
+                Boundary { south: y, ..*self },
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// A line to split a parcel along
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Split {
+    /// A north-south line, giving a western and an eastern parcel
+    AtX(u64),
+    /// An east-west line, giving a southern and a northern parcel
+    AtY(u64),
+}
+
+/// Money owed to a lien holder, secured against a parcel
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Lien {
+    pub holder: AccountId,
+    pub amount: u64,
+}
+
+/// An owner's offer to sell a parcel
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Offer {
+    pub buyer: AccountId,
+    pub price: u64,
+}
+
+/// A parcel of land that is currently registered
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Parcel {
+    pub owner: AccountId,
+    pub boundary: Boundary,
+    pub liens: BTreeMap<LienId, Lien>,
+    pub offer: Option<Offer>,
+}
+
+/// One entry in the registry's audit log
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Record {
+    Registered {
+        parcel: ParcelId,
+        owner: AccountId,
+        boundary: Boundary,
+    },
+    Offered {
+        parcel: ParcelId,
+        buyer: AccountId,
+        price: u64,
+    },
+    OfferWithdrawn {
+        parcel: ParcelId,
+    },
+    Transferred {
+        parcel: ParcelId,
+        from: AccountId,
+        to: AccountId,
+        price: u64,
+    },
+    LienPlaced {
+        parcel: ParcelId,
+        lien: LienId,
+        holder: AccountId,
+        amount: u64,
+    },
+    LienDischarged {
+        parcel: ParcelId,
+        lien: LienId,
+    },
+    Subdivided {
+        parcel: ParcelId,
+        into: [ParcelId; 2],
+    },
+    Merged {
+        parcels: [ParcelId; 2],
+        into: ParcelId,
+    },
+}
+
+impl Record {
+    /// Every parcel this record is about.
+    pub fn parcels(&self) -> Vec<ParcelId> {
+        match self {
+            Record::Registered { parcel, .. }
+            | Record::Offered { parcel, .. }
+            | Record::OfferWithdrawn { parcel }
+            | Record::Transferred { parcel, .. }
+            | Record::LienPlaced { parcel, .. }
+            | Record::LienDischarged { parcel, .. } => vec![*parcel],
+            Record::Subdivided { parcel, into } => vec![*parcel, into[0], into[1]],
+            Record::Merged { parcels, into } => vec![parcels[0], parcels[1], *into],
+        }
+    }
+}
+
+/// The state of the land registry
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Cadastre {
+    /// Every parcel currently registered
+    parcels: BTreeMap<ParcelId, Parcel>,
+    /// The parcels each parcel, retired or not, was made from
+    predecessors: BTreeMap<ParcelId, Vec<ParcelId>>,
+    /// Everything that has ever happened, oldest first
+    log: Vec<Record>,
+    next_parcel: ParcelId,
+    next_lien: LienId,
+}
+
+impl Cadastre {
+    /// A registered parcel, or `None` if it never existed or has been retired.
+    pub fn parcel(&self, id: ParcelId) -> Option<&Parcel> {
+        self.parcels.get(&id)
+    }
+
+    /// Every parcel currently registered.
+    pub fn parcels(&self) -> impl Iterator<Item = (ParcelId, &Parcel)> {
+        self.parcels.iter().map(|(id, parcel)| (*id, parcel))
+    }
+
+    /// The parcels currently owned by someone.
+    pub fn owned_by(&self, owner: &str) -> Vec<ParcelId> {
+        self.parcels()
+            .filter(|(_, parcel)| parcel.owner == owner)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// The total area of all registered land. Subdividing and merging never change it, and
+    /// registration refuses land that would make it too large to count, so it is only `None`
+    /// for a cadastre that was not built by the registry.
+    pub fn total_area(&self) -> Option<u64> {
+        self.parcels
+            .values()
+            .try_fold(0u64, |total, p| total.checked_add(p.boundary.area()?))
+    }
+
+    /// Everything that has ever happened in the registry, oldest first.
+    pub fn log(&self) -> &[Record] {
+        &self.log
+    }
+
+    /// The full history of a parcel, oldest first. This includes the history of every parcel it
+    /// was subdivided or merged from, all the way back to their registration.
+    pub fn history(&self, id: ParcelId) -> Vec<&Record> {
+        let mut lineage = BTreeSet::new();
+        let mut pending = vec![id];
+        while let Some(parcel) = pending.pop() {
+            if lineage.insert(parcel) {
+                pending.extend(self.predecessors.get(&parcel).into_iter().flatten());
+            }
+        }
+        self.log
+            .iter()
+            .filter(|record| {
+                // A subdivision belongs to the history of both halves, but the other half's own
+                // later history does not.
+                record.parcels().iter().any(|p| lineage.contains(p))
+                    && match record {
+                        Record::Subdivided { parcel, .. } => lineage.contains(parcel),
+                        _ => true,
+                    }
+            })
+            .collect()
+    }
+
+    /// Add a new parcel made from some predecessors.
+    fn create(&mut self, owner: AccountId, boundary: Boundary, from: Vec<ParcelId>) -> ParcelId {
+        let id = self.next_parcel;
+        self.next_parcel += 1;
+        self.parcels.insert(
+            id,
+            Parcel {
+                owner,
+                boundary,
+                liens: BTreeMap::new(),
+                offer: None,
+            },
+        );
+        self.predecessors.insert(id, from);
+        id
+    }
+
+    /// A parcel the account owns, which it may therefore change.
+    fn owned(&mut self, account: &str, id: ParcelId) -> Result<&mut Parcel, LandError> {
+        let parcel = self.parcels.get_mut(&id).ok_or(LandError::NoSuchParcel)?;
+        if parcel.owner != account {
+            return Err(LandError::NotOwner);
+        }
+        Ok(parcel)
+    }
+
+    /// A parcel the account owns that is free to be reshaped.
+    fn reshapeable(&mut self, account: &str, id: ParcelId) -> Result<Parcel, LandError> {
+        let parcel = self.owned(account, id)?;
+        if !parcel.liens.is_empty() {
+            return Err(LandError::Encumbered);
+        }
+        if parcel.offer.is_some() {
+            return Err(LandError::OfferPending);
+        }
+        Ok(parcel.clone())
+    }
+}
+
+/// Things people do at the land registry
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum LandAction {
+    /// Claim unregistered land.
+    Register {
+        account: AccountId,
+        boundary: Boundary,
+    },
+    /// Offer to sell your parcel to a buyer, replacing any earlier offer.
+    Offer {
+        account: AccountId,
+        parcel: ParcelId,
+        buyer: AccountId,
+        price: u64,
+    },
+    /// Withdraw your offer to sell.
+    WithdrawOffer {
+        account: AccountId,
+        parcel: ParcelId,
+    },
+    /// Accept an offer made to you, completing the sale.
+    Accept {
+        account: AccountId,
+        parcel: ParcelId,
+        price: u64,
+    },
+    /// Pledge your parcel as security for money owed to the holder.
+    PlaceLien {
+        account: AccountId,
+        parcel: ParcelId,
+        holder: AccountId,
+        amount: u64,
+    },
+    /// Release a lien you hold, once the money has been repaid.
+    Discharge {
+        account: AccountId,
+        parcel: ParcelId,
+        lien: LienId,
+    },
+    /// Split your parcel in two.
+    Subdivide {
+        account: AccountId,
+        parcel: ParcelId,
+        split: Split,
+    },
+    /// Join two of your neighbouring parcels into one.
+    Merge {
+        account: AccountId,
+        parcels: [ParcelId; 2],
+    },
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LandError {
+    /// The boundary encloses no land
+    EmptyBoundary,
+    /// The boundary encloses so much land that the registry's total could not be counted
+    TooLarge,
+    /// The land is already part of this parcel
+    Overlaps(ParcelId),
+    /// There is no registered parcel with this id
+    NoSuchParcel,
+    /// Only the owner may do this
+    NotOwner,
+    /// Owners cannot sell to themselves
+    SelfSale,
+    /// The parcel has liens that must be discharged first
+    Encumbered,
+    /// The parcel has an offer that must be withdrawn or accepted first
+    OfferPending,
+    /// The parcel has not been offered for sale
+    NoOffer,
+    /// The parcel was offered to somebody else
+    NotBuyer,
+    /// The price accepted is not the price offered
+    PriceMismatch { offered: u64 },
+    /// There is no such lien on the parcel
+    NoSuchLien,
+    /// Only the lien holder may discharge a lien
+    NotHolder,
+    /// The split line does not cross the parcel
+    BadSplit,
+    /// Only parcels sharing a whole edge can be merged
+    NotAdjacent,
+}
+
+impl TryStateMachine for LandRegistry {
+    type State = Cadastre;
+    type Transition = LandAction;
+    type Error = LandError;
+
+    fn try_next_state(starting_state: &Cadastre, t: &LandAction) -> Result<Cadastre, LandError> {
+        let mut state = starting_state.clone();
+        match t {
+            LandAction::Register { account, boundary } => {
+                let area = boundary.area().ok_or(LandError::TooLarge)?;
+                if area == 0 {
+                    return Err(LandError::EmptyBoundary);
+                }
+                if state
+                    .total_area()
+                    .and_then(|total| total.checked_add(area))
+                    .is_none()
+                {
+                    return Err(LandError::TooLarge);
+                }
+                if let Some((id, _)) = state
+                    .parcels()
+                    .find(|(_, parcel)| parcel.boundary.overlaps(boundary))
+                {
+                    return Err(LandError::Overlaps(id));
+                }
+                let parcel = state.create(account.clone(), *boundary, vec![]);
+                state.log.push(Record::Registered {
+                    parcel,
+                    owner: account.clone(),
+                    boundary: *boundary,
+                });
+            }
+            LandAction::Offer {
+                account,
+                parcel,
+                buyer,
+                price,
+            } => {
+                let owned = state.owned(account, *parcel)?;
+                if buyer == account {
+                    return Err(LandError::SelfSale);
+                }
+                if !owned.liens.is_empty() {
+                    return Err(LandError::Encumbered);
+                }
+                owned.offer = Some(Offer {
+                    buyer: buyer.clone(),
+                    price: *price,
+                });
+                state.log.push(Record::Offered {
+                    parcel: *parcel,
+                    buyer: buyer.clone(),
+                    price: *price,
+                });
+            }
+            LandAction::WithdrawOffer { account, parcel } => {
+                state
+                    .owned(account, *parcel)?
+                    .offer
+                    .take()
+                    .ok_or(LandError::NoOffer)?;
+                state.log.push(Record::OfferWithdrawn { parcel: *parcel });
+            }
+            LandAction::Accept {
+                account,
+                parcel,
+                price,
+            } => {
+                let sold = state
+                    .parcels
+                    .get_mut(parcel)
+                    .ok_or(LandError::NoSuchParcel)?;
+                let offer = sold.offer.as_ref().ok_or(LandError::NoOffer)?;
+                if offer.buyer != *account {
+                    return Err(LandError::NotBuyer);
+                }
+                if offer.price != *price {
+                    return Err(LandError::PriceMismatch {
+                        offered: offer.price,
+                    });
+                }
+                // A lien may have been placed after the offer was made.
+                if !sold.liens.is_empty() {
+                    return Err(LandError::Encumbered);
+                }
+                let from = std::mem::replace(&mut sold.owner, account.clone());
+                sold.offer = None;
+                state.log.push(Record::Transferred {
+                    parcel: *parcel,
+                    from,
+                    to: account.clone(),
+                    price: *price,
+                });
+            }
+            LandAction::PlaceLien {
+                account,
+                parcel,
+                holder,
+                amount,
+            } => {
+                let lien = state.next_lien;
+                state.owned(account, *parcel)?.liens.insert(
+                    lien,
+                    Lien {
+                        holder: holder.clone(),
+                        amount: *amount,
+                    },
+                );
+                state.next_lien += 1;
+                state.log.push(Record::LienPlaced {
+                    parcel: *parcel,
+                    lien,
+                    holder: holder.clone(),
+                    amount: *amount,
+                });
+            }
+            LandAction::Discharge {
+                account,
+                parcel,
+                lien,
+            } => {
+                let liens = &mut state
+                    .parcels
+                    .get_mut(parcel)
+                    .ok_or(LandError::NoSuchParcel)?
+                    .liens;
+                if liens.get(lien).ok_or(LandError::NoSuchLien)?.holder != *account {
+                    return Err(LandError::NotHolder);
+                }
+                liens.remove(lien);
+                state.log.push(Record::LienDischarged {
+                    parcel: *parcel,
+                    lien: *lien,
+                });
+            }
+            LandAction::Subdivide {
+                account,
+                parcel,
+                split,
+            } => {
+                let old = state.reshapeable(account, *parcel)?;
+                let (first, second) = old.boundary.split(*split).ok_or(LandError::BadSplit)?;
+                state.parcels.remove(parcel);
+                let into = [
+                    state.create(account.clone(), first, vec![*parcel]),
+                    state.create(account.clone(), second, vec![*parcel]),
+                ];
+                state.log.push(Record::Subdivided {
+                    parcel: *parcel,
+                    into,
+                });
+            }
+            LandAction::Merge { account, parcels } => {
+                let [a, b] = *parcels;
+                if a == b {
+                    return Err(LandError::NotAdjacent);
+                }
+                let first = state.reshapeable(account, a)?;
+                let second = state.reshapeable(account, b)?;
+                let boundary = first
+                    .boundary
+                    .union(&second.boundary)
+                    .ok_or(LandError::NotAdjacent)?;
+                state.parcels.remove(&a);
+                state.parcels.remove(&b);
+                let into = state.create(account.clone(), boundary, vec![a, b]);
+                state.log.push(Record::Merged {
+                    parcels: *parcels,
+                    into,
+                });
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Land Registry".into()
+    }
+}
+
+impl RejectsInPlace for LandRegistry {}
+
+impl ReplMachine for LandRegistry {
+    fn initial_state() -> Cadastre {
+        Cadastre::default()
+    }
+
+    fn parse_transition(input: &str) -> Result<LandAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let account = words.get(1).ok_or("missing account")?.to_string();
+        let name = |index: usize, what: &str| -> Result<String, String> {
+            words
+                .get(index)
+                .map(|w| w.to_string())
+                .ok_or_else(|| format!("missing {}", what))
+        };
+        match words[0] {
+            "register" => Ok(LandAction::Register {
+                account,
+                boundary: Boundary {
+                    west: parse_number(&words, 2, "west edge")?,
+                    south: parse_number(&words, 3, "south edge")?,
+                    east: parse_number(&words, 4, "east edge")?,
+                    north: parse_number(&words, 5, "north edge")?,
+                },
+            }),
+            "offer" => Ok(LandAction::Offer {
+                account,
+                parcel: parse_number(&words, 2, "parcel")?,
+                buyer: name(3, "buyer")?,
+                price: parse_number(&words, 4, "price")?,
+            }),
+            "withdraw" => Ok(LandAction::WithdrawOffer {
+                account,
+                parcel: parse_number(&words, 2, "parcel")?,
+            }),
+            "accept" => Ok(LandAction::Accept {
+                account,
+                parcel: parse_number(&words, 2, "parcel")?,
+                price: parse_number(&words, 3, "price")?,
+            }),
+            "lien" => Ok(LandAction::PlaceLien {
+                account,
+                parcel: parse_number(&words, 2, "parcel")?,
+                holder: name(3, "lien holder")?,
+                amount: parse_number(&words, 4, "amount")?,
+            }),
+            "discharge" => Ok(LandAction::Discharge {
+                account,
+                parcel: parse_number(&words, 2, "parcel")?,
+                lien: parse_number(&words, 3, "lien")?,
+            }),
+            "subdivide" => {
+                let at = parse_number(&words, 4, "split line")?;
+                let split = match words.get(3) {
+                    Some(&"x") => Split::AtX(at),
+                    Some(&"y") => Split::AtY(at),
+                    _ => return Err("split along x or y".into()),
+                };
+                Ok(LandAction::Subdivide {
+                    account,
+                    parcel: parse_number(&words, 2, "parcel")?,
+                    split,
+                })
+            }
+            "merge" => Ok(LandAction::Merge {
+                account,
+                parcels: [
+                    parse_number(&words, 2, "parcel")?,
+                    parse_number(&words, 3, "parcel")?,
+                ],
+            }),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "register <who> <west> <south> <east> <north>  claim unregistered land",
+            "offer <owner> <parcel> <buyer> <price>        offer a parcel for sale",
+            "withdraw <owner> <parcel>                     withdraw an offer",
+            "accept <buyer> <parcel> <price>               buy a parcel offered to you",
+            "lien <owner> <parcel> <holder> <amount>       pledge a parcel as security",
+            "discharge <holder> <parcel> <lien>            release a lien",
+            "subdivide <owner> <parcel> x|y <at>           split a parcel in two",
+            "merge <owner> <parcel> <parcel>               join two neighbouring parcels",
+        ]
+    }
+}
+
+/// Alice owns parcel 0, a 10 by 10 square in the corner, and bob owns parcel 1 north of it.
+#[cfg(test)]
+fn registered() -> Cadastre {
+    apply_commands::<LandRegistry>(
+        &Cadastre::default(),
+        &["register alice 0 0 10 10", "register bob 0 10 10 20"],
+    )
+}
+
+#[test]
+fn land_registry_register() {
+    let state = registered();
+    assert_eq!(state.owned_by("alice"), vec![0]);
+    assert_eq!(state.owned_by("bob"), vec![1]);
+    assert_eq!(state.total_area(), Some(200));
+}
+
+#[test]
+fn land_registry_rejects_overlapping_and_empty_claims() {
+    let state = registered();
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "register carol 5 15 15 25"),
+        Err(LandError::Overlaps(1))
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "register carol 20 0 20 10"),
+        Err(LandError::EmptyBoundary)
+    );
+    assert!(try_command::<LandRegistry>(&state, "register carol 10 0 20 20").is_ok());
+}
+
+#[test]
+fn land_registry_rejects_claims_too_large_to_count() {
+    assert_eq!(
+        try_command::<LandRegistry>(
+            &Cadastre::default(),
+            "register carol 0 0 18446744073709551615 3"
+        ),
+        Err(LandError::TooLarge)
+    );
+    // Each claim fits on its own, but together they would be too much to count.
+    let state = apply_commands::<LandRegistry>(
+        &Cadastre::default(),
+        &["register carol 0 0 18446744073709551615 1"],
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "register dave 0 1 10 2"),
+        Err(LandError::TooLarge)
+    );
+    assert_eq!(state.total_area(), Some(u64::MAX));
+}
+
+#[test]
+fn land_registry_sale_needs_both_parties() {
+    let state = apply_commands::<LandRegistry>(&registered(), &["offer alice 0 carol 500"]);
+    assert_eq!(state.parcel(0).unwrap().owner, "alice");
+
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "accept dave 0 500"),
+        Err(LandError::NotBuyer)
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "accept carol 0 400"),
+        Err(LandError::PriceMismatch { offered: 500 })
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "offer bob 0 carol 1"),
+        Err(LandError::NotOwner)
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "offer alice 0 alice 1"),
+        Err(LandError::SelfSale)
+    );
+
+    let state = apply_commands::<LandRegistry>(&state, &["accept carol 0 500"]);
+    assert_eq!(state.parcel(0).unwrap().owner, "carol");
+    assert_eq!(state.parcel(0).unwrap().offer, None);
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "accept carol 0 500"),
+        Err(LandError::NoOffer)
+    );
+}
+
+#[test]
+fn land_registry_withdrawn_offer_cannot_be_accepted() {
+    let state = apply_commands::<LandRegistry>(
+        &registered(),
+        &["offer alice 0 carol 500", "withdraw alice 0"],
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "accept carol 0 500"),
+        Err(LandError::NoOffer)
+    );
+}
+
+#[test]
+fn land_registry_liens_block_transfers_until_discharged() {
+    let state = apply_commands::<LandRegistry>(&registered(), &["lien alice 0 bank 1000"]);
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "offer alice 0 carol 500"),
+        Err(LandError::Encumbered)
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "subdivide alice 0 x 5"),
+        Err(LandError::Encumbered)
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "discharge alice 0 0"),
+        Err(LandError::NotHolder)
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "discharge bank 0 1"),
+        Err(LandError::NoSuchLien)
+    );
+
+    let state = apply_commands::<LandRegistry>(
+        &state,
+        &[
+            "discharge bank 0 0",
+            "offer alice 0 carol 500",
+            "accept carol 0 500",
+        ],
+    );
+    assert_eq!(state.parcel(0).unwrap().owner, "carol");
+}
+
+#[test]
+fn land_registry_lien_after_offer_blocks_acceptance() {
+    let state = apply_commands::<LandRegistry>(
+        &registered(),
+        &["offer alice 0 carol 500", "lien alice 0 bank 1000"],
+    );
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "accept carol 0 500"),
+        Err(LandError::Encumbered)
+    );
+}
+
+#[test]
+fn land_registry_subdivide_and_merge() {
+    let state = apply_commands::<LandRegistry>(&registered(), &["subdivide alice 0 x 4"]);
+    assert_eq!(state.parcel(0), None);
+    assert_eq!(state.owned_by("alice"), vec![2, 3]);
+    assert_eq!(state.parcel(2).unwrap().boundary.area(), Some(40));
+    assert_eq!(state.parcel(3).unwrap().boundary.area(), Some(60));
+    assert_eq!(state.total_area(), Some(200));
+
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "subdivide alice 2 x 4"),
+        Err(LandError::BadSplit)
+    );
+    // Parcel 1 only borders part of parcel 2.
+    let state =
+        apply_commands::<LandRegistry>(&state, &["offer bob 1 alice 5", "accept alice 1 5"]);
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "merge alice 1 2"),
+        Err(LandError::NotAdjacent)
+    );
+
+    let state = apply_commands::<LandRegistry>(&state, &["merge alice 3 2", "merge alice 4 1"]);
+    assert_eq!(state.owned_by("alice"), vec![5]);
+    assert_eq!(
+        state.parcel(5).unwrap().boundary,
+        Boundary {
+            west: 0,
+            south: 0,
+            east: 10,
+            north: 20
+        }
+    );
+}
+
+#[test]
+fn land_registry_merge_needs_one_owner() {
+    let state = registered();
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "merge alice 0 1"),
+        Err(LandError::NotOwner)
+    );
+}
+
+#[test]
+fn land_registry_subdivide_waits_for_offers() {
+    let state = apply_commands::<LandRegistry>(&registered(), &["offer alice 0 carol 500"]);
+    assert_eq!(
+        try_command::<LandRegistry>(&state, "subdivide alice 0 y 5"),
+        Err(LandError::OfferPending)
+    );
+    let state = apply_commands::<LandRegistry>(&state, &["withdraw alice 0"]);
+    assert!(try_command::<LandRegistry>(&state, "subdivide alice 0 y 5").is_ok());
+}
+
+#[test]
+fn land_registry_history_follows_lineage() {
+    let state = apply_commands::<LandRegistry>(
+        &registered(),
+        &[
+            "lien alice 0 bank 10",
+            "discharge bank 0 0",
+            "subdivide alice 0 y 5",
+            "offer alice 3 carol 50",
+            "accept carol 3 50",
+            "offer alice 2 dave 20",
+        ],
+    );
+
+    // Parcel 2 shares parcel 0's past, but not what happened to its sibling afterwards.
+    assert_eq!(
+        state.history(2),
+        vec![
+            &state.log()[0],
+            &state.log()[2],
+            &state.log()[3],
+            &state.log()[4],
+            &state.log()[7],
+        ]
+    );
+    assert_eq!(
+        state.history(3),
+        vec![
+            &state.log()[0],
+            &state.log()[2],
+            &state.log()[3],
+            &state.log()[4],
+            &state.log()[5],
+            &state.log()[6],
+        ]
+    );
+    assert_eq!(state.history(1), vec![&state.log()[1]]);
+}
+
+#[test]
+fn land_registry_random_actions_never_overlap_or_lose_land() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let accounts = ["alice", "bob", "carol", "bank"];
+    let mut rng = StdRng::seed_from_u64(18);
+
+    for _ in 0..20 {
+        let mut state = Cadastre::default();
+        for _ in 0..300 {
+            let account = accounts[rng.gen_range(0..accounts.len())].to_string();
+            let other = accounts[rng.gen_range(0..accounts.len())].to_string();
+            let parcel = rng.gen_range(0..state.next_parcel.max(1));
+            let action = match rng.gen_range(0..8) {
+                0 => {
+                    let (west, south) = (rng.gen_range(0..20), rng.gen_range(0..20));
+                    LandAction::Register {
+                        account,
+                        boundary: Boundary {
+                            west,
+                            south,
+                            east: west + rng.gen_range(1..6),
+                            north: south + rng.gen_range(1..6),
+                        },
+                    }
+                }
+                1 => LandAction::Offer {
+                    account,
+                    parcel,
+                    buyer: other,
+                    price: 5,
+                },
+                2 => LandAction::Accept {
+                    account,
+                    parcel,
+                    price: 5,
+                },
+                3 => LandAction::PlaceLien {
+                    account,
+                    parcel,
+                    holder: other,
+                    amount: 5,
+                },
+                4 => LandAction::Discharge {
+                    account,
+                    parcel,
+                    lien: rng.gen_range(0..state.next_lien.max(1)),
+                },
+                5 => LandAction::Subdivide {
+                    account,
+                    parcel,
+                    split: if rng.gen() {
+                        Split::AtX(rng.gen_range(0..25))
+                    } else {
+                        Split::AtY(rng.gen_range(0..25))
+                    },
+                },
+                6 => LandAction::Merge {
+                    account,
+                    parcels: [parcel, rng.gen_range(0..state.next_parcel.max(1))],
+                },
+                _ => LandAction::WithdrawOffer { account, parcel },
+            };
+
+            let registered_area = match &action {
+                LandAction::Register { boundary, .. } => boundary.area().unwrap_or(0),
+                _ => 0,
+            };
+            let next = LandRegistry::next_state(&state, &action);
+            if next != state {
+                assert_eq!(
+                    next.total_area(),
+                    state.total_area().map(|total| total + registered_area),
+                    "{:?}",
+                    action
+                );
+            }
+            state = next;
+
+            let parcels: Vec<_> = state.parcels().collect();
+            for (i, (a, first)) in parcels.iter().enumerate() {
+                for (b, second) in &parcels[i + 1..] {
+                    assert!(
+                        !first.boundary.overlaps(&second.boundary),
+                        "parcels {} and {} overlap",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+}
@@ -19,17 +19,17 @@
 //!   * Social Graph
 //!   * Web of Trust
 //!   * Reputation System
+//!
+//! The submodules below are our own attempts at some of these ideas.
 
-pub struct State {
-
-}
-
-pub enum Transition {
-
-}
-
-impl StateMachine<Transition> for State {
-    fn next_state(&self, t: &Transition) -> Self {
-        todo!()
-    }
-}
\ No newline at end of file
+pub mod checkers;
+pub mod chess;
+pub mod dmv;
+pub mod growing_prize;
+pub mod land_registry;
+pub mod prediction_market;
+pub mod social_graph;
+pub mod tcr;
+pub mod tic_tac_toe;
+pub mod utility;
+pub mod web_of_trust;
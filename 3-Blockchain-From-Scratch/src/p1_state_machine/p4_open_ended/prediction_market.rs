@@ -0,0 +1,856 @@
+//! A prediction market. Anyone can open a market on a question with several possible outcomes.
+//! Traders buy and sell shares in the outcomes they believe in, and once the market closes an
+//! oracle reports the real outcome. Each share in the winning outcome can then be redeemed for a
+//! single unit of money, while shares in the other outcomes become worthless.
+//!
+//! Shares are traded against an automated market maker rather than other traders. The market
+//! creator provides the initial liquidity, and the maker keeps the product of its share pools
+//! constant. Buying an outcome makes its shares scarcer in the pool, and so more expensive.
+//!
+//! Every share is backed by money held in the market. Buying with some money first mints that
+//! many complete sets of shares, one in each outcome, and selling burns complete sets. Whatever
+//! the outcome, the market always holds exactly enough money to pay every winning share.
+//!
+//! All amounts are integers and all maps are ordered, so that every node running this machine
+//! agrees on the result. When a division does not come out even, the pool keeps the remainder.
+
+#[cfg(test)]
+use crate::p1_state_machine::repl::{apply_commands, try_command};
+use crate::p1_state_machine::repl::{parse_number, ReplMachine};
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+use std::collections::BTreeMap;
+
+/// A prediction market exchange hosting any number of markets.
+pub struct PredictionMarket;
+
+/// Identifies a trader
+pub type AccountId = String;
+
+/// Identifies a market. Markets are numbered in the order they were created.
+pub type MarketId = u64;
+
+/// The most outcomes a single market can have. This keeps the product of the pools from
+/// overflowing for all but absurd amounts of liquidity.
+pub const MAX_OUTCOMES: usize = 4;
+
+/// Where a market is in its life
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MarketStatus {
+    /// Shares can be bought and sold
+    Open,
+    /// Trading has stopped and the market is waiting for the oracle
+    Closed,
+    /// The oracle has reported the winning outcome
+    Resolved(usize),
+}
+
+/// A single market on a single question
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Market {
+    /// Whoever provided the liquidity. They can close the market, and are owed whatever shares
+    /// are left in the pools when it resolves.
+    pub creator: AccountId,
+    /// Whoever reports the outcome
+    pub oracle: AccountId,
+    /// What the market is about
+    pub question: String,
+    /// The possible answers
+    pub outcomes: Vec<String>,
+    /// The shares of each outcome held by the market maker
+    pools: Vec<u64>,
+    /// The money held by the market, which backs every share
+    collateral: u64,
+    /// The shares of each outcome held by each trader
+    holdings: BTreeMap<(AccountId, usize), u64>,
+    /// Where the market is in its life
+    pub status: MarketStatus,
+}
+
+impl Market {
+    /// The shares of each outcome held by the market maker.
+    pub fn pools(&self) -> &[u64] {
+        &self.pools
+    }
+
+    /// The money held by the market.
+    pub fn collateral(&self) -> u64 {
+        self.collateral
+    }
+
+    /// The shares of an outcome held by a trader.
+    pub fn shares(&self, account: &str, outcome: usize) -> u64 {
+        self.holdings
+            .get(&(account.to_string(), outcome))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Every share in existence, for each outcome, whether held by the pool or by traders.
+    pub fn supply(&self) -> Vec<u64> {
+        (0..self.outcomes.len())
+            .map(|outcome| {
+                let held: u64 = self
+                    .holdings
+                    .iter()
+                    .filter(|((_, o), _)| *o == outcome)
+                    .map(|(_, shares)| shares)
+                    .sum();
+                self.pools[outcome] + held
+            })
+            .collect()
+    }
+
+    /// The product of the pools, which the market maker keeps from decreasing.
+    fn invariant(pools: &[u64]) -> Result<u128, MarketError> {
+        pools.iter().try_fold(1u128, |product, pool| {
+            product
+                .checked_mul(*pool as u128)
+                .ok_or(MarketError::Overflow)
+        })
+    }
+
+    /// How big the pool of `outcome` must be for the product of all the pools to be at least
+    /// `k`, given the sizes of the other pools.
+    fn balancing_pool(k: u128, pools: &[u64], outcome: usize) -> Result<u64, MarketError> {
+        let others: Vec<u64> = pools
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != outcome)
+            .map(|(_, p)| *p)
+            .collect();
+        let others = Self::invariant(&others)?;
+        u64::try_from(k.div_ceil(others)).map_err(|_| MarketError::Overflow)
+    }
+}
+
+/// The state of the whole exchange
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Exchange {
+    /// The money held by each account outside of any market
+    balances: BTreeMap<AccountId, u64>,
+    /// Every market ever created
+    markets: BTreeMap<MarketId, Market>,
+    /// The id the next market will get
+    next_market: MarketId,
+}
+
+impl Exchange {
+    /// An exchange with no markets, where the given accounts start with the given money.
+    /// There is no other way to create money.
+    pub fn new(balances: &[(&str, u64)]) -> Self {
+        Self {
+            balances: balances
+                .iter()
+                .map(|(account, amount)| (account.to_string(), *amount))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The money an account holds outside of any market.
+    pub fn balance(&self, account: &str) -> u64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    /// A market, if it exists.
+    pub fn market(&self, market: MarketId) -> Option<&Market> {
+        self.markets.get(&market)
+    }
+
+    /// All the money in the system, whether held by accounts or locked in markets. No transition
+    /// ever changes this.
+    pub fn total_money(&self) -> u128 {
+        self.balances.values().map(|b| *b as u128).sum::<u128>()
+            + self
+                .markets
+                .values()
+                .map(|m| m.collateral as u128)
+                .sum::<u128>()
+    }
+
+    /// Take money from an account.
+    fn withdraw(&mut self, account: &str, amount: u64) -> Result<(), MarketError> {
+        let balance = self.balance(account);
+        if balance < amount {
+            return Err(MarketError::InsufficientBalance {
+                needed: amount,
+                available: balance,
+            });
+        }
+        self.balances.insert(account.to_string(), balance - amount);
+        Ok(())
+    }
+
+    /// Give money to an account, as long as its balance can hold it.
+    fn deposit(&mut self, account: &str, amount: u64) -> Result<(), MarketError> {
+        let balance = self
+            .balance(account)
+            .checked_add(amount)
+            .ok_or(MarketError::Overflow)?;
+        self.balances.insert(account.to_string(), balance);
+        Ok(())
+    }
+}
+
+/// Things people do on a prediction market exchange
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MarketAction {
+    /// Open a new market, locking up `liquidity` of the creator's money in it.
+    Create {
+        creator: AccountId,
+        oracle: AccountId,
+        question: String,
+        outcomes: Vec<String>,
+        liquidity: u64,
+    },
+    /// Spend exactly `amount` on shares of one outcome.
+    Buy {
+        account: AccountId,
+        market: MarketId,
+        outcome: usize,
+        amount: u64,
+    },
+    /// Sell as many shares of one outcome as it takes to get exactly `amount` back.
+    Sell {
+        account: AccountId,
+        market: MarketId,
+        outcome: usize,
+        amount: u64,
+    },
+    /// Stop trading. Only the creator may do this.
+    Close {
+        account: AccountId,
+        market: MarketId,
+    },
+    /// Report the winning outcome of a closed market. Only the oracle may do this.
+    Resolve {
+        account: AccountId,
+        market: MarketId,
+        outcome: usize,
+    },
+    /// Exchange winning shares for money once the market is resolved.
+    Redeem {
+        account: AccountId,
+        market: MarketId,
+    },
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MarketError {
+    /// There is no market with this id
+    UnknownMarket(MarketId),
+    /// Markets need between 2 and `MAX_OUTCOMES` outcomes
+    BadOutcomeCount(usize),
+    /// The market has no outcome with this index
+    NoSuchOutcome(usize),
+    /// Amounts of zero are not allowed
+    ZeroAmount,
+    /// The account doesn't have enough money
+    InsufficientBalance { needed: u64, available: u64 },
+    /// The account doesn't have enough shares
+    InsufficientShares { needed: u64, available: u64 },
+    /// The market maker cannot pay out that much
+    InsufficientLiquidity,
+    /// Only the market's creator can do this
+    NotCreator,
+    /// Only the market's oracle can do this
+    NotOracle,
+    /// The market is not open for trading
+    NotOpen,
+    /// The market must be closed first
+    NotClosed,
+    /// The market has not been resolved yet
+    NotResolved,
+    /// The account has no winning shares
+    NothingToRedeem,
+    /// The numbers involved are too big to handle
+    Overflow,
+}
+
+impl TryStateMachine for PredictionMarket {
+    type State = Exchange;
+    type Transition = MarketAction;
+    type Error = MarketError;
+
+    fn try_next_state(
+        starting_state: &Exchange,
+        t: &MarketAction,
+    ) -> Result<Exchange, MarketError> {
+        let mut state = starting_state.clone();
+        match t {
+            MarketAction::Create {
+                creator,
+                oracle,
+                question,
+                outcomes,
+                liquidity,
+            } => {
+                if !(2..=MAX_OUTCOMES).contains(&outcomes.len()) {
+                    return Err(MarketError::BadOutcomeCount(outcomes.len()));
+                }
+                if *liquidity == 0 {
+                    return Err(MarketError::ZeroAmount);
+                }
+                state.withdraw(creator, *liquidity)?;
+                let pools = vec![*liquidity; outcomes.len()];
+                Market::invariant(&pools)?;
+                state.markets.insert(
+                    state.next_market,
+                    Market {
+                        creator: creator.clone(),
+                        oracle: oracle.clone(),
+                        question: question.clone(),
+                        outcomes: outcomes.clone(),
+                        pools,
+                        collateral: *liquidity,
+                        holdings: BTreeMap::new(),
+                        status: MarketStatus::Open,
+                    },
+                );
+                state.next_market += 1;
+            }
+            MarketAction::Buy {
+                account,
+                market: id,
+                outcome,
+                amount,
+            } => {
+                let mut market = open_market(&state, *id, *outcome, *amount)?;
+                state.withdraw(account, *amount)?;
+
+                // Mint complete sets into the pools, then take out shares of the chosen outcome
+                // until the pools are back in balance.
+                let k = Market::invariant(&market.pools)?;
+                for pool in market.pools.iter_mut() {
+                    *pool = pool.checked_add(*amount).ok_or(MarketError::Overflow)?;
+                }
+                let remaining = Market::balancing_pool(k, &market.pools, *outcome)?;
+                let bought = market.pools[*outcome] - remaining;
+                market.pools[*outcome] = remaining;
+                market.collateral = market
+                    .collateral
+                    .checked_add(*amount)
+                    .ok_or(MarketError::Overflow)?;
+                let held = market.shares(account, *outcome);
+                market.holdings.insert(
+                    (account.clone(), *outcome),
+                    held.checked_add(bought).ok_or(MarketError::Overflow)?,
+                );
+                state.markets.insert(*id, market);
+            }
+            MarketAction::Sell {
+                account,
+                market: id,
+                outcome,
+                amount,
+            } => {
+                let mut market = open_market(&state, *id, *outcome, *amount)?;
+                let drained = market
+                    .pools
+                    .iter()
+                    .enumerate()
+                    .any(|(i, pool)| i != *outcome && *pool <= *amount);
+                if drained {
+                    return Err(MarketError::InsufficientLiquidity);
+                }
+
+                // Burn complete sets out of the pools, then put in shares of the chosen outcome
+                // until the pools are back in balance.
+                let k = Market::invariant(&market.pools)?;
+                let mut pools = market.pools.clone();
+                for (i, pool) in pools.iter_mut().enumerate() {
+                    if i != *outcome {
+                        *pool -= amount;
+                    }
+                }
+                let balanced = Market::balancing_pool(k, &pools, *outcome)?;
+                let sold = (balanced + amount) - market.pools[*outcome];
+                let available = market.shares(account, *outcome);
+                if available < sold {
+                    return Err(MarketError::InsufficientShares {
+                        needed: sold,
+                        available,
+                    });
+                }
+
+                pools[*outcome] = balanced;
+                market.pools = pools;
+                market.collateral -= amount;
+                market
+                    .holdings
+                    .insert((account.clone(), *outcome), available - sold);
+                state.markets.insert(*id, market);
+                state.deposit(account, *amount)?;
+            }
+            MarketAction::Close {
+                account,
+                market: id,
+            } => {
+                let market = state
+                    .markets
+                    .get_mut(id)
+                    .ok_or(MarketError::UnknownMarket(*id))?;
+                if market.creator != *account {
+                    return Err(MarketError::NotCreator);
+                }
+                if market.status != MarketStatus::Open {
+                    return Err(MarketError::NotOpen);
+                }
+                market.status = MarketStatus::Closed;
+            }
+            MarketAction::Resolve {
+                account,
+                market: id,
+                outcome,
+            } => {
+                let market = state
+                    .markets
+                    .get_mut(id)
+                    .ok_or(MarketError::UnknownMarket(*id))?;
+                if market.oracle != *account {
+                    return Err(MarketError::NotOracle);
+                }
+                if market.status != MarketStatus::Closed {
+                    return Err(MarketError::NotClosed);
+                }
+                if *outcome >= market.outcomes.len() {
+                    return Err(MarketError::NoSuchOutcome(*outcome));
+                }
+
+                // The winning shares left in the pool belong to the creator.
+                let leftover = market.pools[*outcome];
+                let held = market.shares(&market.creator, *outcome);
+                market.holdings.insert(
+                    (market.creator.clone(), *outcome),
+                    held.checked_add(leftover).ok_or(MarketError::Overflow)?,
+                );
+                market.pools = vec![0; market.outcomes.len()];
+                market.status = MarketStatus::Resolved(*outcome);
+            }
+            MarketAction::Redeem {
+                account,
+                market: id,
+            } => {
+                let market = state
+                    .markets
+                    .get_mut(id)
+                    .ok_or(MarketError::UnknownMarket(*id))?;
+                let MarketStatus::Resolved(winner) = market.status else {
+                    return Err(MarketError::NotResolved);
+                };
+                let winnings = market.shares(account, winner);
+                if winnings == 0 {
+                    return Err(MarketError::NothingToRedeem);
+                }
+                market.holdings.retain(|(holder, _), _| holder != account);
+                market.collateral -= winnings;
+                state.deposit(account, winnings)?;
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Prediction Market".into()
+    }
+}
+
+/// Look up a market that is open for trading an outcome with a non-zero amount.
+fn open_market(
+    state: &Exchange,
+    id: MarketId,
+    outcome: usize,
+    amount: u64,
+) -> Result<Market, MarketError> {
+    let market = state
+        .markets
+        .get(&id)
+        .ok_or(MarketError::UnknownMarket(id))?;
+    if market.status != MarketStatus::Open {
+        return Err(MarketError::NotOpen);
+    }
+    if outcome >= market.outcomes.len() {
+        return Err(MarketError::NoSuchOutcome(outcome));
+    }
+    if amount == 0 {
+        return Err(MarketError::ZeroAmount);
+    }
+    Ok(market.clone())
+}
+
+impl RejectsInPlace for PredictionMarket {}
+
+impl ReplMachine for PredictionMarket {
+    fn initial_state() -> Exchange {
+        Exchange::new(&[("alice", 1000), ("bob", 1000), ("carol", 1000)])
+    }
+
+    fn parse_transition(input: &str) -> Result<MarketAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let account = words
+            .get(1)
+            .map(|w| w.to_string())
+            .ok_or("missing account")?;
+
+        match words[0] {
+            "create" => Ok(MarketAction::Create {
+                creator: account,
+                oracle: words.get(2).ok_or("missing oracle")?.to_string(),
+                liquidity: parse_number(&words, 3, "liquidity")?,
+                outcomes: words
+                    .get(4)
+                    .ok_or("missing outcomes")?
+                    .split(',')
+                    .map(String::from)
+                    .collect(),
+                question: words[5.min(words.len())..].join(" "),
+            }),
+            "buy" => Ok(MarketAction::Buy {
+                account,
+                market: parse_number(&words, 2, "market")?,
+                outcome: parse_number(&words, 3, "outcome")?,
+                amount: parse_number(&words, 4, "amount")?,
+            }),
+            "sell" => Ok(MarketAction::Sell {
+                account,
+                market: parse_number(&words, 2, "market")?,
+                outcome: parse_number(&words, 3, "outcome")?,
+                amount: parse_number(&words, 4, "amount")?,
+            }),
+            "close" => Ok(MarketAction::Close {
+                account,
+                market: parse_number(&words, 2, "market")?,
+            }),
+            "resolve" => Ok(MarketAction::Resolve {
+                account,
+                market: parse_number(&words, 2, "market")?,
+                outcome: parse_number(&words, 3, "outcome")?,
+            }),
+            "redeem" => Ok(MarketAction::Redeem {
+                account,
+                market: parse_number(&words, 2, "market")?,
+            }),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "create <who> <oracle> <liquidity> <yes,no,..> <question>",
+            "buy <who> <market> <outcome> <amount>      spend amount on shares",
+            "sell <who> <market> <outcome> <amount>     sell shares to get amount back",
+            "close <who> <market>                       stop trading",
+            "resolve <who> <market> <outcome>           report the winner",
+            "redeem <who> <market>                      cash in winning shares",
+        ]
+    }
+}
+
+#[cfg(test)]
+fn rain_market() -> Exchange {
+    apply_commands::<PredictionMarket>(
+        &PredictionMarket::initial_state(),
+        &["create bob carol 100 yes,no Will it rain tomorrow?"],
+    )
+}
+
+#[cfg(test)]
+fn buy(account: &str, outcome: usize, amount: u64) -> MarketAction {
+    MarketAction::Buy {
+        account: account.into(),
+        market: 0,
+        outcome,
+        amount,
+    }
+}
+
+#[test]
+fn prediction_market_create() {
+    let state = rain_market();
+    let market = state.market(0).unwrap();
+    assert_eq!(market.pools(), &[100, 100]);
+    assert_eq!(market.collateral(), 100);
+    assert_eq!(market.status, MarketStatus::Open);
+    assert_eq!(state.balance("bob"), 900);
+    assert_eq!(state.total_money(), 3000);
+}
+
+#[test]
+fn prediction_market_buy_moves_price() {
+    let state = apply_commands::<PredictionMarket>(&rain_market(), &["buy alice 0 0 50"]);
+    let market = state.market(0).unwrap();
+
+    // 50 complete sets are minted, then yes shares are taken out until 100 * 100 <= yes * 150.
+    assert_eq!(market.pools(), &[67, 150]);
+    assert_eq!(market.shares("alice", 0), 83);
+    assert_eq!(market.collateral(), 150);
+    assert_eq!(state.balance("alice"), 950);
+
+    // The same money now buys fewer yes shares than before.
+    let again = apply_commands::<PredictionMarket>(&state, &["buy alice 0 0 50"]);
+    assert!(again.market(0).unwrap().shares("alice", 0) - 83 < 83);
+}
+
+#[test]
+fn prediction_market_sell() {
+    let state = apply_commands::<PredictionMarket>(
+        &rain_market(),
+        &["buy alice 0 0 50", "sell alice 0 0 20"],
+    );
+    let market = state.market(0).unwrap();
+
+    // 20 complete sets are burned, then yes shares are put in until 67 * 150 <= yes * 130.
+    assert_eq!(market.pools(), &[78, 130]);
+    assert_eq!(market.shares("alice", 0), 52);
+    assert_eq!(market.collateral(), 130);
+    assert_eq!(state.balance("alice"), 970);
+}
+
+#[test]
+fn prediction_market_full_lifecycle() {
+    let state = apply_commands::<PredictionMarket>(
+        &rain_market(),
+        &[
+            "buy alice 0 0 50",
+            "buy carol 0 1 30",
+            "close bob 0",
+            "resolve carol 0 0",
+        ],
+    );
+    let market = state.market(0).unwrap();
+    assert_eq!(market.status, MarketStatus::Resolved(0));
+    assert_eq!(market.supply()[0], market.collateral());
+
+    let state = apply_commands::<PredictionMarket>(&state, &["redeem alice 0", "redeem bob 0"]);
+    assert_eq!(state.balance("alice"), 950 + 83);
+    assert_eq!(state.balance("carol"), 970);
+    assert_eq!(state.market(0).unwrap().collateral(), 0);
+    assert_eq!(state.total_money(), 3000);
+
+    // Carol bet on the wrong outcome, and alice has already been paid.
+    for account in ["carol", "alice"] {
+        assert_eq!(
+            PredictionMarket::try_next_state(
+                &state,
+                &MarketAction::Redeem {
+                    account: account.into(),
+                    market: 0,
+                }
+            ),
+            Err(MarketError::NothingToRedeem)
+        );
+    }
+}
+
+#[test]
+fn prediction_market_only_creator_closes_and_only_oracle_resolves() {
+    let state = rain_market();
+    let close = |account: &str| MarketAction::Close {
+        account: account.into(),
+        market: 0,
+    };
+    let resolve = |account: &str| MarketAction::Resolve {
+        account: account.into(),
+        market: 0,
+        outcome: 1,
+    };
+
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &close("alice")),
+        Err(MarketError::NotCreator)
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &resolve("carol")),
+        Err(MarketError::NotClosed)
+    );
+    let state = apply_commands::<PredictionMarket>(&state, &["close bob 0"]);
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &resolve("bob")),
+        Err(MarketError::NotOracle)
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &buy("alice", 0, 10)),
+        Err(MarketError::NotOpen)
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(
+            &state,
+            &MarketAction::Redeem {
+                account: "alice".into(),
+                market: 0
+            }
+        ),
+        Err(MarketError::NotResolved)
+    );
+}
+
+#[test]
+fn prediction_market_rejects_bad_trades() {
+    let state = apply_commands::<PredictionMarket>(&rain_market(), &["buy alice 0 0 50"]);
+    let sell = |account: &str, outcome, amount| MarketAction::Sell {
+        account: account.into(),
+        market: 0,
+        outcome,
+        amount,
+    };
+
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &buy("alice", 2, 10)),
+        Err(MarketError::NoSuchOutcome(2))
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &buy("alice", 0, 0)),
+        Err(MarketError::ZeroAmount)
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &buy("alice", 0, 5000)),
+        Err(MarketError::InsufficientBalance {
+            needed: 5000,
+            available: 950
+        })
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &sell("carol", 0, 10)),
+        Err(MarketError::InsufficientShares {
+            needed: 15,
+            available: 0
+        })
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &sell("alice", 0, 150)),
+        Err(MarketError::InsufficientLiquidity)
+    );
+    assert!(PredictionMarket::try_next_state(&state, &buy("alice", 0, 10)).is_ok());
+}
+
+#[test]
+fn prediction_market_rejects_bad_markets() {
+    let create = |outcomes: usize, liquidity| MarketAction::Create {
+        creator: "alice".into(),
+        oracle: "bob".into(),
+        question: "?".into(),
+        outcomes: vec!["x".into(); outcomes],
+        liquidity,
+    };
+    let state = PredictionMarket::initial_state();
+
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &create(1, 10)),
+        Err(MarketError::BadOutcomeCount(1))
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &create(MAX_OUTCOMES + 1, 10)),
+        Err(MarketError::BadOutcomeCount(MAX_OUTCOMES + 1))
+    );
+    assert_eq!(
+        PredictionMarket::try_next_state(&state, &create(2, 0)),
+        Err(MarketError::ZeroAmount)
+    );
+    // Rejected actions change nothing at all.
+    assert_eq!(PredictionMarket::next_state(&state, &create(2, 0)), state);
+}
+
+#[test]
+fn prediction_market_rejects_overflow() {
+    let state = Exchange::new(&[("alice", u64::MAX), ("bob", 1000)]);
+    assert_eq!(state.total_money(), u64::MAX as u128 + 1000);
+
+    let state = apply_commands::<PredictionMarket>(
+        &state,
+        &[
+            "create bob bob 1000 yes,no Will it overflow?",
+            "buy alice 0 0 1000",
+            "close bob 0",
+            "resolve bob 0 0",
+        ],
+    );
+    // Alice's winnings are worth more than she paid, and her balance can't hold them.
+    assert_eq!(
+        try_command::<PredictionMarket>(&state, "redeem alice 0"),
+        Err(MarketError::Overflow)
+    );
+    let state = apply_commands::<PredictionMarket>(&state, &["redeem bob 0"]);
+    assert_eq!(state.total_money(), u64::MAX as u128 + 1000);
+}
+
+#[test]
+fn prediction_market_conserves_money() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let accounts = ["alice", "bob", "carol"];
+    let mut rng = StdRng::seed_from_u64(7);
+
+    for _ in 0..20 {
+        let mut state = PredictionMarket::initial_state();
+        for _ in 0..200 {
+            let account = accounts[rng.gen_range(0..accounts.len())].to_string();
+            let market = rng.gen_range(0..3);
+            let outcome = rng.gen_range(0..3);
+            let amount = rng.gen_range(1..120);
+            let action = match rng.gen_range(0..10) {
+                0 => MarketAction::Create {
+                    creator: account,
+                    oracle: accounts[rng.gen_range(0..accounts.len())].into(),
+                    question: "?".into(),
+                    outcomes: vec!["a".into(), "b".into(), "c".into()][..rng.gen_range(2..=3)]
+                        .to_vec(),
+                    liquidity: amount,
+                },
+                1..=3 => MarketAction::Buy {
+                    account,
+                    market,
+                    outcome,
+                    amount,
+                },
+                4..=6 => MarketAction::Sell {
+                    account,
+                    market,
+                    outcome,
+                    amount: amount / 4 + 1,
+                },
+                7 => MarketAction::Close { account, market },
+                8 => MarketAction::Resolve {
+                    account,
+                    market,
+                    outcome,
+                },
+                _ => MarketAction::Redeem { account, market },
+            };
+            state = PredictionMarket::next_state(&state, &action);
+
+            assert_eq!(state.total_money(), 3000);
+            for market in state.markets.values() {
+                let supply = market.supply();
+                match market.status {
+                    MarketStatus::Resolved(winner) => {
+                        assert_eq!(supply[winner], market.collateral())
+                    }
+                    _ => assert!(supply.iter().all(|s| *s == market.collateral())),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn prediction_market_repl_commands() {
+    assert_eq!(
+        PredictionMarket::parse_transition("create bob carol 100 yes,no Will it rain tomorrow?"),
+        Ok(MarketAction::Create {
+            creator: "bob".into(),
+            oracle: "carol".into(),
+            question: "Will it rain tomorrow?".into(),
+            outcomes: vec!["yes".into(), "no".into()],
+            liquidity: 100,
+        })
+    );
+    assert_eq!(
+        PredictionMarket::parse_transition("buy alice 0 1 25"),
+        Ok(buy("alice", 1, 25))
+    );
+    assert!(PredictionMarket::parse_transition("buy alice 0 yes 25").is_err());
+    assert!(PredictionMarket::parse_transition("").is_err());
+}
@@ -0,0 +1,655 @@
+//! A small social network. Users follow each other and write posts, and each user's feed shows
+//! the posts of everyone they follow. Following does not need permission, but blocking does
+//! override it: when either user blocks the other, any follows between them are dropped, and
+//! neither can follow the other again until the block is lifted.
+//!
+//! Moderation happens in two stages. Any user can flag a post they think breaks the rules, and a
+//! post flagged by enough different users is hidden straight away. Moderators then have the
+//! final say, either removing the post for good or restoring it.
+//!
+//! Every action names the user taking it, and nothing depends on anything but the actions
+//! themselves, so the same machine could serve as the runtime of a chain where each action is
+//! signed by the user it names.
+
+use crate::p1_state_machine::repl::ReplMachine;
+#[cfg(test)]
+use crate::p1_state_machine::repl::{apply_commands, try_command};
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A social network
+pub struct SocialGraph;
+
+/// Identifies a user
+pub type UserId = String;
+
+/// Identifies a post
+pub type PostId = u64;
+
+/// How many different users must flag a post before it is hidden
+pub const FLAG_THRESHOLD: usize = 3;
+/// The longest a post may be, in characters
+pub const MAX_POST_LENGTH: usize = 280;
+
+/// A user and the people they follow and have blocked
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct User {
+    pub following: BTreeSet<UserId>,
+    pub blocked: BTreeSet<UserId>,
+}
+
+/// Whether a post can be seen
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Visibility {
+    /// Shown in feeds
+    Visible,
+    /// Flagged by enough users that it is hidden until a moderator looks at it
+    Hidden,
+    /// Removed by a moderator. This is final.
+    Removed,
+}
+
+/// Something a user wrote
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Post {
+    pub author: UserId,
+    pub text: String,
+    pub visibility: Visibility,
+    /// The users who flagged the post since a moderator last looked at it
+    pub flags: BTreeSet<UserId>,
+}
+
+/// The state of the social network
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Network {
+    users: BTreeMap<UserId, User>,
+    moderators: BTreeSet<UserId>,
+    posts: BTreeMap<PostId, Post>,
+    next_post: PostId,
+}
+
+impl Network {
+    /// A network whose only users are the given moderators.
+    pub fn new(moderators: &[&str]) -> Self {
+        Self {
+            users: moderators
+                .iter()
+                .map(|m| (m.to_string(), User::default()))
+                .collect(),
+            moderators: moderators.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// A user, if they have joined.
+    pub fn user(&self, user: &str) -> Option<&User> {
+        self.users.get(user)
+    }
+
+    /// A post, whatever its visibility.
+    pub fn post(&self, post: PostId) -> Option<&Post> {
+        self.posts.get(&post)
+    }
+
+    /// Whether a user is a moderator.
+    pub fn is_moderator(&self, user: &str) -> bool {
+        self.moderators.contains(user)
+    }
+
+    /// Whether one user follows another.
+    pub fn follows(&self, user: &str, whom: &str) -> bool {
+        self.user(user).is_some_and(|u| u.following.contains(whom))
+    }
+
+    /// Whether either user has blocked the other.
+    pub fn blocked_between(&self, a: &str, b: &str) -> bool {
+        let blocks = |x: &str, y: &str| self.user(x).is_some_and(|u| u.blocked.contains(y));
+        blocks(a, b) || blocks(b, a)
+    }
+
+    /// The users who follow a user.
+    pub fn followers(&self, user: &str) -> BTreeSet<&UserId> {
+        self.users
+            .iter()
+            .filter(|(_, u)| u.following.contains(user))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// The users who follow a user and are followed back.
+    pub fn friends(&self, user: &str) -> BTreeSet<&UserId> {
+        self.followers(user)
+            .into_iter()
+            .filter(|follower| self.follows(user, follower))
+            .collect()
+    }
+
+    /// The friends two users have in common.
+    pub fn mutual_friends(&self, a: &str, b: &str) -> BTreeSet<&UserId> {
+        self.friends(a)
+            .intersection(&self.friends(b))
+            .copied()
+            .collect()
+    }
+
+    /// The visible posts by a user and everyone they follow, newest first.
+    pub fn feed(&self, user: &str) -> Vec<(PostId, &Post)> {
+        let Some(reader) = self.user(user) else {
+            return Vec::new();
+        };
+        self.posts
+            .iter()
+            .rev()
+            .filter(|(_, post)| post.visibility == Visibility::Visible)
+            .filter(|(_, post)| post.author == user || reader.following.contains(&post.author))
+            .map(|(id, post)| (*id, post))
+            .collect()
+    }
+
+    /// A user who has joined, who may therefore act.
+    fn member(&mut self, user: &str) -> Result<&mut User, SocialError> {
+        self.users
+            .get_mut(user)
+            .ok_or_else(|| SocialError::NoSuchUser(user.into()))
+    }
+
+    /// Check that both users exist and are different people.
+    fn check_pair(&self, user: &str, whom: &str) -> Result<(), SocialError> {
+        for u in [user, whom] {
+            if !self.users.contains_key(u) {
+                return Err(SocialError::NoSuchUser(u.into()));
+            }
+        }
+        if user == whom {
+            return Err(SocialError::Yourself);
+        }
+        Ok(())
+    }
+}
+
+/// How a moderator rules on a post
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Verdict {
+    Remove,
+    Restore,
+}
+
+/// Things users do on the network
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SocialAction {
+    /// Join the network.
+    Join { user: UserId },
+    /// Start following someone.
+    Follow { user: UserId, whom: UserId },
+    /// Stop following someone.
+    Unfollow { user: UserId, whom: UserId },
+    /// Block someone, dropping any follows between you.
+    Block { user: UserId, whom: UserId },
+    /// Lift a block. Any follows it dropped stay dropped.
+    Unblock { user: UserId, whom: UserId },
+    /// Write a post.
+    Post { user: UserId, text: String },
+    /// Flag a post as breaking the rules.
+    Flag { user: UserId, post: PostId },
+    /// Rule on a post as a moderator.
+    Moderate {
+        user: UserId,
+        post: PostId,
+        verdict: Verdict,
+    },
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SocialError {
+    /// Somebody has already joined with this name
+    UserExists,
+    /// Nobody has joined with this name
+    NoSuchUser(UserId),
+    /// Users cannot follow or block themselves
+    Yourself,
+    /// The user already follows them
+    AlreadyFollowing,
+    /// The user does not follow them
+    NotFollowing,
+    /// One of the users has blocked the other
+    Blocked,
+    /// The user has not blocked them
+    NotBlocked,
+    /// Posts must have some text, and not too much
+    BadLength(usize),
+    /// There is no post with this id
+    NoSuchPost,
+    /// The user has already flagged this post
+    AlreadyFlagged,
+    /// The post has been removed
+    Removed,
+    /// Only moderators can do this
+    NotModerator,
+}
+
+impl TryStateMachine for SocialGraph {
+    type State = Network;
+    type Transition = SocialAction;
+    type Error = SocialError;
+
+    fn try_next_state(starting_state: &Network, t: &SocialAction) -> Result<Network, SocialError> {
+        let mut state = starting_state.clone();
+        match t {
+            SocialAction::Join { user } => {
+                if state.users.contains_key(user) {
+                    return Err(SocialError::UserExists);
+                }
+                state.users.insert(user.clone(), User::default());
+            }
+            SocialAction::Follow { user, whom } => {
+                state.check_pair(user, whom)?;
+                if state.blocked_between(user, whom) {
+                    return Err(SocialError::Blocked);
+                }
+                if !state.member(user)?.following.insert(whom.clone()) {
+                    return Err(SocialError::AlreadyFollowing);
+                }
+            }
+            SocialAction::Unfollow { user, whom } => {
+                if !state.member(user)?.following.remove(whom) {
+                    return Err(SocialError::NotFollowing);
+                }
+            }
+            SocialAction::Block { user, whom } => {
+                state.check_pair(user, whom)?;
+                let blocker = state.member(user)?;
+                if !blocker.blocked.insert(whom.clone()) {
+                    return Err(SocialError::Blocked);
+                }
+                blocker.following.remove(whom);
+                state.member(whom)?.following.remove(user);
+            }
+            SocialAction::Unblock { user, whom } => {
+                if !state.member(user)?.blocked.remove(whom) {
+                    return Err(SocialError::NotBlocked);
+                }
+            }
+            SocialAction::Post { user, text } => {
+                state.member(user)?;
+                let length = text.chars().count();
+                if length == 0 || length > MAX_POST_LENGTH {
+                    return Err(SocialError::BadLength(length));
+                }
+                state.posts.insert(
+                    state.next_post,
+                    Post {
+                        author: user.clone(),
+                        text: text.clone(),
+                        visibility: Visibility::Visible,
+                        flags: BTreeSet::new(),
+                    },
+                );
+                state.next_post += 1;
+            }
+            SocialAction::Flag { user, post } => {
+                state.member(user)?;
+                let flagged = state.posts.get_mut(post).ok_or(SocialError::NoSuchPost)?;
+                if flagged.visibility == Visibility::Removed {
+                    return Err(SocialError::Removed);
+                }
+                if !flagged.flags.insert(user.clone()) {
+                    return Err(SocialError::AlreadyFlagged);
+                }
+                if flagged.flags.len() >= FLAG_THRESHOLD {
+                    flagged.visibility = Visibility::Hidden;
+                }
+            }
+            SocialAction::Moderate {
+                user,
+                post,
+                verdict,
+            } => {
+                if !state.is_moderator(user) {
+                    return Err(SocialError::NotModerator);
+                }
+                let ruled = state.posts.get_mut(post).ok_or(SocialError::NoSuchPost)?;
+                if ruled.visibility == Visibility::Removed {
+                    return Err(SocialError::Removed);
+                }
+                ruled.flags.clear();
+                ruled.visibility = match verdict {
+                    Verdict::Remove => Visibility::Removed,
+                    Verdict::Restore => Visibility::Visible,
+                };
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Social Graph".into()
+    }
+}
+
+impl RejectsInPlace for SocialGraph {}
+
+impl ReplMachine for SocialGraph {
+    fn initial_state() -> Network {
+        Network::new(&["moderator"])
+    }
+
+    fn parse_transition(input: &str) -> Result<SocialAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let user = words.get(1).ok_or("missing user")?.to_string();
+        let whom = || -> Result<UserId, String> {
+            Ok(words.get(2).ok_or("missing other user")?.to_string())
+        };
+        let post = || -> Result<PostId, String> {
+            let word = words.get(2).ok_or("missing post")?;
+            word.parse()
+                .map_err(|_| format!("not a valid post: {}", word))
+        };
+        match words[0] {
+            "join" => Ok(SocialAction::Join { user }),
+            "follow" => Ok(SocialAction::Follow {
+                user,
+                whom: whom()?,
+            }),
+            "unfollow" => Ok(SocialAction::Unfollow {
+                user,
+                whom: whom()?,
+            }),
+            "block" => Ok(SocialAction::Block {
+                user,
+                whom: whom()?,
+            }),
+            "unblock" => Ok(SocialAction::Unblock {
+                user,
+                whom: whom()?,
+            }),
+            "post" => Ok(SocialAction::Post {
+                user,
+                text: words[2..].join(" "),
+            }),
+            "flag" => Ok(SocialAction::Flag {
+                user,
+                post: post()?,
+            }),
+            "remove" => Ok(SocialAction::Moderate {
+                user,
+                post: post()?,
+                verdict: Verdict::Remove,
+            }),
+            "restore" => Ok(SocialAction::Moderate {
+                user,
+                post: post()?,
+                verdict: Verdict::Restore,
+            }),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "join <who>                 join the network",
+            "follow <who> <whom>        follow someone",
+            "unfollow <who> <whom>      stop following someone",
+            "block <who> <whom>         block someone",
+            "unblock <who> <whom>       lift a block",
+            "post <who> <text...>       write a post",
+            "flag <who> <post>          flag a post as breaking the rules",
+            "remove <moderator> <post>  remove a post for good",
+            "restore <moderator> <post> restore a hidden post",
+        ]
+    }
+}
+
+/// Alice, bob, and carol all follow each other, and dave follows alice.
+#[cfg(test)]
+fn network() -> Network {
+    apply_commands::<SocialGraph>(
+        &Network::new(&["moderator"]),
+        &[
+            "join alice",
+            "join bob",
+            "join carol",
+            "join dave",
+            "follow alice bob",
+            "follow bob alice",
+            "follow alice carol",
+            "follow carol alice",
+            "follow bob carol",
+            "follow carol bob",
+            "follow dave alice",
+        ],
+    )
+}
+
+#[cfg(test)]
+fn names<'a>(users: impl IntoIterator<Item = &'a UserId>) -> Vec<&'a str> {
+    users.into_iter().map(|u| u.as_str()).collect()
+}
+
+#[test]
+fn social_graph_follow_and_unfollow() {
+    let state = network();
+    assert!(state.follows("dave", "alice"));
+    assert!(!state.follows("alice", "dave"));
+    assert_eq!(
+        names(state.followers("alice")),
+        vec!["bob", "carol", "dave"]
+    );
+
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "follow dave alice"),
+        Err(SocialError::AlreadyFollowing)
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "follow dave dave"),
+        Err(SocialError::Yourself)
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "follow dave erin"),
+        Err(SocialError::NoSuchUser("erin".into()))
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "unfollow alice dave"),
+        Err(SocialError::NotFollowing)
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "join dave"),
+        Err(SocialError::UserExists)
+    );
+
+    let state = apply_commands::<SocialGraph>(&state, &["unfollow dave alice"]);
+    assert!(!state.follows("dave", "alice"));
+}
+
+#[test]
+fn social_graph_friends_and_mutual_friends() {
+    let state = network();
+    assert_eq!(names(state.friends("alice")), vec!["bob", "carol"]);
+    assert_eq!(names(state.friends("dave")), Vec::<&str>::new());
+    assert_eq!(names(state.mutual_friends("alice", "bob")), vec!["carol"]);
+
+    let state = apply_commands::<SocialGraph>(
+        &state,
+        &["follow alice dave", "follow dave bob", "follow bob dave"],
+    );
+    assert_eq!(
+        names(state.mutual_friends("alice", "bob")),
+        vec!["carol", "dave"]
+    );
+}
+
+#[test]
+fn social_graph_blocking_drops_follows_both_ways() {
+    let state = apply_commands::<SocialGraph>(&network(), &["block bob alice"]);
+    assert!(!state.follows("alice", "bob"));
+    assert!(!state.follows("bob", "alice"));
+    assert!(state.blocked_between("alice", "bob"));
+
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "follow alice bob"),
+        Err(SocialError::Blocked)
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "follow bob alice"),
+        Err(SocialError::Blocked)
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "unblock alice bob"),
+        Err(SocialError::NotBlocked)
+    );
+
+    // Lifting the block allows following again, but does not restore the old follows.
+    let state = apply_commands::<SocialGraph>(&state, &["unblock bob alice"]);
+    assert!(!state.follows("alice", "bob"));
+    assert!(try_command::<SocialGraph>(&state, "follow alice bob").is_ok());
+}
+
+#[test]
+fn social_graph_feed() {
+    let state = apply_commands::<SocialGraph>(
+        &network(),
+        &[
+            "post alice hello",
+            "post bob hi alice",
+            "post dave nobody follows me back",
+            "post carol hello everyone",
+        ],
+    );
+    let feed: Vec<&str> = state
+        .feed("alice")
+        .into_iter()
+        .map(|(_, p)| p.text.as_str())
+        .collect();
+    assert_eq!(feed, vec!["hello everyone", "hi alice", "hello"]);
+    assert_eq!(state.feed("dave").len(), 2);
+    assert!(state.feed("nobody").is_empty());
+
+    let state = apply_commands::<SocialGraph>(&state, &["block alice carol"]);
+    assert_eq!(state.feed("alice").len(), 2);
+}
+
+#[test]
+fn social_graph_post_length() {
+    let state = network();
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "post alice"),
+        Err(SocialError::BadLength(0))
+    );
+    let long = format!("post alice {}", "a".repeat(MAX_POST_LENGTH + 1));
+    assert_eq!(
+        try_command::<SocialGraph>(&state, &long),
+        Err(SocialError::BadLength(MAX_POST_LENGTH + 1))
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "post erin hello"),
+        Err(SocialError::NoSuchUser("erin".into()))
+    );
+}
+
+#[test]
+fn social_graph_flags_hide_posts() {
+    let state = apply_commands::<SocialGraph>(
+        &network(),
+        &["post bob spam", "flag alice 0", "flag carol 0"],
+    );
+    assert_eq!(state.post(0).unwrap().visibility, Visibility::Visible);
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "flag alice 0"),
+        Err(SocialError::AlreadyFlagged)
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "flag alice 1"),
+        Err(SocialError::NoSuchPost)
+    );
+
+    let state = apply_commands::<SocialGraph>(&state, &["flag dave 0"]);
+    assert_eq!(state.post(0).unwrap().visibility, Visibility::Hidden);
+    assert!(state.feed("bob").is_empty());
+}
+
+#[test]
+fn social_graph_moderation() {
+    let state = apply_commands::<SocialGraph>(
+        &network(),
+        &[
+            "post bob spam",
+            "flag alice 0",
+            "flag carol 0",
+            "flag dave 0",
+        ],
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&state, "restore bob 0"),
+        Err(SocialError::NotModerator)
+    );
+
+    let restored = apply_commands::<SocialGraph>(&state, &["restore moderator 0"]);
+    assert_eq!(restored.post(0).unwrap().visibility, Visibility::Visible);
+    assert!(restored.post(0).unwrap().flags.is_empty());
+    // Users can flag a restored post again.
+    assert!(try_command::<SocialGraph>(&restored, "flag alice 0").is_ok());
+
+    let removed = apply_commands::<SocialGraph>(&state, &["remove moderator 0"]);
+    assert_eq!(removed.post(0).unwrap().visibility, Visibility::Removed);
+    assert_eq!(
+        try_command::<SocialGraph>(&removed, "restore moderator 0"),
+        Err(SocialError::Removed)
+    );
+    assert_eq!(
+        try_command::<SocialGraph>(&removed, "flag alice 0"),
+        Err(SocialError::Removed)
+    );
+}
+
+#[test]
+fn social_graph_random_actions_never_follow_across_a_block() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let users = ["alice", "bob", "carol", "dave", "moderator"];
+    let mut rng = StdRng::seed_from_u64(22);
+
+    for _ in 0..20 {
+        let mut state = SocialGraph::initial_state();
+        for _ in 0..300 {
+            let user = users[rng.gen_range(0..users.len())].to_string();
+            let whom = users[rng.gen_range(0..users.len())].to_string();
+            let post = rng.gen_range(0..state.next_post + 1);
+            let action = match rng.gen_range(0..9) {
+                0 => SocialAction::Join { user },
+                1 | 2 => SocialAction::Follow { user, whom },
+                3 => SocialAction::Unfollow { user, whom },
+                4 => SocialAction::Block { user, whom },
+                5 => SocialAction::Unblock { user, whom },
+                6 => SocialAction::Post {
+                    user,
+                    text: "hello".into(),
+                },
+                7 => SocialAction::Flag { user, post },
+                _ => SocialAction::Moderate {
+                    user,
+                    post,
+                    verdict: if rng.gen() {
+                        Verdict::Remove
+                    } else {
+                        Verdict::Restore
+                    },
+                },
+            };
+            state = SocialGraph::next_state(&state, &action);
+
+            for (id, user) in &state.users {
+                for followed in &user.following {
+                    assert!(!state.blocked_between(id, followed));
+                    assert_ne!(id, followed);
+                }
+            }
+            for post in state.posts.values() {
+                if post.visibility == Visibility::Visible {
+                    assert!(post.flags.len() < FLAG_THRESHOLD);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,885 @@
+//! A token curated registry. A community keeps a list of things, and token holders decide
+//! together what belongs on it.
+//!
+//! To get something listed, an applicant locks up a deposit. If nobody objects before the
+//! application period ends, the listing joins the registry. Anyone who thinks a listing does not
+//! belong can challenge it by staking tokens of their own, at any time. Token holders then vote
+//! on whether to keep or remove the listing.
+//!
+//! Voting happens in two stages so that nobody can simply follow the crowd. First, voters commit
+//! to a hidden vote by submitting a hash of it along with the tokens they want to vote with.
+//! Later, they reveal the vote and the salt they hashed it with. Only revealed votes count.
+//!
+//! The loser of a challenge forfeits their stake. Part of it goes to the winner, and the rest is
+//! shared among the voters who sided with the winner, in proportion to the tokens they voted
+//! with. Every voter gets their own tokens back regardless.
+//!
+//! Time is measured in blocks and only moves forward when a `Tick` transition is applied. Each
+//! tick whitelists unchallenged applications and settles challenges whose voting has finished.
+
+#[cfg(test)]
+use crate::p1_state_machine::repl::{apply_commands, try_command};
+use crate::p1_state_machine::repl::{parse_number, ReplMachine};
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+use std::collections::BTreeMap;
+
+/// A token curated registry
+pub struct TokenCuratedRegistry;
+
+/// Identifies a token holder
+pub type AccountId = String;
+
+/// Identifies a challenge. Challenges are numbered in the order they were made.
+pub type ChallengeId = u64;
+
+/// The smallest deposit an applicant may lock up, and the stake needed to challenge.
+pub const MIN_DEPOSIT: u64 = 100;
+/// How many blocks an application can be challenged before it is listed automatically
+pub const APPLY_STAGE_LEN: u64 = 3;
+/// How many blocks voters have to commit their votes
+pub const COMMIT_STAGE_LEN: u64 = 2;
+/// How many blocks voters have to reveal their votes once committing is over
+pub const REVEAL_STAGE_LEN: u64 = 2;
+/// The percentage of the loser's stake that goes to the winner of a challenge. The rest goes
+/// to the voters who sided with the winner.
+pub const DISPENSATION_PCT: u64 = 50;
+
+/// Which way a token holder votes in a challenge
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Vote {
+    /// The listing belongs in the registry
+    Keep,
+    /// The listing should be removed
+    Remove,
+}
+
+/// The hash a voter commits to. The salt stops others guessing the vote from the hash.
+pub fn commitment(vote: Vote, salt: u64) -> u64 {
+    crate::hash(&(vote, salt))
+}
+
+/// Something that has applied to the registry, or already been accepted.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Listing {
+    /// Whoever applied, and gets the deposit back when the listing leaves
+    pub owner: AccountId,
+    /// The tokens locked up by the owner
+    pub deposit: u64,
+    /// The block at which the application is accepted if nobody challenges it
+    pub application_end: u64,
+    /// Whether the listing has been accepted into the registry
+    pub whitelisted: bool,
+    /// The challenge currently running against the listing, if any
+    pub challenge: Option<ChallengeId>,
+}
+
+/// A challenge to a listing, and the vote that settles it.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Challenge {
+    /// The name of the challenged listing
+    pub listing: String,
+    /// Whoever made the challenge
+    pub challenger: AccountId,
+    /// Votes may be committed before this block
+    pub commit_end: u64,
+    /// Votes may be revealed from `commit_end` until just before this block. The challenge is
+    /// settled on this block.
+    pub reveal_end: u64,
+    /// The challenger's stake, until the challenge is settled
+    stake: u64,
+    /// The commitment and the tokens of each voter who has not yet claimed
+    commits: BTreeMap<AccountId, (u64, u64)>,
+    /// The votes revealed so far
+    reveals: BTreeMap<AccountId, Vote>,
+    /// The side that won, once the challenge is settled
+    pub winner: Option<Vote>,
+    /// The reward still to be shared among winning voters
+    pool: u64,
+    /// The tokens voted on the winning side by voters who have not yet claimed
+    winning_tokens: u64,
+}
+
+impl Challenge {
+    /// The tokens revealed on one side of the vote
+    pub fn tally(&self, side: Vote) -> u64 {
+        self.reveals
+            .iter()
+            .filter(|(_, vote)| **vote == side)
+            .filter_map(|(voter, _)| self.commits.get(voter))
+            .map(|(_, tokens)| tokens)
+            .sum()
+    }
+}
+
+/// The state of the whole registry
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Registry {
+    /// The current block number
+    block: u64,
+    /// The tokens held by each account and not locked up anywhere
+    balances: BTreeMap<AccountId, u64>,
+    /// Every application and listing, by name
+    listings: BTreeMap<String, Listing>,
+    /// Every challenge that still holds tokens
+    challenges: BTreeMap<ChallengeId, Challenge>,
+    /// The id the next challenge will get
+    next_challenge: ChallengeId,
+}
+
+impl Registry {
+    /// A registry at block zero with no listings, where the given accounts start with the given
+    /// tokens. There is no other way to create tokens.
+    pub fn new(balances: &[(&str, u64)]) -> Self {
+        Self {
+            balances: balances
+                .iter()
+                .map(|(account, amount)| (account.to_string(), *amount))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The current block number.
+    pub fn block(&self) -> u64 {
+        self.block
+    }
+
+    /// The tokens an account holds and has not locked up.
+    pub fn balance(&self, account: &str) -> u64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    /// A listing or application, if there is one with this name.
+    pub fn listing(&self, name: &str) -> Option<&Listing> {
+        self.listings.get(name)
+    }
+
+    /// Whether a listing has been accepted into the registry.
+    pub fn is_whitelisted(&self, name: &str) -> bool {
+        self.listing(name).is_some_and(|l| l.whitelisted)
+    }
+
+    /// A challenge, as long as it still holds tokens.
+    pub fn challenge(&self, id: ChallengeId) -> Option<&Challenge> {
+        self.challenges.get(&id)
+    }
+
+    /// Every token in existence, whether held freely, locked in a listing, or held by a
+    /// challenge. No transition ever changes this.
+    pub fn total_tokens(&self) -> u64 {
+        let challenges: u64 = self
+            .challenges
+            .values()
+            .map(|c| c.stake + c.pool + c.commits.values().map(|(_, t)| t).sum::<u64>())
+            .sum();
+        self.balances.values().sum::<u64>()
+            + self.listings.values().map(|l| l.deposit).sum::<u64>()
+            + challenges
+    }
+
+    /// Take tokens from an account.
+    fn withdraw(&mut self, account: &str, amount: u64) -> Result<(), TcrError> {
+        let balance = self.balance(account);
+        if balance < amount {
+            return Err(TcrError::InsufficientBalance {
+                needed: amount,
+                available: balance,
+            });
+        }
+        self.balances.insert(account.to_string(), balance - amount);
+        Ok(())
+    }
+
+    /// Give tokens to an account.
+    fn deposit(&mut self, account: &str, amount: u64) {
+        *self.balances.entry(account.to_string()).or_insert(0) += amount;
+    }
+
+    /// Look up a challenge that still holds tokens.
+    fn challenge_mut(&mut self, id: ChallengeId) -> Result<&mut Challenge, TcrError> {
+        self.challenges
+            .get_mut(&id)
+            .ok_or(TcrError::UnknownChallenge(id))
+    }
+
+    /// Settle a challenge whose voting has finished. A tie keeps the listing.
+    fn settle(&mut self, id: ChallengeId) {
+        let challenge = &self.challenges[&id];
+        let keep = challenge.tally(Vote::Keep);
+        let remove = challenge.tally(Vote::Remove);
+        let winner = if remove > keep {
+            Vote::Remove
+        } else {
+            Vote::Keep
+        };
+        let winning_tokens = keep.max(remove);
+
+        let challenger = challenge.challenger.clone();
+        let stake = challenge.stake;
+        let name = challenge.listing.clone();
+        let reward = stake * DISPENSATION_PCT / 100;
+        // With no winning voters to share it, the whole stake goes to the winner.
+        let (reward, pool) = if winning_tokens == 0 {
+            (stake, 0)
+        } else {
+            (reward, stake - reward)
+        };
+
+        match winner {
+            Vote::Remove => {
+                // The listing forfeits a stake equal to the challenger's. Anything above that is
+                // returned to the owner.
+                let listing = self
+                    .listings
+                    .remove(&name)
+                    .expect("challenged listings exist until the challenge settles");
+                self.deposit(&listing.owner, listing.deposit - stake);
+                self.deposit(&challenger, stake + reward);
+            }
+            Vote::Keep => {
+                let listing = self
+                    .listings
+                    .get_mut(&name)
+                    .expect("challenged listings exist until the challenge settles");
+                listing.deposit += reward;
+                listing.whitelisted = true;
+                listing.challenge = None;
+            }
+        }
+
+        let challenge = self.challenges.get_mut(&id).expect("looked up above");
+        challenge.stake = 0;
+        challenge.winner = Some(winner);
+        challenge.pool = pool;
+        challenge.winning_tokens = winning_tokens;
+        if challenge.commits.is_empty() {
+            self.challenges.remove(&id);
+        }
+    }
+}
+
+/// Things people do with a token curated registry
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum TcrAction {
+    /// Move on to the next block.
+    Tick,
+    /// Apply to add a listing, locking up a deposit.
+    Apply {
+        account: AccountId,
+        listing: String,
+        deposit: u64,
+    },
+    /// Challenge a listing, staking `MIN_DEPOSIT` tokens.
+    Challenge { account: AccountId, listing: String },
+    /// Commit to a hidden vote, locking up the tokens to vote with.
+    Commit {
+        account: AccountId,
+        challenge: ChallengeId,
+        commitment: u64,
+        tokens: u64,
+    },
+    /// Reveal a committed vote.
+    Reveal {
+        account: AccountId,
+        challenge: ChallengeId,
+        vote: Vote,
+        salt: u64,
+    },
+    /// Take back the tokens voted with, plus a share of the reward for voting with the winner.
+    Claim {
+        account: AccountId,
+        challenge: ChallengeId,
+    },
+    /// Remove your own listing from the registry and take back the deposit.
+    Exit { account: AccountId, listing: String },
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TcrError {
+    /// There is already a listing or application with this name
+    ListingExists,
+    /// There is no listing or application with this name
+    UnknownListing,
+    /// Deposits must be at least `MIN_DEPOSIT`
+    DepositTooLow,
+    /// The account doesn't have enough free tokens
+    InsufficientBalance { needed: u64, available: u64 },
+    /// The listing is already being challenged
+    AlreadyChallenged,
+    /// There is no challenge with this id, or it has been fully settled
+    UnknownChallenge(ChallengeId),
+    /// The commit stage of this challenge is over
+    NotCommitStage,
+    /// The challenge is not in its reveal stage
+    NotRevealStage,
+    /// The account has already committed a vote in this challenge
+    AlreadyCommitted,
+    /// The account never committed a vote in this challenge, or already claimed
+    NotCommitted,
+    /// The account has already revealed its vote
+    AlreadyRevealed,
+    /// The vote and salt do not match the commitment
+    CommitmentMismatch,
+    /// The challenge has not been settled yet
+    NotSettled,
+    /// Only the owner of a listing can do this
+    NotOwner,
+    /// Listings cannot leave while challenged, and applications cannot leave at all
+    CannotExit,
+    /// Votes need some tokens behind them
+    ZeroAmount,
+}
+
+impl TryStateMachine for TokenCuratedRegistry {
+    type State = Registry;
+    type Transition = TcrAction;
+    type Error = TcrError;
+
+    fn try_next_state(starting_state: &Registry, t: &TcrAction) -> Result<Registry, TcrError> {
+        let mut state = starting_state.clone();
+        let block = state.block;
+        match t {
+            TcrAction::Tick => {
+                state.block += 1;
+                let now = state.block;
+                for listing in state.listings.values_mut() {
+                    if listing.challenge.is_none() && listing.application_end <= now {
+                        listing.whitelisted = true;
+                    }
+                }
+                let due: Vec<ChallengeId> = state
+                    .challenges
+                    .iter()
+                    .filter(|(_, c)| c.winner.is_none() && c.reveal_end <= now)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in due {
+                    state.settle(id);
+                }
+            }
+            TcrAction::Apply {
+                account,
+                listing,
+                deposit,
+            } => {
+                if state.listings.contains_key(listing) {
+                    return Err(TcrError::ListingExists);
+                }
+                if *deposit < MIN_DEPOSIT {
+                    return Err(TcrError::DepositTooLow);
+                }
+                state.withdraw(account, *deposit)?;
+                state.listings.insert(
+                    listing.clone(),
+                    Listing {
+                        owner: account.clone(),
+                        deposit: *deposit,
+                        application_end: block + APPLY_STAGE_LEN,
+                        whitelisted: false,
+                        challenge: None,
+                    },
+                );
+            }
+            TcrAction::Challenge { account, listing } => {
+                let id = state.next_challenge;
+                let entry = state
+                    .listings
+                    .get(listing)
+                    .ok_or(TcrError::UnknownListing)?;
+                if entry.challenge.is_some() {
+                    return Err(TcrError::AlreadyChallenged);
+                }
+                state.withdraw(account, MIN_DEPOSIT)?;
+                state
+                    .listings
+                    .get_mut(listing)
+                    .expect("the listing was found above")
+                    .challenge = Some(id);
+                state.challenges.insert(
+                    id,
+                    Challenge {
+                        listing: listing.clone(),
+                        challenger: account.clone(),
+                        commit_end: block + COMMIT_STAGE_LEN,
+                        reveal_end: block + COMMIT_STAGE_LEN + REVEAL_STAGE_LEN,
+                        stake: MIN_DEPOSIT,
+                        commits: BTreeMap::new(),
+                        reveals: BTreeMap::new(),
+                        winner: None,
+                        pool: 0,
+                        winning_tokens: 0,
+                    },
+                );
+                state.next_challenge += 1;
+            }
+            TcrAction::Commit {
+                account,
+                challenge: id,
+                commitment,
+                tokens,
+            } => {
+                if *tokens == 0 {
+                    return Err(TcrError::ZeroAmount);
+                }
+                let challenge = state.challenge_mut(*id)?;
+                if block >= challenge.commit_end {
+                    return Err(TcrError::NotCommitStage);
+                }
+                if challenge.commits.contains_key(account) {
+                    return Err(TcrError::AlreadyCommitted);
+                }
+                state.withdraw(account, *tokens)?;
+                state
+                    .challenge_mut(*id)?
+                    .commits
+                    .insert(account.clone(), (*commitment, *tokens));
+            }
+            TcrAction::Reveal {
+                account,
+                challenge: id,
+                vote,
+                salt,
+            } => {
+                let challenge = state.challenge_mut(*id)?;
+                if block < challenge.commit_end || block >= challenge.reveal_end {
+                    return Err(TcrError::NotRevealStage);
+                }
+                let (committed, _) = challenge
+                    .commits
+                    .get(account)
+                    .ok_or(TcrError::NotCommitted)?;
+                if challenge.reveals.contains_key(account) {
+                    return Err(TcrError::AlreadyRevealed);
+                }
+                if *committed != commitment(*vote, *salt) {
+                    return Err(TcrError::CommitmentMismatch);
+                }
+                challenge.reveals.insert(account.clone(), *vote);
+            }
+            TcrAction::Claim {
+                account,
+                challenge: id,
+            } => {
+                let challenge = state.challenge_mut(*id)?;
+                let winner = challenge.winner.ok_or(TcrError::NotSettled)?;
+                let (_, tokens) = challenge
+                    .commits
+                    .remove(account)
+                    .ok_or(TcrError::NotCommitted)?;
+
+                // Dividing what remains by the winning tokens that remain means the last winner
+                // to claim picks up any rounding dust, and nothing is ever left behind.
+                let mut payout = tokens;
+                if challenge.reveals.get(account) == Some(&winner) {
+                    let reward = challenge.pool * tokens / challenge.winning_tokens;
+                    challenge.pool -= reward;
+                    challenge.winning_tokens -= tokens;
+                    payout += reward;
+                }
+                if challenge.commits.is_empty() {
+                    // Everyone has claimed, so the last winner took whatever was left of the pool.
+                    debug_assert_eq!(challenge.pool, 0);
+                    state.challenges.remove(id);
+                }
+                state.deposit(account, payout);
+            }
+            TcrAction::Exit { account, listing } => {
+                let entry = state
+                    .listings
+                    .get(listing)
+                    .ok_or(TcrError::UnknownListing)?;
+                if entry.owner != *account {
+                    return Err(TcrError::NotOwner);
+                }
+                if !entry.whitelisted || entry.challenge.is_some() {
+                    return Err(TcrError::CannotExit);
+                }
+                let deposit = entry.deposit;
+                state.listings.remove(listing);
+                state.deposit(account, deposit);
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Token Curated Registry".into()
+    }
+}
+
+impl RejectsInPlace for TokenCuratedRegistry {}
+
+/// Parse one word of a repl command as a vote.
+fn parse_vote(words: &[&str], index: usize) -> Result<Vote, String> {
+    match words.get(index) {
+        Some(&"keep") => Ok(Vote::Keep),
+        Some(&"remove") => Ok(Vote::Remove),
+        Some(other) => Err(format!("not a vote: {}", other)),
+        None => Err("missing vote".into()),
+    }
+}
+
+impl ReplMachine for TokenCuratedRegistry {
+    fn initial_state() -> Registry {
+        Registry::new(&[
+            ("alice", 1000),
+            ("bob", 1000),
+            ("carol", 1000),
+            ("dave", 1000),
+        ])
+    }
+
+    fn parse_transition(input: &str) -> Result<TcrAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        if words.first() == Some(&"tick") {
+            return Ok(TcrAction::Tick);
+        }
+        let account = words
+            .get(1)
+            .map(|w| w.to_string())
+            .ok_or("missing account")?;
+        let listing = || words.get(2).map(|w| w.to_string()).ok_or("missing listing");
+
+        match words[0] {
+            "apply" => Ok(TcrAction::Apply {
+                account,
+                listing: listing()?,
+                deposit: parse_number(&words, 3, "deposit")?,
+            }),
+            "challenge" => Ok(TcrAction::Challenge {
+                account,
+                listing: listing()?,
+            }),
+            // The repl hashes the vote for you. A real voter would do this privately.
+            "commit" => Ok(TcrAction::Commit {
+                account,
+                challenge: parse_number(&words, 2, "challenge")?,
+                commitment: commitment(parse_vote(&words, 3)?, parse_number(&words, 4, "salt")?),
+                tokens: parse_number(&words, 5, "tokens")?,
+            }),
+            "reveal" => Ok(TcrAction::Reveal {
+                account,
+                challenge: parse_number(&words, 2, "challenge")?,
+                vote: parse_vote(&words, 3)?,
+                salt: parse_number(&words, 4, "salt")?,
+            }),
+            "claim" => Ok(TcrAction::Claim {
+                account,
+                challenge: parse_number(&words, 2, "challenge")?,
+            }),
+            "exit" => Ok(TcrAction::Exit {
+                account,
+                listing: listing()?,
+            }),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "tick                                       move on to the next block",
+            "apply <who> <listing> <deposit>            apply to be listed",
+            "challenge <who> <listing>                  challenge a listing",
+            "commit <who> <id> <keep|remove> <salt> <tokens>",
+            "reveal <who> <id> <keep|remove> <salt>",
+            "claim <who> <id>                           collect tokens and rewards",
+            "exit <who> <listing>                       leave the registry",
+        ]
+    }
+}
+
+#[cfg(test)]
+fn ticks(n: usize) -> Vec<&'static str> {
+    vec!["tick"; n]
+}
+
+#[cfg(test)]
+fn commit(account: &str, vote: Vote, tokens: u64) -> TcrAction {
+    TcrAction::Commit {
+        account: account.into(),
+        challenge: 0,
+        commitment: commitment(vote, 42),
+        tokens,
+    }
+}
+
+#[cfg(test)]
+fn reveal(account: &str, vote: Vote) -> TcrAction {
+    TcrAction::Reveal {
+        account: account.into(),
+        challenge: 0,
+        vote,
+        salt: 42,
+    }
+}
+
+#[cfg(test)]
+fn claim(account: &str) -> TcrAction {
+    TcrAction::Claim {
+        account: account.into(),
+        challenge: 0,
+    }
+}
+
+/// Alice applies to list "rust" with a deposit of 150, and bob challenges it straight away.
+#[cfg(test)]
+fn challenged() -> Registry {
+    apply_commands::<TokenCuratedRegistry>(
+        &TokenCuratedRegistry::initial_state(),
+        &["apply alice rust 150", "challenge bob rust"],
+    )
+}
+
+#[test]
+fn tcr_unchallenged_application_is_listed() {
+    let state = apply_commands::<TokenCuratedRegistry>(
+        &TokenCuratedRegistry::initial_state(),
+        &["apply alice rust 150"],
+    );
+    assert_eq!(state.balance("alice"), 850);
+
+    let state =
+        apply_commands::<TokenCuratedRegistry>(&state, &ticks(APPLY_STAGE_LEN as usize - 1));
+    assert!(!state.is_whitelisted("rust"));
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(1));
+    assert!(state.is_whitelisted("rust"));
+
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &["exit alice rust"]);
+    assert_eq!(state.listing("rust"), None);
+    assert_eq!(state.balance("alice"), 1000);
+}
+
+#[test]
+fn tcr_challenge_succeeds() {
+    let state = apply_commands::<TokenCuratedRegistry>(
+        &challenged(),
+        &["commit carol 0 remove 42 300", "commit dave 0 keep 42 200"],
+    );
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(COMMIT_STAGE_LEN as usize));
+    let state = apply_commands::<TokenCuratedRegistry>(
+        &state,
+        &["reveal carol 0 remove 42", "reveal dave 0 keep 42"],
+    );
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(REVEAL_STAGE_LEN as usize));
+
+    assert_eq!(state.challenge(0).unwrap().winner, Some(Vote::Remove));
+    assert_eq!(state.listing("rust"), None);
+    // Alice gets back what she deposited above the minimum, and bob wins half the minimum.
+    assert_eq!(state.balance("alice"), 900);
+    assert_eq!(state.balance("bob"), 1050);
+
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &["claim carol 0", "claim dave 0"]);
+    assert_eq!(state.balance("carol"), 1050);
+    assert_eq!(state.balance("dave"), 1000);
+    assert_eq!(state.challenge(0), None);
+    assert_eq!(state.total_tokens(), 4000);
+}
+
+#[test]
+fn tcr_challenge_fails_and_tie_keeps_listing() {
+    let state = apply_commands::<TokenCuratedRegistry>(
+        &challenged(),
+        &["commit carol 0 remove 42 200", "commit dave 0 keep 42 200"],
+    );
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(COMMIT_STAGE_LEN as usize));
+    let state = apply_commands::<TokenCuratedRegistry>(
+        &state,
+        &["reveal carol 0 remove 42", "reveal dave 0 keep 42"],
+    );
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(REVEAL_STAGE_LEN as usize));
+
+    assert!(state.is_whitelisted("rust"));
+    assert_eq!(state.listing("rust").unwrap().deposit, 200);
+    assert_eq!(state.balance("bob"), 900);
+
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &["claim dave 0", "claim carol 0"]);
+    assert_eq!(state.balance("dave"), 1050);
+    assert_eq!(state.balance("carol"), 1000);
+    assert_eq!(state.total_tokens(), 4000);
+}
+
+#[test]
+fn tcr_unrevealed_votes_do_not_count() {
+    let state = apply_commands::<TokenCuratedRegistry>(
+        &challenged(),
+        &["commit carol 0 remove 42 500", "commit dave 0 keep 42 10"],
+    );
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(COMMIT_STAGE_LEN as usize));
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &["reveal dave 0 keep 42"]);
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(REVEAL_STAGE_LEN as usize));
+    assert_eq!(state.challenge(0).unwrap().winner, Some(Vote::Keep));
+
+    // Carol still gets her tokens back, just no reward.
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &["claim carol 0", "claim dave 0"]);
+    assert_eq!(state.balance("carol"), 1000);
+    assert_eq!(state.balance("dave"), 1050);
+}
+
+#[test]
+fn tcr_nobody_votes() {
+    let state = apply_commands::<TokenCuratedRegistry>(&challenged(), &ticks(4));
+    // The listing wins by default and takes the whole stake, so nothing is left to claim.
+    assert!(state.is_whitelisted("rust"));
+    assert_eq!(state.listing("rust").unwrap().deposit, 250);
+    assert_eq!(state.challenge(0), None);
+    assert_eq!(state.total_tokens(), 4000);
+}
+
+#[test]
+fn tcr_voting_stages_are_enforced() {
+    let state =
+        apply_commands::<TokenCuratedRegistry>(&challenged(), &["commit carol 0 remove 42 100"]);
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(&state, &reveal("carol", Vote::Remove)),
+        Err(TcrError::NotRevealStage)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(&state, &commit("carol", Vote::Keep, 1)),
+        Err(TcrError::AlreadyCommitted)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(&state, &claim("carol")),
+        Err(TcrError::NotSettled)
+    );
+
+    let state = apply_commands::<TokenCuratedRegistry>(&state, &ticks(COMMIT_STAGE_LEN as usize));
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(&state, &commit("dave", Vote::Keep, 1)),
+        Err(TcrError::NotCommitStage)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(&state, &reveal("carol", Vote::Keep)),
+        Err(TcrError::CommitmentMismatch)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(&state, &reveal("dave", Vote::Keep)),
+        Err(TcrError::NotCommitted)
+    );
+}
+
+#[test]
+fn tcr_checks_votes_before_taking_tokens() {
+    let state =
+        apply_commands::<TokenCuratedRegistry>(&challenged(), &["commit carol 0 remove 42 100"]);
+    // Nobody has 5000 tokens, but each of these is wrong for a more basic reason.
+    assert_eq!(
+        try_command::<TokenCuratedRegistry>(&state, "commit dave 7 keep 42 5000"),
+        Err(TcrError::UnknownChallenge(7))
+    );
+    assert_eq!(
+        try_command::<TokenCuratedRegistry>(&state, "commit carol 0 keep 42 5000"),
+        Err(TcrError::AlreadyCommitted)
+    );
+    assert_eq!(
+        try_command::<TokenCuratedRegistry>(&state, "commit dave 0 keep 42 5000"),
+        Err(TcrError::InsufficientBalance {
+            needed: 5000,
+            available: 1000
+        })
+    );
+}
+
+#[test]
+fn tcr_rejects_bad_listings() {
+    let state = challenged();
+    let apply_for = |account: &str, deposit| TcrAction::Apply {
+        account: account.into(),
+        listing: "rust".into(),
+        deposit,
+    };
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(&state, &apply_for("carol", 100)),
+        Err(TcrError::ListingExists)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(
+            &TokenCuratedRegistry::initial_state(),
+            &apply_for("carol", 99)
+        ),
+        Err(TcrError::DepositTooLow)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(
+            &state,
+            &TcrAction::Challenge {
+                account: "carol".into(),
+                listing: "rust".into()
+            }
+        ),
+        Err(TcrError::AlreadyChallenged)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::try_next_state(
+            &state,
+            &TcrAction::Exit {
+                account: "alice".into(),
+                listing: "rust".into()
+            }
+        ),
+        Err(TcrError::CannotExit)
+    );
+}
+
+#[test]
+fn tcr_conserves_tokens() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let accounts = ["alice", "bob", "carol", "dave"];
+    let listings = ["rust", "go", "zig"];
+    let mut rng = StdRng::seed_from_u64(3);
+
+    for _ in 0..20 {
+        let mut state = TokenCuratedRegistry::initial_state();
+        for _ in 0..300 {
+            let account = accounts[rng.gen_range(0..accounts.len())].to_string();
+            let listing = listings[rng.gen_range(0..listings.len())].to_string();
+            let challenge = rng.gen_range(0..4);
+            let vote = if rng.gen() { Vote::Keep } else { Vote::Remove };
+            let action = match rng.gen_range(0..10) {
+                0..=2 => TcrAction::Tick,
+                3 => TcrAction::Apply {
+                    account,
+                    listing,
+                    deposit: rng.gen_range(90..200),
+                },
+                4 => TcrAction::Challenge { account, listing },
+                5 => TcrAction::Commit {
+                    account,
+                    challenge,
+                    commitment: commitment(vote, 1),
+                    tokens: rng.gen_range(1..100),
+                },
+                6 | 7 => TcrAction::Reveal {
+                    account,
+                    challenge,
+                    vote,
+                    salt: 1,
+                },
+                8 => TcrAction::Claim { account, challenge },
+                _ => TcrAction::Exit { account, listing },
+            };
+            state = TokenCuratedRegistry::next_state(&state, &action);
+            assert_eq!(state.total_tokens(), 4000);
+        }
+    }
+}
+
+#[test]
+fn tcr_repl_commands() {
+    assert_eq!(
+        TokenCuratedRegistry::parse_transition("tick"),
+        Ok(TcrAction::Tick)
+    );
+    assert_eq!(
+        TokenCuratedRegistry::parse_transition("commit carol 0 remove 42 300"),
+        Ok(commit("carol", Vote::Remove, 300))
+    );
+    assert_eq!(
+        TokenCuratedRegistry::parse_transition("reveal carol 0 remove 42"),
+        Ok(reveal("carol", Vote::Remove))
+    );
+    assert!(TokenCuratedRegistry::parse_transition("reveal carol 0 maybe 42").is_err());
+}
@@ -0,0 +1,515 @@
+//! Tic tac toe. Two players take turns marking cells on a three by three grid, and the first to
+//! get three in a row wins. If the grid fills up with nobody winning, the game is a draw.
+//!
+//! The game is small enough that every reachable position can be explored, so this module also
+//! includes a perfect computer opponent based on minimax.
+
+use crate::p1_state_machine::repl::ReplMachine;
+use crate::p1_state_machine::{FiniteStateMachine, RejectsInPlace, StateMachine, TryStateMachine};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Every line of three cells that wins the game. Cells are numbered from 0 to 8 in reading
+/// order, starting at the top left.
+const LINES: [[usize; 3]; 8] = [
+    [0, 1, 2],
+    [3, 4, 5],
+    [6, 7, 8],
+    [0, 3, 6],
+    [1, 4, 7],
+    [2, 5, 8],
+    [0, 4, 8],
+    [2, 4, 6],
+];
+
+/// A game of tic tac toe between two players.
+pub struct TicTacToe;
+
+/// One of the two players
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Player {
+    X,
+    O,
+}
+
+impl Player {
+    /// The player's opponent
+    pub fn other(&self) -> Player {
+        match self {
+            Player::X => Player::O,
+            Player::O => Player::X,
+        }
+    }
+}
+
+/// The state of a game: what is on the board, and whose turn it is.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Game {
+    /// The mark in each cell, in reading order
+    board: [Option<Player>; 9],
+    /// The player who moves next
+    to_move: Player,
+}
+
+/// How a game stands
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    /// There are still moves to play
+    InProgress,
+    /// This player got three in a row
+    Won(Player),
+    /// The board is full and nobody won
+    Draw,
+}
+
+impl Game {
+    /// An empty board with `X` to move.
+    pub fn new() -> Self {
+        Self {
+            board: [None; 9],
+            to_move: Player::X,
+        }
+    }
+
+    /// The mark in the given cell, if any.
+    pub fn cell(&self, cell: usize) -> Option<Player> {
+        self.board.get(cell).copied().flatten()
+    }
+
+    /// The player who moves next.
+    pub fn to_move(&self) -> Player {
+        self.to_move
+    }
+
+    /// Whether the game has been won, drawn, or is still going.
+    pub fn outcome(&self) -> Outcome {
+        for [a, b, c] in LINES {
+            if let Some(player) = self.board[a] {
+                if self.board[b] == Some(player) && self.board[c] == Some(player) {
+                    return Outcome::Won(player);
+                }
+            }
+        }
+        if self.board.iter().all(Option::is_some) {
+            Outcome::Draw
+        } else {
+            Outcome::InProgress
+        }
+    }
+
+    /// Every move the player to move could legally make. There are none once the game is over.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        if self.outcome() != Outcome::InProgress {
+            return Vec::new();
+        }
+        (0..9)
+            .filter(|cell| self.board[*cell].is_none())
+            .map(|cell| Move {
+                player: self.to_move,
+                cell,
+            })
+            .collect()
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shows the board one row at a time, for example `Game(XO./.X./..., O to move)`.
+impl fmt::Debug for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<String> = self
+            .board
+            .chunks(3)
+            .map(|row| {
+                row.iter()
+                    .map(|cell| match cell {
+                        Some(Player::X) => 'X',
+                        Some(Player::O) => 'O',
+                        None => '.',
+                    })
+                    .collect()
+            })
+            .collect();
+        let status = match self.outcome() {
+            Outcome::InProgress => format!("{:?} to move", self.to_move),
+            Outcome::Won(player) => format!("{:?} won", player),
+            Outcome::Draw => "draw".into(),
+        };
+        write!(f, "Game({}, {})", rows.join("/"), status)
+    }
+}
+
+/// A player marking a cell
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Move {
+    /// The player making the move
+    pub player: Player,
+    /// The cell to mark, from 0 to 8 in reading order
+    pub cell: usize,
+}
+
+/// Why a move was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MoveError {
+    /// Somebody already won, or the board is full.
+    GameOver,
+    /// It is the other player's turn.
+    OutOfTurn,
+    /// There is no cell with this number.
+    NoSuchCell(usize),
+    /// The cell has already been marked.
+    Occupied(usize),
+}
+
+impl TryStateMachine for TicTacToe {
+    type State = Game;
+    type Transition = Move;
+    type Error = MoveError;
+
+    fn try_next_state(starting_state: &Game, t: &Move) -> Result<Game, MoveError> {
+        if starting_state.outcome() != Outcome::InProgress {
+            return Err(MoveError::GameOver);
+        }
+        if t.player != starting_state.to_move {
+            return Err(MoveError::OutOfTurn);
+        }
+        match starting_state.board.get(t.cell) {
+            None => Err(MoveError::NoSuchCell(t.cell)),
+            Some(Some(_)) => Err(MoveError::Occupied(t.cell)),
+            Some(None) => {
+                let mut next = starting_state.clone();
+                next.board[t.cell] = Some(t.player);
+                next.to_move = t.player.other();
+                Ok(next)
+            }
+        }
+    }
+
+    fn human_name() -> String {
+        "Tic Tac Toe".into()
+    }
+}
+
+impl RejectsInPlace for TicTacToe {}
+
+impl FiniteStateMachine for TicTacToe {
+    fn transitions(state: &Game) -> Vec<Move> {
+        state.legal_moves()
+    }
+}
+
+/// Parse a cell typed by a human, numbered from 1 to 9 in reading order.
+fn parse_cell(input: &str) -> Result<usize, String> {
+    match input.parse::<usize>() {
+        Ok(cell) if (1..=9).contains(&cell) => Ok(cell - 1),
+        _ => Err(format!("not a cell from 1 to 9: {}", input)),
+    }
+}
+
+impl ReplMachine for TicTacToe {
+    fn initial_state() -> Game {
+        Game::new()
+    }
+
+    fn parse_transition(input: &str) -> Result<Move, String> {
+        let (player, cell) = input
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("unknown command: {}", input))?;
+        let player = match player {
+            "x" | "X" => Player::X,
+            "o" | "O" => Player::O,
+            _ => return Err(format!("unknown player: {}", player)),
+        };
+        Ok(Move {
+            player,
+            cell: parse_cell(cell.trim())?,
+        })
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "x <cell>     X marks a cell, numbered 1 to 9 from the top left",
+            "o <cell>     O marks a cell",
+        ]
+    }
+}
+
+/// The value of a game for `X`, assuming both players play perfectly from here on. Wins are
+/// positive and losses negative. Quicker wins and slower losses are worth more, so the computer
+/// doesn't toy with its opponent or give up early.
+///
+/// Many different move orders lead to the same position, so values are remembered in `memo`.
+fn minimax(game: &Game, memo: &mut HashMap<Game, i32>) -> i32 {
+    if let Some(value) = memo.get(game) {
+        return *value;
+    }
+    let empty = game.board.iter().filter(|c| c.is_none()).count() as i32;
+    let value = match game.outcome() {
+        Outcome::Won(Player::X) => 1 + empty,
+        Outcome::Won(Player::O) => -1 - empty,
+        Outcome::Draw => 0,
+        Outcome::InProgress => {
+            let values = game
+                .legal_moves()
+                .into_iter()
+                .map(|m| minimax(&TicTacToe::next_state(game, &m), memo));
+            match game.to_move {
+                Player::X => values.max(),
+                Player::O => values.min(),
+            }
+            .expect("a game in progress has a legal move")
+        }
+    };
+    memo.insert(game.clone(), value);
+    value
+}
+
+/// The best move for the player to move, or `None` if the game is over. When several moves are
+/// equally good, the lowest numbered cell is chosen.
+pub fn best_move(game: &Game) -> Option<Move> {
+    let mut memo = HashMap::new();
+    let value = |m: &Move| minimax(&TicTacToe::next_state(game, m), &mut memo);
+    let moves = game.legal_moves();
+    match game.to_move {
+        Player::X => moves.into_iter().rev().max_by_key(value),
+        Player::O => moves.into_iter().min_by_key(value),
+    }
+}
+
+/// Tic tac toe against the computer. The human plays `X`, and after every move the computer
+/// answers as `O` with the best move it can find.
+pub struct AgainstComputer;
+
+impl StateMachine for AgainstComputer {
+    type State = Game;
+    /// The cell the human marks
+    type Transition = usize;
+
+    fn next_state(starting_state: &Game, t: &usize) -> Game {
+        let human = Move {
+            player: starting_state.to_move,
+            cell: *t,
+        };
+        let next = match TicTacToe::try_next_state(starting_state, &human) {
+            Ok(next) => next,
+            Err(_) => return starting_state.clone(),
+        };
+        match best_move(&next) {
+            Some(reply) => TicTacToe::next_state(&next, &reply),
+            None => next,
+        }
+    }
+
+    fn human_name() -> String {
+        "Tic Tac Toe against the computer".into()
+    }
+}
+
+impl ReplMachine for AgainstComputer {
+    fn initial_state() -> Game {
+        Game::new()
+    }
+
+    fn parse_transition(input: &str) -> Result<usize, String> {
+        parse_cell(input)
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec!["<cell>       mark a cell, numbered 1 to 9 from the top left"]
+    }
+}
+
+#[cfg(test)]
+fn play(cells: &[usize]) -> Game {
+    let mut game = Game::new();
+    for cell in cells {
+        game = TicTacToe::try_next_state(
+            &game,
+            &Move {
+                player: game.to_move(),
+                cell: *cell,
+            },
+        )
+        .unwrap();
+    }
+    game
+}
+
+#[test]
+fn tic_tac_toe_first_move() {
+    let game = play(&[4]);
+    assert_eq!(game.cell(4), Some(Player::X));
+    assert_eq!(game.to_move(), Player::O);
+    assert_eq!(format!("{:?}", game), "Game(.../.X./..., O to move)");
+}
+
+#[test]
+fn tic_tac_toe_rejects_occupied_cell() {
+    let game = play(&[4]);
+    assert_eq!(
+        TicTacToe::try_next_state(
+            &game,
+            &Move {
+                player: Player::O,
+                cell: 4
+            }
+        ),
+        Err(MoveError::Occupied(4))
+    );
+}
+
+#[test]
+fn tic_tac_toe_rejects_out_of_turn() {
+    let game = play(&[4]);
+    let out_of_turn = Move {
+        player: Player::X,
+        cell: 0,
+    };
+    assert_eq!(
+        TicTacToe::try_next_state(&game, &out_of_turn),
+        Err(MoveError::OutOfTurn)
+    );
+    // Ignored entirely by the infallible machine
+    assert_eq!(TicTacToe::next_state(&game, &out_of_turn), game);
+}
+
+#[test]
+fn tic_tac_toe_rejects_missing_cell() {
+    assert_eq!(
+        TicTacToe::try_next_state(
+            &Game::new(),
+            &Move {
+                player: Player::X,
+                cell: 9
+            }
+        ),
+        Err(MoveError::NoSuchCell(9))
+    );
+}
+
+#[test]
+fn tic_tac_toe_detects_win() {
+    // X takes the top row while O plays in the middle.
+    let game = play(&[0, 3, 1, 4, 2]);
+    assert_eq!(game.outcome(), Outcome::Won(Player::X));
+    assert!(game.legal_moves().is_empty());
+    assert_eq!(
+        TicTacToe::try_next_state(
+            &game,
+            &Move {
+                player: Player::O,
+                cell: 5
+            }
+        ),
+        Err(MoveError::GameOver)
+    );
+}
+
+#[test]
+fn tic_tac_toe_detects_diagonal_win() {
+    let game = play(&[0, 2, 3, 4, 8, 6]);
+    assert_eq!(game.outcome(), Outcome::Won(Player::O));
+}
+
+#[test]
+fn tic_tac_toe_detects_draw() {
+    // X O X
+    // X O O
+    // O X X
+    let game = play(&[0, 1, 2, 4, 3, 5, 7, 6, 8]);
+    assert_eq!(game.outcome(), Outcome::Draw);
+    assert_eq!(format!("{:?}", game), "Game(XOX/XOO/OXX, draw)");
+}
+
+#[test]
+fn tic_tac_toe_reachable_positions() {
+    use crate::p1_state_machine::model_check::ModelChecker;
+
+    let report = ModelChecker::<TicTacToe>::new()
+        .state_invariant("players alternate", |game| {
+            let count = |p| game.board.iter().filter(|c| **c == Some(p)).count();
+            let (x, o) = (count(Player::X), count(Player::O));
+            x == o || x == o + 1
+        })
+        .state_invariant("at most one winner", |game| {
+            let winners = LINES
+                .iter()
+                .filter_map(|[a, b, c]| {
+                    let p = game.board[*a]?;
+                    (game.board[*b] == Some(p) && game.board[*c] == Some(p)).then_some(p)
+                })
+                .collect::<std::collections::HashSet<_>>();
+            winners.len() <= 1
+        })
+        .check(&Game::new())
+        .unwrap();
+
+    // The well known number of legal tic tac toe positions
+    assert_eq!(report.states, 5478);
+}
+
+#[test]
+fn tic_tac_toe_perfect_play_is_a_draw() {
+    let mut game = Game::new();
+    while let Some(m) = best_move(&game) {
+        game = TicTacToe::next_state(&game, &m);
+    }
+    assert_eq!(game.outcome(), Outcome::Draw);
+}
+
+#[test]
+fn tic_tac_toe_best_move_takes_win() {
+    // X can win in the top row, or block O in the middle row. Winning is better.
+    let game = play(&[0, 3, 1, 4]);
+    assert_eq!(
+        best_move(&game),
+        Some(Move {
+            player: Player::X,
+            cell: 2
+        })
+    );
+}
+
+#[test]
+fn tic_tac_toe_best_move_blocks() {
+    // O must block the top row.
+    let game = play(&[0, 4, 1]);
+    assert_eq!(best_move(&game).map(|m| m.cell), Some(2));
+}
+
+#[test]
+fn tic_tac_toe_best_move_breaks_ties_by_lowest_cell() {
+    // Against a centre opening every corner holds the draw, so O takes the first one.
+    assert_eq!(best_move(&play(&[4])).map(|m| m.cell), Some(0));
+    // Against a corner opening only the centre holds, whichever corner X took.
+    assert_eq!(best_move(&play(&[8])).map(|m| m.cell), Some(4));
+}
+
+#[test]
+fn tic_tac_toe_computer_answers() {
+    let game = AgainstComputer::next_state(&Game::new(), &0);
+    assert_eq!(game.cell(0), Some(Player::X));
+    // The only move that doesn't lose against a corner opening is the centre.
+    assert_eq!(game.cell(4), Some(Player::O));
+    assert_eq!(game.to_move(), Player::X);
+
+    // Illegal moves are ignored, and the computer doesn't get a free move.
+    assert_eq!(AgainstComputer::next_state(&game, &4), game);
+}
+
+#[test]
+fn tic_tac_toe_repl_commands() {
+    assert_eq!(
+        TicTacToe::parse_transition("o 9"),
+        Ok(Move {
+            player: Player::O,
+            cell: 8
+        })
+    );
+    assert!(TicTacToe::parse_transition("x 0").is_err());
+    assert_eq!(AgainstComputer::parse_transition("5"), Ok(4));
+}
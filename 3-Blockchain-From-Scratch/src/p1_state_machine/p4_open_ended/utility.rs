@@ -0,0 +1,498 @@
+//! A public utility, such as a water or electricity provider, billing its customers.
+//!
+//! Customers open an account, consume metered units, and receive a bill at the end of every
+//! billing cycle. A bill that is still not fully paid when the next cycle ends is late, and a late
+//! fee is added to it. After too many late cycles in a row the account is disconnected and its
+//! meter stops accepting readings until everything owed has been paid.
+//!
+//! The provider can change its tariff at any time. Consumption is priced when it is metered, so
+//! a new tariff only ever applies to consumption from then on, never to units already used.
+//!
+//! All money is counted in whole cents and every calculation is integer arithmetic, so every
+//! node computes exactly the same bills. Anything that would overflow is rejected instead, except
+//! for the end of a billing cycle, which can't be rejected on behalf of a single customer. A bill
+//! that would overflow stops at the most a `u64` can count.
+
+#[cfg(test)]
+use crate::p1_state_machine::repl::{apply_commands, try_command};
+use crate::p1_state_machine::repl::{parse_number, ReplMachine};
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+use std::collections::BTreeMap;
+
+/// A public utility provider
+pub struct UtilityProvider;
+
+/// Identifies a customer
+pub type AccountId = String;
+
+/// The late fee, as a percentage of the amount overdue, rounded down
+pub const LATE_FEE_PCT: u64 = 5;
+/// How many late cycles in a row it takes to be disconnected
+pub const DISCONNECT_AFTER: u32 = 2;
+
+/// Whether a customer is being supplied
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Supply {
+    #[default]
+    Connected,
+    Disconnected,
+}
+
+/// A customer's account with the utility
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Account {
+    pub supply: Supply,
+    /// The cost of consumption metered since the last bill
+    pub unbilled: u64,
+    /// Billed charges and late fees not yet paid
+    pub owed: u64,
+    /// How many cycles in a row a bill has been late
+    pub late_cycles: u32,
+}
+
+/// The state of the utility
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Utility {
+    /// The price of a single unit, in cents
+    tariff: u64,
+    /// How many billing cycles have ended
+    cycle: u64,
+    accounts: BTreeMap<AccountId, Account>,
+    /// Everything ever paid to the utility
+    collected: u64,
+}
+
+impl Utility {
+    /// A utility with no customers, charging the given number of cents per unit.
+    pub fn new(tariff: u64) -> Self {
+        Self {
+            tariff,
+            ..Default::default()
+        }
+    }
+
+    /// The current price of a single unit, in cents.
+    pub fn tariff(&self) -> u64 {
+        self.tariff
+    }
+
+    /// How many billing cycles have ended.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// A customer's account, if it is open.
+    pub fn account(&self, account: &str) -> Option<&Account> {
+        self.accounts.get(account)
+    }
+
+    /// Everything ever paid to the utility.
+    pub fn collected(&self) -> u64 {
+        self.collected
+    }
+
+    /// Everything customers owe, whether billed yet or not. Each account's balances fit in a
+    /// `u64`, but their total across every account may not, so it is counted in a `u128`.
+    pub fn total_outstanding(&self) -> u128 {
+        self.accounts
+            .values()
+            .map(|a| a.owed as u128 + a.unbilled as u128)
+            .sum()
+    }
+
+    fn open_account(&mut self, account: &str) -> Result<&mut Account, UtilityError> {
+        self.accounts
+            .get_mut(account)
+            .ok_or(UtilityError::NoSuchAccount)
+    }
+
+    /// End the billing cycle for one account.
+    fn bill(account: &mut Account) {
+        if account.owed > 0 {
+            // Split up so that the multiplication cannot overflow
+            let fee = account.owed / 100 * LATE_FEE_PCT + account.owed % 100 * LATE_FEE_PCT / 100;
+            account.owed = account.owed.saturating_add(fee);
+            account.late_cycles = account.late_cycles.saturating_add(1);
+            if account.late_cycles >= DISCONNECT_AFTER {
+                account.supply = Supply::Disconnected;
+            }
+        }
+        account.owed = account.owed.saturating_add(account.unbilled);
+        account.unbilled = 0;
+    }
+}
+
+/// Things that happen at a utility
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum UtilityAction {
+    /// Open a new account.
+    Open { account: AccountId },
+    /// Close an account with nothing outstanding.
+    Close { account: AccountId },
+    /// Record units consumed by a customer at the current tariff.
+    Meter { account: AccountId, units: u64 },
+    /// End the billing cycle, billing every customer for their consumption.
+    Bill,
+    /// Pay towards what you owe.
+    Pay { account: AccountId, amount: u64 },
+    /// Change the price of future consumption.
+    SetTariff { tariff: u64 },
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UtilityError {
+    /// There is already an open account with this name
+    AccountExists,
+    /// There is no open account with this name
+    NoSuchAccount,
+    /// Disconnected accounts cannot consume anything
+    Disconnected,
+    /// Accounts can only be closed once everything has been billed and paid
+    Outstanding(u64),
+    /// Payments cannot be more than what has been billed
+    Overpayment { owed: u64 },
+    /// The amounts involved are too large
+    Overflow,
+}
+
+impl TryStateMachine for UtilityProvider {
+    type State = Utility;
+    type Transition = UtilityAction;
+    type Error = UtilityError;
+
+    fn try_next_state(
+        starting_state: &Utility,
+        t: &UtilityAction,
+    ) -> Result<Utility, UtilityError> {
+        let mut state = starting_state.clone();
+        match t {
+            UtilityAction::Open { account } => {
+                if state.accounts.contains_key(account) {
+                    return Err(UtilityError::AccountExists);
+                }
+                state.accounts.insert(account.clone(), Account::default());
+            }
+            UtilityAction::Close { account } => {
+                let open = state.open_account(account)?;
+                let outstanding = open.owed.saturating_add(open.unbilled);
+                if outstanding > 0 {
+                    return Err(UtilityError::Outstanding(outstanding));
+                }
+                state.accounts.remove(account);
+            }
+            UtilityAction::Meter { account, units } => {
+                let tariff = state.tariff;
+                let open = state.open_account(account)?;
+                if open.supply == Supply::Disconnected {
+                    return Err(UtilityError::Disconnected);
+                }
+                open.unbilled = units
+                    .checked_mul(tariff)
+                    .and_then(|cost| open.unbilled.checked_add(cost))
+                    .ok_or(UtilityError::Overflow)?;
+            }
+            UtilityAction::Bill => {
+                for account in state.accounts.values_mut() {
+                    Utility::bill(account);
+                }
+                state.cycle += 1;
+            }
+            UtilityAction::Pay { account, amount } => {
+                let open = state.open_account(account)?;
+                if *amount > open.owed {
+                    return Err(UtilityError::Overpayment { owed: open.owed });
+                }
+                open.owed -= amount;
+                // Paying everything off forgives the late history and restores supply.
+                if open.owed == 0 {
+                    open.late_cycles = 0;
+                    open.supply = Supply::Connected;
+                }
+                state.collected = state
+                    .collected
+                    .checked_add(*amount)
+                    .ok_or(UtilityError::Overflow)?;
+            }
+            UtilityAction::SetTariff { tariff } => state.tariff = *tariff,
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Utility Provider".into()
+    }
+}
+
+impl RejectsInPlace for UtilityProvider {}
+
+impl ReplMachine for UtilityProvider {
+    fn initial_state() -> Utility {
+        Utility::new(25)
+    }
+
+    fn parse_transition(input: &str) -> Result<UtilityAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let account = || -> Result<AccountId, String> {
+            Ok(words.get(1).ok_or("missing account")?.to_string())
+        };
+        match words.first() {
+            Some(&"open") => Ok(UtilityAction::Open {
+                account: account()?,
+            }),
+            Some(&"close") => Ok(UtilityAction::Close {
+                account: account()?,
+            }),
+            Some(&"meter") => Ok(UtilityAction::Meter {
+                account: account()?,
+                units: parse_number(&words, 2, "units")?,
+            }),
+            Some(&"bill") => Ok(UtilityAction::Bill),
+            Some(&"pay") => Ok(UtilityAction::Pay {
+                account: account()?,
+                amount: parse_number(&words, 2, "amount")?,
+            }),
+            Some(&"tariff") => Ok(UtilityAction::SetTariff {
+                tariff: parse_number(&words, 1, "tariff")?,
+            }),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "open <who>            open an account",
+            "close <who>           close an account with nothing outstanding",
+            "meter <who> <units>   record consumption",
+            "bill                  end the billing cycle",
+            "pay <who> <cents>     pay towards a bill",
+            "tariff <cents>        change the price of a unit",
+        ]
+    }
+}
+
+#[test]
+fn utility_bills_metered_consumption() {
+    let state = apply_commands::<UtilityProvider>(
+        &Utility::new(25),
+        &["open alice", "meter alice 10", "meter alice 30"],
+    );
+    assert_eq!(state.account("alice").unwrap().unbilled, 1000);
+    assert_eq!(state.account("alice").unwrap().owed, 0);
+
+    let state = apply_commands::<UtilityProvider>(&state, &["bill"]);
+    assert_eq!(state.cycle(), 1);
+    assert_eq!(state.account("alice").unwrap().unbilled, 0);
+    assert_eq!(state.account("alice").unwrap().owed, 1000);
+
+    let state = apply_commands::<UtilityProvider>(&state, &["pay alice 1000"]);
+    assert_eq!(state.account("alice").unwrap().owed, 0);
+    assert_eq!(state.collected(), 1000);
+}
+
+#[test]
+fn utility_tariff_changes_only_apply_to_future_consumption() {
+    let state = apply_commands::<UtilityProvider>(
+        &Utility::new(25),
+        &[
+            "open alice",
+            "meter alice 10",
+            "tariff 40",
+            "meter alice 10",
+            "bill",
+        ],
+    );
+    assert_eq!(state.tariff(), 40);
+    assert_eq!(state.account("alice").unwrap().owed, 650);
+}
+
+#[test]
+fn utility_late_fees_and_disconnection() {
+    let state = apply_commands::<UtilityProvider>(
+        &Utility::new(10),
+        &["open alice", "meter alice 100", "bill", "pay alice 999"],
+    );
+    assert_eq!(state.account("alice").unwrap().owed, 1);
+
+    // One cent late is still late, and five percent of one cent rounds down to nothing.
+    let state = apply_commands::<UtilityProvider>(&state, &["meter alice 100", "bill"]);
+    assert_eq!(
+        state.account("alice").unwrap(),
+        &Account {
+            supply: Supply::Connected,
+            unbilled: 0,
+            owed: 1001,
+            late_cycles: 1,
+        }
+    );
+
+    let state = apply_commands::<UtilityProvider>(&state, &["bill"]);
+    assert_eq!(
+        state.account("alice").unwrap(),
+        &Account {
+            supply: Supply::Disconnected,
+            unbilled: 0,
+            owed: 1051,
+            late_cycles: 2,
+        }
+    );
+    assert_eq!(
+        try_command::<UtilityProvider>(&state, "meter alice 1"),
+        Err(UtilityError::Disconnected)
+    );
+
+    // Partial payment is not enough to reconnect.
+    let state = apply_commands::<UtilityProvider>(&state, &["pay alice 1000"]);
+    assert_eq!(state.account("alice").unwrap().supply, Supply::Disconnected);
+    let state = apply_commands::<UtilityProvider>(&state, &["pay alice 51", "meter alice 1"]);
+    assert_eq!(state.account("alice").unwrap().supply, Supply::Connected);
+    assert_eq!(state.account("alice").unwrap().late_cycles, 0);
+}
+
+#[test]
+fn utility_rejects_overpayment() {
+    let state =
+        apply_commands::<UtilityProvider>(&Utility::new(10), &["open alice", "meter alice 5"]);
+    // Unbilled consumption cannot be paid for yet.
+    assert_eq!(
+        try_command::<UtilityProvider>(&state, "pay alice 1"),
+        Err(UtilityError::Overpayment { owed: 0 })
+    );
+}
+
+#[test]
+fn utility_open_and_close_accounts() {
+    let state =
+        apply_commands::<UtilityProvider>(&Utility::new(10), &["open alice", "meter alice 5"]);
+    assert_eq!(
+        try_command::<UtilityProvider>(&state, "open alice"),
+        Err(UtilityError::AccountExists)
+    );
+    assert_eq!(
+        try_command::<UtilityProvider>(&state, "close alice"),
+        Err(UtilityError::Outstanding(50))
+    );
+    assert_eq!(
+        try_command::<UtilityProvider>(&state, "meter bob 5"),
+        Err(UtilityError::NoSuchAccount)
+    );
+
+    let state = apply_commands::<UtilityProvider>(&state, &["bill", "pay alice 50", "close alice"]);
+    assert_eq!(state.account("alice"), None);
+    // A closed account can be opened again from scratch.
+    let state = apply_commands::<UtilityProvider>(&state, &["open alice"]);
+    assert_eq!(state.account("alice"), Some(&Account::default()));
+}
+
+#[test]
+fn utility_rejects_overflow() {
+    let state = apply_commands::<UtilityProvider>(
+        &Utility::new(u64::MAX),
+        &["open alice", "meter alice 1"],
+    );
+    assert_eq!(
+        try_command::<UtilityProvider>(&state, "meter alice 1"),
+        Err(UtilityError::Overflow)
+    );
+    assert_eq!(
+        try_command::<UtilityProvider>(&state, "meter alice 2"),
+        Err(UtilityError::Overflow)
+    );
+
+    // Several accounts may each owe nearly everything a u64 can count.
+    let state = apply_commands::<UtilityProvider>(
+        &Utility::new(u64::MAX / 2),
+        &[
+            "open alice",
+            "open bob",
+            "meter alice 2",
+            "bill",
+            "meter alice 1",
+            "meter bob 2",
+        ],
+    );
+    assert_eq!(state.total_outstanding(), (u64::MAX / 2) as u128 * 5);
+}
+
+#[test]
+fn utility_bill_caps_an_overflowing_account() {
+    let state = apply_commands::<UtilityProvider>(
+        &Utility::new(u64::MAX / 2),
+        &[
+            "open alice",
+            "open bob",
+            "meter alice 2",
+            "bill",
+            "meter alice 1",
+            "meter bob 1",
+        ],
+    );
+
+    // Alice's late fee and new charges don't fit, but Bob is still billed.
+    let state = apply_commands::<UtilityProvider>(&state, &["bill"]);
+    assert_eq!(state.cycle(), 2);
+    assert_eq!(state.account("alice").unwrap().owed, u64::MAX);
+    assert_eq!(state.account("alice").unwrap().late_cycles, 1);
+    assert_eq!(state.account("bob").unwrap().owed, u64::MAX / 2);
+    assert_eq!(state.account("bob").unwrap().unbilled, 0);
+}
+
+#[test]
+fn utility_random_actions_account_for_every_cent() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let accounts = ["alice", "bob", "carol"];
+    let mut rng = StdRng::seed_from_u64(19);
+
+    for _ in 0..20 {
+        let mut state = Utility::new(rng.gen_range(1..50));
+        // Everything ever charged to customers, at the tariff of the time, plus late fees
+        let mut charged = 0;
+        for _ in 0..300 {
+            let account = accounts[rng.gen_range(0..accounts.len())].to_string();
+            let action = match rng.gen_range(0..10) {
+                0 => UtilityAction::Open { account },
+                1 => UtilityAction::Close { account },
+                2..=4 => UtilityAction::Meter {
+                    account,
+                    units: rng.gen_range(0..100),
+                },
+                5 => UtilityAction::Bill,
+                6 | 7 => UtilityAction::Pay {
+                    account,
+                    amount: rng.gen_range(0..3000),
+                },
+                _ => UtilityAction::SetTariff {
+                    tariff: rng.gen_range(1..50),
+                },
+            };
+
+            let next = UtilityProvider::next_state(&state, &action);
+            match &action {
+                UtilityAction::Meter { units, .. } if next != state => {
+                    charged += units * state.tariff();
+                }
+                UtilityAction::Bill => {
+                    for (name, before) in &state.accounts {
+                        charged += next.account(name).unwrap().owed - before.owed - before.unbilled;
+                    }
+                }
+                _ => {}
+            }
+            state = next;
+
+            assert_eq!(
+                charged as u128,
+                state.collected() as u128 + state.total_outstanding()
+            );
+            for account in state.accounts.values() {
+                assert_eq!(
+                    account.supply == Supply::Disconnected,
+                    account.late_cycles >= DISCONNECT_AFTER
+                );
+            }
+        }
+    }
+}
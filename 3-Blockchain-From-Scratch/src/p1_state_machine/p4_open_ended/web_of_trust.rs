@@ -0,0 +1,526 @@
+//! A web of trust. Identities vouch for one another by publishing signed trust edges, each with
+//! a weight saying how much they trust the other party. Edges can be revoked at any time.
+//!
+//! A small set of root identities is trusted completely. Everyone else earns a trust score from
+//! their best chain of vouches back to a root. Trust decays with every hop and is scaled by the
+//! weight of every edge along the way, so a long chain of lukewarm vouches is worth little. Only
+//! the best chain counts, so creating many fake identities that vouch for each other gains
+//! nothing unless a trusted identity vouches for one of them.
+//!
+//! The scores decide who may become an authority in a proof of authority network.
+//!
+//! This crate has no public key cryptography, so signatures are modelled as a hash of the
+//! signer's key and the message. Anybody who can read the state could forge one, but the
+//! machine checks them exactly where a real chain would. Every signed action also carries the
+//! signer's next nonce, so that an old signature cannot be replayed.
+
+use crate::p1_state_machine::repl::{parse_number, ReplMachine};
+#[cfg(test)]
+use crate::p1_state_machine::StateMachine;
+use crate::p1_state_machine::{RejectsInPlace, TryStateMachine};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::hash::Hash;
+
+/// A web of trust
+pub struct WebOfTrust;
+
+/// Identifies a participant
+pub type IdentityId = String;
+
+/// The score of a root. Scores are integers so every node computes exactly the same ones.
+pub const MAX_SCORE: u64 = 1_000_000;
+/// The percentage of trust that survives each hop along a chain of vouches
+pub const DECAY_PCT: u64 = 80;
+/// The lowest trust score that allows an identity to become an authority
+pub const AUTHORITY_THRESHOLD: u64 = 500_000;
+
+/// Sign a message with a key.
+pub fn sign<M: Hash>(key: u64, message: &M) -> u64 {
+    crate::hash(&(key, message))
+}
+
+/// The state of the web of trust
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct TrustGraph {
+    /// The key of every registered identity
+    keys: BTreeMap<IdentityId, u64>,
+    /// The nonce each identity must use for its next signed action
+    nonces: BTreeMap<IdentityId, u64>,
+    /// The identities that are trusted completely
+    roots: BTreeSet<IdentityId>,
+    /// How much one identity trusts another, as a percentage from 1 to 100
+    edges: BTreeMap<(IdentityId, IdentityId), u8>,
+}
+
+impl TrustGraph {
+    /// A web of trust containing only the given roots, each with its key.
+    pub fn new(roots: &[(&str, u64)]) -> Self {
+        Self {
+            keys: roots
+                .iter()
+                .map(|(id, key)| (id.to_string(), *key))
+                .collect(),
+            roots: roots.iter().map(|(id, _)| id.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether an identity has been registered.
+    pub fn is_registered(&self, identity: &str) -> bool {
+        self.keys.contains_key(identity)
+    }
+
+    /// The nonce an identity must use for its next signed action.
+    pub fn nonce(&self, identity: &str) -> u64 {
+        self.nonces.get(identity).copied().unwrap_or(0)
+    }
+
+    /// How much one identity directly trusts another, if at all.
+    pub fn edge(&self, from: &str, to: &str) -> Option<u8> {
+        self.edges.get(&(from.to_string(), to.to_string())).copied()
+    }
+
+    /// The trust score of every identity reachable from a root.
+    ///
+    /// Each identity's score is the best product of edge weights and decay along any chain from
+    /// a root. Because trust only ever shrinks along a chain, the best chains can be found the
+    /// same way as shortest paths, always expanding the most trusted identity next.
+    pub fn scores(&self) -> BTreeMap<IdentityId, u64> {
+        let mut scores = BTreeMap::new();
+        // Ties are broken by identity, so the result never depends on insertion order.
+        let mut frontier: BinaryHeap<(u64, Reverse<&IdentityId>)> =
+            self.roots.iter().map(|r| (MAX_SCORE, Reverse(r))).collect();
+
+        while let Some((score, Reverse(identity))) = frontier.pop() {
+            if scores.contains_key(identity) {
+                continue;
+            }
+            scores.insert(identity.clone(), score);
+            let outgoing = self
+                .edges
+                .range((identity.clone(), String::new())..)
+                .take_while(|((from, _), _)| from == identity);
+            for ((_, to), weight) in outgoing {
+                if !scores.contains_key(to) {
+                    let next = score * *weight as u64 * DECAY_PCT / 10_000;
+                    frontier.push((next, Reverse(to)));
+                }
+            }
+        }
+        scores
+    }
+
+    /// The trust score of a single identity. Identities with no chain to a root score zero.
+    pub fn trust_score(&self, identity: &str) -> u64 {
+        self.scores().get(identity).copied().unwrap_or(0)
+    }
+
+    /// Every identity trusted enough to be an authority.
+    pub fn authorities(&self) -> BTreeSet<IdentityId> {
+        self.scores()
+            .into_iter()
+            .filter(|(_, score)| *score >= AUTHORITY_THRESHOLD)
+            .map(|(identity, _)| identity)
+            .collect()
+    }
+
+    /// Whether an identity is trusted enough to be an authority.
+    pub fn may_be_authority(&self, identity: &str) -> bool {
+        self.trust_score(identity) >= AUTHORITY_THRESHOLD
+    }
+}
+
+/// Things identities do in a web of trust
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum TrustAction {
+    /// Join the web of trust. Anyone may register, but nobody trusts a new identity yet.
+    Register { identity: IdentityId, key: u64 },
+    /// Vouch for another identity, replacing any earlier edge between them.
+    Trust {
+        from: IdentityId,
+        to: IdentityId,
+        weight: u8,
+        nonce: u64,
+        signature: u64,
+    },
+    /// Withdraw a vouch.
+    Revoke {
+        from: IdentityId,
+        to: IdentityId,
+        nonce: u64,
+        signature: u64,
+    },
+}
+
+impl TrustAction {
+    /// A trust edge signed with the truster's key.
+    pub fn trust(key: u64, from: &str, to: &str, weight: u8, nonce: u64) -> Self {
+        Self::Trust {
+            from: from.into(),
+            to: to.into(),
+            weight,
+            nonce,
+            signature: sign(key, &(from, to, Some(weight), nonce)),
+        }
+    }
+
+    /// A revocation signed with the truster's key.
+    pub fn revoke(key: u64, from: &str, to: &str, nonce: u64) -> Self {
+        Self::Revoke {
+            from: from.into(),
+            to: to.into(),
+            nonce,
+            signature: sign(key, &(from, to, None::<u8>, nonce)),
+        }
+    }
+}
+
+/// Why an action was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TrustError {
+    /// There is already an identity with this name
+    IdentityExists,
+    /// There is no identity with this name
+    UnknownIdentity(IdentityId),
+    /// The signature does not match the signer's key and the message
+    BadSignature,
+    /// The nonce is not the signer's next one
+    BadNonce { expected: u64, found: u64 },
+    /// Weights must be between 1 and 100
+    BadWeight(u8),
+    /// Identities cannot vouch for themselves
+    SelfTrust,
+    /// There is no edge to revoke
+    NoSuchEdge,
+}
+
+impl TrustGraph {
+    /// Check the signature and nonce of a signed action, and use up the nonce.
+    fn verify<M: Hash>(
+        &mut self,
+        signer: &str,
+        nonce: u64,
+        message: &M,
+        signature: u64,
+    ) -> Result<(), TrustError> {
+        let key = *self
+            .keys
+            .get(signer)
+            .ok_or_else(|| TrustError::UnknownIdentity(signer.into()))?;
+        let expected = self.nonce(signer);
+        if nonce != expected {
+            return Err(TrustError::BadNonce {
+                expected,
+                found: nonce,
+            });
+        }
+        if sign(key, message) != signature {
+            return Err(TrustError::BadSignature);
+        }
+        self.nonces.insert(signer.into(), expected + 1);
+        Ok(())
+    }
+}
+
+impl TryStateMachine for WebOfTrust {
+    type State = TrustGraph;
+    type Transition = TrustAction;
+    type Error = TrustError;
+
+    fn try_next_state(
+        starting_state: &TrustGraph,
+        t: &TrustAction,
+    ) -> Result<TrustGraph, TrustError> {
+        let mut state = starting_state.clone();
+        match t {
+            TrustAction::Register { identity, key } => {
+                if state.is_registered(identity) {
+                    return Err(TrustError::IdentityExists);
+                }
+                state.keys.insert(identity.clone(), *key);
+            }
+            TrustAction::Trust {
+                from,
+                to,
+                weight,
+                nonce,
+                signature,
+            } => {
+                state.verify(
+                    from,
+                    *nonce,
+                    &(from.as_str(), to.as_str(), Some(*weight), *nonce),
+                    *signature,
+                )?;
+                if !state.is_registered(to) {
+                    return Err(TrustError::UnknownIdentity(to.clone()));
+                }
+                if from == to {
+                    return Err(TrustError::SelfTrust);
+                }
+                if !(1..=100).contains(weight) {
+                    return Err(TrustError::BadWeight(*weight));
+                }
+                state.edges.insert((from.clone(), to.clone()), *weight);
+            }
+            TrustAction::Revoke {
+                from,
+                to,
+                nonce,
+                signature,
+            } => {
+                state.verify(
+                    from,
+                    *nonce,
+                    &(from.as_str(), to.as_str(), None::<u8>, *nonce),
+                    *signature,
+                )?;
+                state
+                    .edges
+                    .remove(&(from.clone(), to.clone()))
+                    .ok_or(TrustError::NoSuchEdge)?;
+            }
+        }
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Web of Trust".into()
+    }
+}
+
+impl RejectsInPlace for WebOfTrust {}
+
+/// The key the repl signs with on behalf of an identity. Real users would keep their keys to
+/// themselves, but in the repl everybody's key is simply derived from their name.
+pub fn demo_key(identity: &str) -> u64 {
+    crate::hash(&identity)
+}
+
+impl ReplMachine for WebOfTrust {
+    fn initial_state() -> TrustGraph {
+        TrustGraph::new(&[("root", demo_key("root"))])
+    }
+
+    fn parse_transition(input: &str) -> Result<TrustAction, String> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let from = *words.get(1).ok_or("missing identity")?;
+        match words[0] {
+            "register" => Ok(TrustAction::Register {
+                identity: from.into(),
+                key: demo_key(from),
+            }),
+            "trust" => Ok(TrustAction::trust(
+                demo_key(from),
+                from,
+                words.get(2).ok_or("missing identity to trust")?,
+                parse_number(&words, 3, "weight")?,
+                parse_number(&words, 4, "nonce")?,
+            )),
+            "revoke" => Ok(TrustAction::revoke(
+                demo_key(from),
+                from,
+                words.get(2).ok_or("missing identity to stop trusting")?,
+                parse_number(&words, 3, "nonce")?,
+            )),
+            _ => Err(format!("unknown command: {}", input)),
+        }
+    }
+
+    fn commands() -> Vec<&'static str> {
+        vec![
+            "register <who>                       join the web of trust",
+            "trust <who> <whom> <weight> <nonce>  vouch for someone, weight 1 to 100",
+            "revoke <who> <whom> <nonce>          withdraw a vouch",
+        ]
+    }
+}
+
+/// Root vouches fully for alice, alice vouches for bob at 75, and bob for carol at 100.
+#[cfg(test)]
+fn chain() -> TrustGraph {
+    let mut state = TrustGraph::new(&[("root", 1)]);
+    for (identity, key) in [("alice", 2), ("bob", 3), ("carol", 4), ("mallory", 5)] {
+        state = WebOfTrust::try_next_state(
+            &state,
+            &TrustAction::Register {
+                identity: identity.into(),
+                key,
+            },
+        )
+        .unwrap();
+    }
+    for action in [
+        TrustAction::trust(1, "root", "alice", 100, 0),
+        TrustAction::trust(2, "alice", "bob", 75, 0),
+        TrustAction::trust(3, "bob", "carol", 100, 0),
+    ] {
+        state = WebOfTrust::try_next_state(&state, &action).unwrap();
+    }
+    state
+}
+
+#[test]
+fn web_of_trust_scores_decay_along_chain() {
+    let state = chain();
+    assert_eq!(state.trust_score("root"), MAX_SCORE);
+    assert_eq!(state.trust_score("alice"), 800_000);
+    assert_eq!(state.trust_score("bob"), 480_000);
+    assert_eq!(state.trust_score("carol"), 384_000);
+    assert_eq!(state.trust_score("mallory"), 0);
+    assert_eq!(state.trust_score("nobody"), 0);
+}
+
+#[test]
+fn web_of_trust_best_chain_counts() {
+    // A second, stronger route to carol replaces the weaker one.
+    let state =
+        WebOfTrust::try_next_state(&chain(), &TrustAction::trust(2, "alice", "carol", 90, 1))
+            .unwrap();
+    assert_eq!(state.trust_score("carol"), 576_000);
+}
+
+#[test]
+fn web_of_trust_cycles_and_sybils_gain_nothing() {
+    let mut state = chain();
+    // Mallory and carol vouch for each other as strongly as they can.
+    for action in [
+        TrustAction::trust(5, "mallory", "carol", 100, 0),
+        TrustAction::trust(4, "carol", "mallory", 100, 0),
+        TrustAction::trust(5, "mallory", "alice", 100, 1),
+    ] {
+        state = WebOfTrust::try_next_state(&state, &action).unwrap();
+    }
+    assert_eq!(state.trust_score("carol"), 384_000);
+    assert_eq!(state.trust_score("alice"), 800_000);
+    assert_eq!(state.trust_score("mallory"), 307_200);
+}
+
+#[test]
+fn web_of_trust_revocation() {
+    let state =
+        WebOfTrust::try_next_state(&chain(), &TrustAction::revoke(2, "alice", "bob", 1)).unwrap();
+    assert_eq!(state.edge("alice", "bob"), None);
+    assert_eq!(state.trust_score("bob"), 0);
+    assert_eq!(state.trust_score("carol"), 0);
+
+    assert_eq!(
+        WebOfTrust::try_next_state(&state, &TrustAction::revoke(2, "alice", "bob", 2)),
+        Err(TrustError::NoSuchEdge)
+    );
+}
+
+#[test]
+fn web_of_trust_checks_signatures_and_nonces() {
+    let state = chain();
+    // Signed with bob's key, claiming to be alice
+    assert_eq!(
+        WebOfTrust::try_next_state(&state, &TrustAction::trust(3, "alice", "mallory", 100, 1)),
+        Err(TrustError::BadSignature)
+    );
+    // Replaying alice's original vouch for bob
+    assert_eq!(
+        WebOfTrust::try_next_state(&state, &TrustAction::trust(2, "alice", "bob", 75, 0)),
+        Err(TrustError::BadNonce {
+            expected: 1,
+            found: 0
+        })
+    );
+    // The signature covers the weight, so it cannot be changed in flight.
+    let TrustAction::Trust {
+        nonce, signature, ..
+    } = TrustAction::trust(2, "alice", "bob", 10, 1)
+    else {
+        unreachable!()
+    };
+    let tampered = TrustAction::Trust {
+        from: "alice".into(),
+        to: "bob".into(),
+        weight: 100,
+        nonce,
+        signature,
+    };
+    assert_eq!(
+        WebOfTrust::try_next_state(&state, &tampered),
+        Err(TrustError::BadSignature)
+    );
+}
+
+#[test]
+fn web_of_trust_rejects_bad_edges() {
+    let state = chain();
+    assert_eq!(
+        WebOfTrust::try_next_state(&state, &TrustAction::trust(2, "alice", "alice", 50, 1)),
+        Err(TrustError::SelfTrust)
+    );
+    assert_eq!(
+        WebOfTrust::try_next_state(&state, &TrustAction::trust(2, "alice", "bob", 0, 1)),
+        Err(TrustError::BadWeight(0))
+    );
+    assert_eq!(
+        WebOfTrust::try_next_state(&state, &TrustAction::trust(2, "alice", "zed", 50, 1)),
+        Err(TrustError::UnknownIdentity("zed".into()))
+    );
+    assert_eq!(
+        WebOfTrust::try_next_state(
+            &state,
+            &TrustAction::Register {
+                identity: "bob".into(),
+                key: 9
+            }
+        ),
+        Err(TrustError::IdentityExists)
+    );
+}
+
+#[test]
+fn web_of_trust_scores_ignore_history() {
+    // The same edges added in a different order give the same scores.
+    let mut state = TrustGraph::new(&[("root", 1)]);
+    for identity in ["carol", "bob", "alice", "mallory"] {
+        state = WebOfTrust::next_state(
+            &state,
+            &TrustAction::Register {
+                identity: identity.into(),
+                key: 7,
+            },
+        );
+    }
+    for action in [
+        TrustAction::trust(7, "bob", "carol", 100, 0),
+        TrustAction::trust(7, "alice", "bob", 75, 0),
+        TrustAction::trust(1, "root", "alice", 100, 0),
+    ] {
+        state = WebOfTrust::try_next_state(&state, &action).unwrap();
+    }
+    assert_eq!(state.scores(), chain().scores());
+}
+
+#[test]
+fn web_of_trust_gates_authorities() {
+    let state = chain();
+    assert_eq!(
+        state.authorities(),
+        ["alice", "root"].iter().map(|s| s.to_string()).collect()
+    );
+    assert!(!state.may_be_authority("bob"));
+
+    let state =
+        WebOfTrust::try_next_state(&state, &TrustAction::trust(1, "root", "bob", 70, 1)).unwrap();
+    assert!(state.may_be_authority("bob"));
+    assert_eq!(state.trust_score("bob"), 560_000);
+}
+
+#[test]
+fn web_of_trust_repl_commands() {
+    let mut session = crate::p1_state_machine::repl::Session::<WebOfTrust>::new();
+    session.apply("register alice").unwrap();
+    session.apply("trust root alice 90 0").unwrap();
+    assert_eq!(session.current().trust_score("alice"), 720_000);
+    // Rejected because the nonce has been used
+    session.apply("trust root alice 10 0").unwrap();
+    assert_eq!(session.current().edge("root", "alice"), Some(90));
+    session.apply("revoke root alice 1").unwrap();
+    assert_eq!(session.current().trust_score("alice"), 0);
+}
@@ -0,0 +1,498 @@
+//! A small interactive program for driving any state machine by hand. The user types commands,
+//! each command is parsed into one of the machine's transitions, and the resulting state is printed.
+//!
+//! Besides transitions, the repl understands a handful of meta commands that start with a colon:
+//! `:help`, `:state`, `:history`, `:undo [n]`, and `:load <path>`. A script is simply a file with
+//! one command per line. Blank lines and lines beginning with `#` are ignored.
+//!
+//! The binary in `src/bin/repl.rs` is a thin wrapper around the sessions defined here.
+
+use super::p1_switches::{DemoPanel, LightSwitch, Switchboard, WeirdSwitchMachine};
+use super::p2_laundry_machine::{ClothesMachine, Delicates, Denim, Garment};
+use super::p3_atm::Atm;
+use super::p4_open_ended::checkers::Checkers;
+use super::p4_open_ended::chess::Chess;
+use super::p4_open_ended::dmv::Dmv;
+use super::p4_open_ended::growing_prize::GrowingPrize;
+use super::p4_open_ended::land_registry::LandRegistry;
+use super::p4_open_ended::prediction_market::PredictionMarket;
+use super::p4_open_ended::social_graph::SocialGraph;
+use super::p4_open_ended::tcr::TokenCuratedRegistry;
+use super::p4_open_ended::tic_tac_toe::{AgainstComputer, TicTacToe};
+use super::p4_open_ended::utility::UtilityProvider;
+use super::p4_open_ended::web_of_trust::WebOfTrust;
+use super::table::Turnstile;
+#[cfg(test)]
+use super::TryStateMachine;
+use super::{ReversibleMachine, StateMachine};
+use std::fmt::Debug;
+use std::fs;
+use std::str::FromStr;
+
+/// A state machine that can be driven from the repl.
+///
+/// The repl only knows about text, so the machine must explain how to turn a line of text
+/// into one of its transitions, and where a fresh session should start.
+pub trait ReplMachine: StateMachine {
+    /// The state that a new session starts in.
+    fn initial_state() -> Self::State;
+
+    /// Parse a single command typed by the user into a transition.
+    fn parse_transition(input: &str) -> Result<Self::Transition, String>;
+
+    /// Short descriptions of the commands understood by `parse_transition`. These are shown
+    /// to the user by `:help`.
+    fn commands() -> Vec<&'static str>;
+}
+
+/// Remembers how to walk a session back one transition at a time.
+trait UndoLog<M: StateMachine> {
+    /// Apply the transition, remembering whatever is needed to undo it later.
+    fn apply(&mut self, state: &M::State, t: &M::Transition) -> M::State;
+
+    /// Undo the most recently applied transition, given the state it produced.
+    fn revert(&mut self, state: &M::State) -> M::State;
+}
+
+/// Works for any machine by keeping a copy of every state visited.
+struct Snapshots<M: StateMachine>(Vec<M::State>);
+
+impl<M> UndoLog<M> for Snapshots<M>
+where
+    M: StateMachine,
+    M::State: Clone,
+{
+    fn apply(&mut self, state: &M::State, t: &M::Transition) -> M::State {
+        self.0.push(state.clone());
+        M::next_state(state, t)
+    }
+
+    fn revert(&mut self, _: &M::State) -> M::State {
+        self.0.pop().expect("only applied transitions are reverted")
+    }
+}
+
+/// Only keeps the small undo records of a reversible machine.
+struct Inverses<M: ReversibleMachine>(Vec<M::Undo>);
+
+impl<M: ReversibleMachine> UndoLog<M> for Inverses<M> {
+    fn apply(&mut self, state: &M::State, t: &M::Transition) -> M::State {
+        let (next, undo) = M::apply(state, t);
+        self.0.push(undo);
+        next
+    }
+
+    fn revert(&mut self, state: &M::State) -> M::State {
+        let undo = self.0.pop().expect("only applied transitions are reverted");
+        M::revert(state, &undo)
+    }
+}
+
+/// One interactive session with a particular state machine.
+///
+/// The session can always undo back to an earlier point. Machines that implement
+/// `ReversibleMachine` should be started with `Session::reversible`, which only keeps the undo
+/// record of each transition. Any other machine falls back to remembering every state visited.
+pub struct Session<M: StateMachine> {
+    /// The state the session started in.
+    initial: M::State,
+    /// The state the machine is currently in.
+    current: M::State,
+    /// Every command applied so far, along with the transition it was parsed into.
+    applied: Vec<(String, M::Transition)>,
+    /// Enough information to walk back through `applied`.
+    log: Box<dyn UndoLog<M>>,
+}
+
+impl<M> Session<M>
+where
+    M: ReplMachine + 'static,
+    M::State: Clone + Debug + 'static,
+{
+    /// Start a new session in the machine's initial state.
+    pub fn new() -> Self {
+        Self::starting_at(M::initial_state())
+    }
+
+    /// Start a new session in the given state.
+    pub fn starting_at(state: M::State) -> Self {
+        Self::with_log(state, Box::new(Snapshots::<M>(Vec::new())))
+    }
+
+    fn with_log(state: M::State, log: Box<dyn UndoLog<M>>) -> Self {
+        Self {
+            initial: state.clone(),
+            current: state,
+            applied: Vec::new(),
+            log,
+        }
+    }
+
+    /// The state the machine is currently in.
+    pub fn current(&self) -> &M::State {
+        &self.current
+    }
+
+    /// All commands applied so far, each paired with the state it produced.
+    ///
+    /// The session does not necessarily keep those states around, so they are recalculated
+    /// from the initial state.
+    pub fn history(&self) -> impl Iterator<Item = (&str, M::State)> + '_ {
+        let mut state = self.initial.clone();
+        self.applied.iter().map(move |(command, t)| {
+            state = M::next_state(&state, t);
+            (command.as_str(), state.clone())
+        })
+    }
+
+    /// Parse the command and apply the resulting transition to the current state.
+    pub fn apply(&mut self, command: &str) -> Result<&M::State, String> {
+        let transition = M::parse_transition(command)?;
+        self.current = self.log.apply(&self.current, &transition);
+        self.applied.push((command.to_string(), transition));
+        Ok(self.current())
+    }
+
+    /// Undo the last `steps` transitions. It is an error to undo past the initial state.
+    pub fn undo(&mut self, steps: usize) -> Result<&M::State, String> {
+        if steps > self.applied.len() {
+            return Err(format!(
+                "cannot undo {} steps, only {} in history",
+                steps,
+                self.applied.len()
+            ));
+        }
+        for _ in 0..steps {
+            self.current = self.log.revert(&self.current);
+            self.applied.pop();
+        }
+        Ok(self.current())
+    }
+
+    /// Run every command in a script. Execution stops at the first command that fails,
+    /// and the error reports the offending line. Commands before it remain applied.
+    ///
+    /// Returns the number of transitions that were applied.
+    pub fn run_script(&mut self, script: &str) -> Result<usize, String> {
+        let mut applied = 0;
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.apply(line)
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+impl<M> Session<M>
+where
+    M: ReplMachine + ReversibleMachine + 'static,
+    M::State: Clone + Debug + 'static,
+    M::Undo: 'static,
+{
+    /// Start a new session in the machine's initial state, undoing through `ReversibleMachine`.
+    pub fn reversible() -> Self {
+        Self::reversible_at(M::initial_state())
+    }
+
+    /// Start a new session in the given state, undoing through `ReversibleMachine`.
+    pub fn reversible_at(state: M::State) -> Self {
+        Self::with_log(state, Box::new(Inverses::<M>(Vec::new())))
+    }
+}
+
+impl<M> Default for Session<M>
+where
+    M: ReplMachine + 'static,
+    M::State: Clone + Debug + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An object-safe view of a session, so the binary can drive whichever machine the user picked
+/// without knowing its concrete type.
+pub trait Driver {
+    /// The human-readable name of the machine being driven.
+    fn name(&self) -> String;
+
+    /// A rendering of the current state.
+    fn state(&self) -> String;
+
+    /// Execute one line of user input, either a meta command or a transition, and return the
+    /// text that should be shown to the user.
+    fn execute(&mut self, line: &str) -> Result<String, String>;
+}
+
+impl<M> Driver for Session<M>
+where
+    M: ReplMachine + 'static,
+    M::State: Clone + Debug + 'static,
+{
+    fn name(&self) -> String {
+        M::human_name()
+    }
+
+    fn state(&self) -> String {
+        format!("{:?}", self.current())
+    }
+
+    fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            ":help" => {
+                let mut help = String::from("Transitions:\n");
+                for c in M::commands() {
+                    help.push_str(&format!("  {}\n", c));
+                }
+                help.push_str("Meta commands:\n");
+                help.push_str("  :state        show the current state\n");
+                help.push_str("  :history      list every transition applied so far\n");
+                help.push_str("  :undo [n]     go back n transitions (default 1)\n");
+                help.push_str("  :load <path>  run every command in a script file\n");
+                help.push_str("  :quit         leave the repl");
+                Ok(help)
+            }
+            ":state" => Ok(self.state()),
+            ":history" => {
+                let mut out = format!("0: (start) -> {:?}", self.initial);
+                for (i, (c, s)) in self.history().enumerate() {
+                    out.push_str(&format!("\n{}: {} -> {:?}", i + 1, c, s));
+                }
+                Ok(out)
+            }
+            ":undo" => {
+                let steps = if argument.is_empty() {
+                    1
+                } else {
+                    argument
+                        .parse()
+                        .map_err(|_| format!("not a number of steps: {}", argument))?
+                };
+                self.undo(steps).map(|s| format!("{:?}", s))
+            }
+            ":load" => {
+                let script = fs::read_to_string(argument)
+                    .map_err(|e| format!("could not read {}: {}", argument, e))?;
+                let applied = self.run_script(&script)?;
+                Ok(format!(
+                    "applied {} transitions\n{:?}",
+                    applied,
+                    self.current()
+                ))
+            }
+            c if c.starts_with(':') => Err(format!("unknown meta command: {}", c)),
+            _ => self.apply(line).map(|s| format!("{:?}", s)),
+        }
+    }
+}
+
+/// Box up a fresh session for the given machine.
+fn session<M>() -> Box<dyn Driver>
+where
+    M: ReplMachine + 'static,
+    M::State: Clone + Debug,
+{
+    Box::new(Session::<M>::new())
+}
+
+/// Box up a fresh session for a machine that knows how to undo its own transitions.
+fn reversible_session<M>() -> Box<dyn Driver>
+where
+    M: ReplMachine + ReversibleMachine + 'static,
+    M::State: Clone + Debug,
+    M::Undo: 'static,
+{
+    Box::new(Session::<M>::reversible())
+}
+
+/// A function that starts a fresh session with one particular machine.
+pub type StartSession = fn() -> Box<dyn Driver>;
+
+/// Every machine the repl knows about, along with the name used to select it.
+pub const MACHINES: &[(&str, StartSession)] = &[
+    ("light-switch", reversible_session::<LightSwitch>),
+    ("weird-switch", reversible_session::<WeirdSwitchMachine>),
+    ("switch-panel", reversible_session::<Switchboard<DemoPanel>>),
+    ("clothes", reversible_session::<ClothesMachine>),
+    ("delicates", reversible_session::<Garment<Delicates>>),
+    ("denim", reversible_session::<Garment<Denim>>),
+    ("turnstile", session::<Turnstile>),
+    ("atm", session::<Atm>),
+    ("tic-tac-toe", session::<TicTacToe>),
+    ("tic-tac-toe-vs-computer", session::<AgainstComputer>),
+    ("chess", session::<Chess>),
+    ("checkers", session::<Checkers>),
+    ("prediction-market", session::<PredictionMarket>),
+    ("tcr", session::<TokenCuratedRegistry>),
+    ("web-of-trust", session::<WebOfTrust>),
+    ("land-registry", session::<LandRegistry>),
+    ("utility", session::<UtilityProvider>),
+    ("growing-prize", session::<GrowingPrize>),
+    ("social-graph", session::<SocialGraph>),
+    ("dmv", session::<Dmv>),
+];
+
+/// Start a session with the machine registered under the given name.
+pub fn driver(name: &str) -> Option<Box<dyn Driver>> {
+    MACHINES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, start)| start())
+}
+
+/// Parse one word of a repl command as a number, for machines whose commands take amounts,
+/// ids and the like. `what` names the argument in error messages.
+pub fn parse_number<T: FromStr>(words: &[&str], index: usize, what: &str) -> Result<T, String> {
+    let word = words
+        .get(index)
+        .ok_or_else(|| format!("missing {}", what))?;
+    word.parse()
+        .map_err(|_| format!("not a valid {}: {}", what, word))
+}
+
+/// Parse a single repl command and try it on the given state.
+///
+/// Tests of fallible machines are much easier to read when written in the same commands a user
+/// would type.
+#[cfg(test)]
+pub(crate) fn try_command<M>(
+    state: &<M as TryStateMachine>::State,
+    command: &str,
+) -> Result<<M as TryStateMachine>::State, M::Error>
+where
+    M: ReplMachine + TryStateMachine<Transition = <M as StateMachine>::Transition>,
+{
+    let transition = M::parse_transition(command)
+        .unwrap_or_else(|e| panic!("{} could not be parsed: {}", command, e));
+    M::try_next_state(state, &transition)
+}
+
+/// Apply every command in the script, panicking if any of them is rejected.
+#[cfg(test)]
+pub(crate) fn apply_commands<M>(
+    state: &<M as TryStateMachine>::State,
+    script: &[&str],
+) -> <M as TryStateMachine>::State
+where
+    M: ReplMachine + TryStateMachine<Transition = <M as StateMachine>::Transition>,
+    <M as TryStateMachine>::State: Clone,
+    M::Error: Debug,
+{
+    script.iter().fold(state.clone(), |state, command| {
+        try_command::<M>(&state, command)
+            .unwrap_or_else(|e| panic!("{} was rejected: {:?}", command, e))
+    })
+}
+
+#[test]
+fn repl_apply_parses_and_transitions() {
+    let mut session = Session::<LightSwitch>::new();
+    assert_eq!(session.apply("toggle"), Ok(&true));
+    assert_eq!(session.apply("toggle"), Ok(&false));
+    assert_eq!(session.history().count(), 2);
+}
+
+#[test]
+fn repl_apply_rejects_unknown_command() {
+    let mut session = Session::<LightSwitch>::new();
+    assert!(session.apply("flip the table").is_err());
+    assert_eq!(session.history().count(), 0);
+}
+
+#[test]
+fn repl_undo_restores_previous_state() {
+    let mut session = Session::<LightSwitch>::new();
+    session.apply("toggle").unwrap();
+    session.apply("toggle").unwrap();
+    session.apply("toggle").unwrap();
+
+    assert_eq!(session.undo(2), Ok(&true));
+    assert_eq!(session.history().count(), 1);
+}
+
+#[test]
+fn repl_cannot_undo_past_start() {
+    let mut session = Session::<LightSwitch>::new();
+    session.apply("toggle").unwrap();
+
+    assert!(session.undo(2).is_err());
+    assert_eq!(session.current(), &true);
+}
+
+#[test]
+fn repl_script_skips_comments_and_blank_lines() {
+    let mut session = Session::<LightSwitch>::new();
+    let script = "# turn it on\ntoggle\n\n  toggle  \ntoggle\n";
+
+    assert_eq!(session.run_script(script), Ok(3));
+    assert_eq!(session.current(), &true);
+}
+
+#[test]
+fn repl_script_reports_failing_line() {
+    let mut session = Session::<LightSwitch>::new();
+    let script = "toggle\nnonsense\ntoggle";
+
+    let err = session.run_script(script).unwrap_err();
+    assert!(err.starts_with("line 2"));
+    // The command before the failure is kept
+    assert_eq!(session.current(), &true);
+}
+
+#[test]
+fn repl_driver_handles_meta_commands() {
+    let mut driver = driver("light-switch").unwrap();
+    driver.execute("toggle").unwrap();
+    driver.execute("toggle").unwrap();
+
+    assert_eq!(driver.execute(":undo"), Ok("true".to_string()));
+    assert!(driver
+        .execute(":history")
+        .unwrap()
+        .contains("1: toggle -> true"));
+    assert!(driver.execute(":bogus").is_err());
+}
+
+#[test]
+fn repl_every_registered_machine_starts() {
+    for (name, start) in MACHINES {
+        let driver = start();
+        assert!(!driver.name().is_empty(), "{} has no name", name);
+    }
+}
+
+#[test]
+fn repl_reversible_undo_matches_snapshots() {
+    let script = "wear\nwash\ndry\nwear\nwash\ndry";
+    let mut reversible = Session::<ClothesMachine>::reversible();
+    let mut snapshots = Session::<ClothesMachine>::new();
+    reversible.run_script(script).unwrap();
+    snapshots.run_script(script).unwrap();
+
+    for steps in [1, 2, 3] {
+        assert_eq!(reversible.undo(steps), snapshots.undo(steps));
+    }
+    assert_eq!(reversible.current(), &ClothesMachine::initial_state());
+    assert!(reversible.undo(1).is_err());
+}
+
+#[test]
+fn repl_history_replays_from_start() {
+    let mut session = Session::<LightSwitch>::reversible();
+    session.run_script("toggle\ntoggle\ntoggle").unwrap();
+    session.undo(1).unwrap();
+
+    let states: Vec<bool> = session.history().map(|(_, s)| s).collect();
+    assert_eq!(states, vec![true, false]);
+}
@@ -0,0 +1,164 @@
+//! Rolling back a reversible machine. A `Journal` keeps the current state and one undo record
+//! per transition applied, rather than a copy of every state visited. Rolling back replays the
+//! undo records in reverse, much like a node unwinding blocks during a reorg.
+
+use super::ReversibleMachine;
+
+/// The current state of a reversible machine, along with enough history to roll it back.
+pub struct Journal<M: ReversibleMachine> {
+    /// The state the machine is currently in
+    state: M::State,
+    /// The undo record for every transition applied so far, oldest first
+    undo: Vec<M::Undo>,
+}
+
+impl<M: ReversibleMachine> Journal<M> {
+    /// Start a new journal in the given state, with no history.
+    pub fn new(state: M::State) -> Self {
+        Self {
+            state,
+            undo: Vec::new(),
+        }
+    }
+
+    /// The state the machine is currently in.
+    pub fn current(&self) -> &M::State {
+        &self.state
+    }
+
+    /// How many transitions can be rolled back.
+    pub fn depth(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Apply a transition and remember how to undo it.
+    pub fn apply(&mut self, t: &M::Transition) -> &M::State {
+        let (next, undo) = M::apply(&self.state, t);
+        self.state = next;
+        self.undo.push(undo);
+        &self.state
+    }
+
+    /// Undo the most recent transitions, one at a time. Nothing is rolled back if there are
+    /// not enough transitions in the journal.
+    pub fn rollback(&mut self, steps: usize) -> Result<&M::State, String> {
+        if steps > self.undo.len() {
+            return Err(format!(
+                "cannot roll back {} steps, only {} in the journal",
+                steps,
+                self.undo.len()
+            ));
+        }
+        for _ in 0..steps {
+            let undo = self.undo.pop().expect("length was checked above");
+            self.state = M::revert(&self.state, &undo);
+        }
+        Ok(&self.state)
+    }
+}
+
+#[cfg(test)]
+use super::p1_switches::{DemoPanel, LightSwitch, Switchboard, Toggle, WeirdSwitchMachine};
+#[cfg(test)]
+use super::p2_laundry_machine::{
+    ClothesAction, ClothesMachine, ClothesState, Delicates, Denim, Garment,
+};
+#[cfg(test)]
+use super::repl::ReplMachine;
+#[cfg(test)]
+use super::FiniteStateMachine;
+#[cfg(test)]
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+#[cfg(test)]
+use std::fmt::Debug;
+
+/// Take many random walks from the start state and check that every walk rolls back to exactly
+/// where it began, and that each individual step reverts to the state before it.
+#[cfg(test)]
+fn check_rollback<M>(start: M::State)
+where
+    M: ReversibleMachine + FiniteStateMachine,
+    M::State: Clone + PartialEq + Debug,
+    M::Transition: Clone,
+{
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..100 {
+        let mut journal = Journal::<M>::new(start.clone());
+        let mut states = vec![start.clone()];
+        for _ in 0..20 {
+            let Some(t) = M::transitions(journal.current()).choose(&mut rng).cloned() else {
+                break;
+            };
+            let before = journal.current().clone();
+            let (after, undo) = M::apply(&before, &t);
+            assert_eq!(after, M::next_state(&before, &t));
+            assert_eq!(M::revert(&after, &undo), before);
+
+            states.push(journal.apply(&t).clone());
+        }
+
+        // Roll back part of the way, then the rest.
+        let partial = rng.gen_range(0..=journal.depth());
+        let expected = &states[states.len() - 1 - partial];
+        assert_eq!(journal.rollback(partial).unwrap(), expected);
+        assert_eq!(journal.rollback(journal.depth()).unwrap(), &start);
+    }
+}
+
+#[test]
+fn rollback_light_switch() {
+    check_rollback::<LightSwitch>(false);
+    check_rollback::<LightSwitch>(true);
+}
+
+#[test]
+fn rollback_weird_switch() {
+    check_rollback::<WeirdSwitchMachine>(WeirdSwitchMachine::initial_state());
+}
+
+#[test]
+fn rollback_switch_panel() {
+    check_rollback::<Switchboard<DemoPanel>>(vec![false; 4]);
+    check_rollback::<Switchboard<DemoPanel>>(vec![true, false, true, true]);
+}
+
+#[test]
+fn rollback_clothes() {
+    for life in [0, 3, 10, 50] {
+        check_rollback::<ClothesMachine>(ClothesState::Clean(life));
+        check_rollback::<Garment<Delicates>>(ClothesState::Wet(life));
+        check_rollback::<Garment<Denim>>(ClothesState::Dirty(life));
+    }
+    check_rollback::<ClothesMachine>(ClothesState::Tattered);
+}
+
+#[test]
+fn rollback_records_only_what_changed() {
+    let (_, undo) = Switchboard::<DemoPanel>::apply(&vec![true, true, true, true], &0);
+    assert_eq!(undo, vec![0, 1, 2, 3]);
+    let (_, undo) = Switchboard::<DemoPanel>::apply(&vec![true, false, false, false], &1);
+    assert_eq!(undo, vec![1]);
+    let (_, undo) =
+        WeirdSwitchMachine::apply(&WeirdSwitchMachine::initial_state(), &Toggle::SecondSwitch);
+    assert_eq!(undo, vec![Toggle::SecondSwitch]);
+}
+
+#[test]
+fn rollback_too_far_changes_nothing() {
+    let mut journal = Journal::<LightSwitch>::new(false);
+    journal.apply(&());
+    assert_eq!(
+        journal.rollback(2),
+        Err("cannot roll back 2 steps, only 1 in the journal".into())
+    );
+    assert_eq!(journal.current(), &true);
+    assert_eq!(journal.depth(), 1);
+}
+
+#[test]
+fn rollback_brings_back_tattered_clothes() {
+    let mut journal = Journal::<ClothesMachine>::new(ClothesState::Clean(1));
+    journal.apply(&ClothesAction::Wash);
+    assert_eq!(journal.current(), &ClothesState::Tattered);
+    assert_eq!(journal.rollback(1), Ok(&ClothesState::Clean(1)));
+}
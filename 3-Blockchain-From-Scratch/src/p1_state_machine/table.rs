@@ -0,0 +1,359 @@
+//! Many simple state machines are nothing more than a table: in this state, when that happens,
+//! go to this other state. Writing such tables as hand-rolled `match` ladders is tedious and easy
+//! to get wrong, so this module provides a macro that writes them for us.
+//!
+//! ```ignore
+//! state_machine! {
+//!     /// A coin operated turnstile
+//!     pub struct Turnstile;
+//!     name = "Turnstile";
+//!
+//!     pub enum TurnstileState { Locked, Unlocked }
+//!     pub enum TurnstileAction { Coin, Push }
+//!
+//!     rules {
+//!         (Locked, Coin) => Unlocked,
+//!         (Unlocked, Push) => Locked,
+//!         (state, _) => state,
+//!     }
+//! }
+//! ```
+//!
+//! Each rule maps a pattern over `(state, transition)` to the resulting state. The rules are
+//! tried in order, just like the arms of a `match`, and the variants of both enums can be named
+//! directly. Because the rules really do become a `match`, the compiler checks that the table
+//! is exhaustive and warns about rules that can never apply.
+//!
+//! The macro generates:
+//! * The machine type, and both enums with an `ALL` constant listing every variant.
+//! * The `StateMachine` implementation.
+//! * A `table()` function listing every `(state, transition, next state)` entry.
+//! * `FiniteStateMachine`, `ReplMachine`, and `TraceCodec` implementations, so that tooling like
+//!   the model checker, graph export, and the repl work without any extra code. The first state
+//!   listed is where the repl starts.
+//!
+//! Not every machine's states fit in a unit-only enum. When the state is a number, a struct, or
+//! any other type that already exists, the macro can instead write just the `StateMachine`
+//! implementation. Rules may carry guards, just like match arms, and patterns may bind the data
+//! inside states.
+//!
+//! ```ignore
+//! state_machine! {
+//!     /// Counts up by the given step, going back to zero rather than passing ten
+//!     impl for Counter;
+//!     name = "Counter";
+//!     type State = u8;
+//!     type Transition = u8;
+//!
+//!     rules {
+//!         (n, step) if n.checked_add(step).is_some_and(|m| m < 10) => n + step,
+//!         (_, _) => 0,
+//!     }
+//! }
+//! ```
+//!
+//! Such states can't be listed, so there is no `table()` and no tooling implementations. Those
+//! are written by hand, usually in terms of which transitions are worth trying.
+
+/// Define a state machine from a transition table. See the module documentation for details.
+#[macro_export]
+macro_rules! state_machine {
+    (
+        $(#[$machine_meta:meta])*
+        $machine_vis:vis struct $machine:ident;
+        name = $name:literal;
+
+        $(#[$state_meta:meta])*
+        $state_vis:vis enum $state:ident {
+            $(#[$initial_meta:meta])* $initial:ident
+            $(, $(#[$state_variant_meta:meta])* $state_variant:ident)* $(,)?
+        }
+
+        $(#[$transition_meta:meta])*
+        $transition_vis:vis enum $transition:ident {
+            $($(#[$transition_variant_meta:meta])* $transition_variant:ident),+ $(,)?
+        }
+
+        rules {
+            $(($from:pat, $on:pat) $(if $guard:expr)? => $to:expr),+ $(,)?
+        }
+    ) => {
+        $(#[$machine_meta])*
+        $machine_vis struct $machine;
+
+        $(#[$state_meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+        $state_vis enum $state {
+            $(#[$initial_meta])* $initial,
+            $($(#[$state_variant_meta])* $state_variant,)*
+        }
+
+        impl $state {
+            /// Every state, in the order they were declared.
+            pub const ALL: &'static [$state] = &[$state::$initial, $($state::$state_variant),*];
+        }
+
+        $(#[$transition_meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+        $transition_vis enum $transition {
+            $($(#[$transition_variant_meta])* $transition_variant,)+
+        }
+
+        impl $transition {
+            /// Every transition, in the order they were declared.
+            pub const ALL: &'static [$transition] = &[$($transition::$transition_variant),+];
+        }
+
+        impl $machine {
+            /// Every entry in the transition table, as `(state, transition, next state)`.
+            pub fn table() -> Vec<($state, $transition, $state)> {
+                use $crate::p1_state_machine::StateMachine;
+
+                let mut table = Vec::new();
+                for state in $state::ALL {
+                    for t in $transition::ALL {
+                        table.push((*state, *t, Self::next_state(state, t)));
+                    }
+                }
+                table
+            }
+        }
+
+        impl $crate::p1_state_machine::StateMachine for $machine {
+            type State = $state;
+            type Transition = $transition;
+
+            fn next_state(starting_state: &$state, t: &$transition) -> $state {
+                #[allow(unused_imports)]
+                use $state::*;
+                #[allow(unused_imports)]
+                use $transition::*;
+
+                match (*starting_state, *t) {
+                    $(($from, $on) $(if $guard)? => $to,)+
+                }
+            }
+
+            fn human_name() -> String {
+                $name.into()
+            }
+        }
+
+        impl $crate::p1_state_machine::FiniteStateMachine for $machine {
+            fn transitions(_: &$state) -> Vec<$transition> {
+                $transition::ALL.to_vec()
+            }
+        }
+
+        impl $crate::p1_state_machine::repl::ReplMachine for $machine {
+            fn initial_state() -> $state {
+                $state::$initial
+            }
+
+            fn parse_transition(input: &str) -> Result<$transition, String> {
+                $transition::ALL
+                    .iter()
+                    .find(|t| format!("{:?}", t).eq_ignore_ascii_case(input))
+                    .copied()
+                    .ok_or_else(|| format!("unknown command: {}", input))
+            }
+
+            fn commands() -> Vec<&'static str> {
+                vec![$(stringify!($transition_variant)),+]
+            }
+        }
+
+        impl $crate::p1_state_machine::trace::TraceCodec for $machine {
+            fn encode_state(state: &$state) -> String {
+                format!("{:?}", state)
+            }
+
+            fn decode_state(text: &str) -> Result<$state, String> {
+                $state::ALL
+                    .iter()
+                    .find(|s| format!("{:?}", s) == text)
+                    .copied()
+                    .ok_or_else(|| format!("unknown state: {}", text))
+            }
+
+            fn encode_transition(t: &$transition) -> String {
+                format!("{:?}", t)
+            }
+
+            fn decode_transition(text: &str) -> Result<$transition, String> {
+                <Self as $crate::p1_state_machine::repl::ReplMachine>::parse_transition(text)
+            }
+        }
+    };
+
+    (
+        $(#[$impl_meta:meta])*
+        impl for $machine:ty;
+        $(name = $name:literal;)?
+        type State = $state:ty;
+        type Transition = $transition:ty;
+
+        rules {
+            $(($from:pat, $on:pat) $(if $guard:expr)? => $to:expr),+ $(,)?
+        }
+    ) => {
+        $(#[$impl_meta])*
+        impl $crate::p1_state_machine::StateMachine for $machine {
+            type State = $state;
+            type Transition = $transition;
+
+            fn next_state(starting_state: &$state, t: &$transition) -> $state {
+                match (starting_state.clone(), t.clone()) {
+                    $(($from, $on) $(if $guard)? => $to,)+
+                }
+            }
+
+            $(
+                fn human_name() -> String {
+                    $name.into()
+                }
+            )?
+        }
+    };
+}
+
+state_machine! {
+    /// A coin operated turnstile. It stays locked until a coin is inserted, and locks again
+    /// once someone pushes through. Extra coins are kept, and pushing a locked turnstile
+    /// does nothing.
+    pub struct Turnstile;
+    name = "Turnstile";
+
+    /// Whether the turnstile will let someone through
+    pub enum TurnstileState {
+        Locked,
+        Unlocked,
+    }
+
+    /// Things people do to turnstiles
+    pub enum TurnstileAction {
+        Coin,
+        Push,
+    }
+
+    rules {
+        (Locked, Coin) => Unlocked,
+        (Unlocked, Push) => Locked,
+        (state, _) => state,
+    }
+}
+
+#[cfg(test)]
+use super::StateMachine;
+
+#[test]
+fn table_turnstile_rules() {
+    use TurnstileAction::*;
+    use TurnstileState::*;
+
+    assert_eq!(Turnstile::next_state(&Locked, &Coin), Unlocked);
+    assert_eq!(Turnstile::next_state(&Locked, &Push), Locked);
+    assert_eq!(Turnstile::next_state(&Unlocked, &Coin), Unlocked);
+    assert_eq!(Turnstile::next_state(&Unlocked, &Push), Locked);
+}
+
+#[test]
+fn table_enumerates_every_entry() {
+    use TurnstileAction::*;
+    use TurnstileState::*;
+
+    assert_eq!(
+        Turnstile::table(),
+        vec![
+            (Locked, Coin, Unlocked),
+            (Locked, Push, Locked),
+            (Unlocked, Coin, Unlocked),
+            (Unlocked, Push, Locked),
+        ]
+    );
+}
+
+#[test]
+fn table_machines_work_with_tooling() {
+    use super::model_check::ModelChecker;
+    use super::repl::ReplMachine;
+    use super::trace::{Trace, TraceCodec};
+
+    assert_eq!(Turnstile::initial_state(), TurnstileState::Locked);
+    assert_eq!(
+        Turnstile::parse_transition("coin"),
+        Ok(TurnstileAction::Coin)
+    );
+    assert_eq!(
+        Turnstile::decode_state("Unlocked"),
+        Ok(TurnstileState::Unlocked)
+    );
+
+    let report = ModelChecker::<Turnstile>::new()
+        .step_invariant("pushing always locks", |_, t, to| {
+            *t != TurnstileAction::Push || *to == TurnstileState::Locked
+        })
+        .check(&TurnstileState::Locked)
+        .unwrap();
+    assert_eq!(report.states, 2);
+
+    let trace = Trace::<Turnstile>::run(
+        TurnstileState::Locked,
+        &[TurnstileAction::Coin, TurnstileAction::Push],
+    );
+    assert_eq!(Trace::<Turnstile>::from_text(&trace.to_text()), Ok(trace));
+}
+
+#[test]
+fn table_rules_apply_in_order() {
+    state_machine! {
+        struct Dimmer;
+        name = "Dimmer";
+
+        enum Brightness { Off, Low, High }
+        enum Knob { Up, Down }
+
+        rules {
+            (Off, Up) => Low,
+            (Low | High, Up) => High,
+            (High, Down) => Low,
+            (_, Down) => Off,
+        }
+    }
+
+    assert_eq!(
+        Dimmer::next_state(&Brightness::High, &Knob::Down),
+        Brightness::Low
+    );
+    assert_eq!(
+        Dimmer::next_state(&Brightness::Low, &Knob::Down),
+        Brightness::Off
+    );
+    assert_eq!(
+        Dimmer::next_state(&Brightness::High, &Knob::Up),
+        Brightness::High
+    );
+    assert_eq!(Dimmer::table().len(), 6);
+}
+
+#[test]
+fn table_rules_over_existing_types() {
+    /// Counts up by the given step, going back to zero rather than passing ten.
+    struct Counter;
+
+    state_machine! {
+        impl for Counter;
+        type State = u8;
+        type Transition = u8;
+
+        rules {
+            (n, step) if n.checked_add(step).is_some_and(|m| m < 10) => n + step,
+            (_, _) => 0,
+        }
+    }
+
+    assert_eq!(Counter::next_state(&3, &4), 7);
+    assert_eq!(Counter::next_state(&8, &3), 0);
+    assert_eq!(Counter::next_state(&250, &10), 0);
+    assert_eq!(Counter::human_name(), "Unnamed state machine");
+}
@@ -0,0 +1,286 @@
+//! When a state machine misbehaves, the first step towards fixing it is reproducing the problem.
+//! A trace records exactly what happened during a run: the state it started in, each transition
+//! that was applied, and the state that each transition produced.
+//!
+//! Traces can be saved to a file and loaded again later. Replaying a trace checks that the
+//! machine still produces the same states from the same transitions. Replaying against a
+//! different implementation of the same machine shows exactly where the two disagree.
+//!
+//! The file format is plain text. The first line is a comment naming the machine. The second
+//! line holds the start state. Every following line holds a transition and the resulting state,
+//! separated by a tab.
+
+use super::StateMachine;
+use std::fmt;
+use std::fs;
+
+/// A state machine whose states and transitions can be written as a single line of text and
+/// read back again. This is what allows traces to be saved to files.
+pub trait TraceCodec: StateMachine {
+    /// Write a state as text. The text must not contain tabs or newlines.
+    fn encode_state(state: &Self::State) -> String;
+
+    /// Read a state written by `encode_state`.
+    fn decode_state(text: &str) -> Result<Self::State, String>;
+
+    /// Write a transition as text. The text must not contain tabs or newlines.
+    fn encode_transition(t: &Self::Transition) -> String;
+
+    /// Read a transition written by `encode_transition`.
+    fn decode_transition(text: &str) -> Result<Self::Transition, String>;
+}
+
+/// The place where a replay first disagreed with the recorded trace.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Divergence<S> {
+    /// The index of the step that disagreed, starting from zero
+    pub step: usize,
+    /// The state that was recorded
+    pub expected: S,
+    /// The state the replaying machine produced instead
+    pub actual: S,
+}
+
+/// A complete record of one run of a state machine.
+pub struct Trace<M: StateMachine> {
+    /// The state the run started in
+    pub start: M::State,
+    /// Each transition applied, along with the state it produced
+    pub steps: Vec<(M::Transition, M::State)>,
+}
+
+impl<M> Trace<M>
+where
+    M: StateMachine,
+    M::State: Clone + PartialEq,
+    M::Transition: Clone,
+{
+    /// Begin recording a new run from the given state.
+    pub fn new(start: M::State) -> Self {
+        Self {
+            start,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Record a run in which the given transitions are applied in order.
+    pub fn run(start: M::State, transitions: &[M::Transition]) -> Self {
+        let mut trace = Self::new(start);
+        for t in transitions {
+            trace.record(t.clone());
+        }
+        trace
+    }
+
+    /// The most recently recorded state.
+    pub fn current(&self) -> &M::State {
+        self.steps.last().map_or(&self.start, |(_, s)| s)
+    }
+
+    /// Apply a transition to the current state and record the result.
+    pub fn record(&mut self, t: M::Transition) -> &M::State {
+        let next = M::next_state(self.current(), &t);
+        self.steps.push((t, next));
+        self.current()
+    }
+
+    /// Every transition in the trace, in order.
+    pub fn transitions(&self) -> Vec<M::Transition> {
+        self.steps.iter().map(|(t, _)| t.clone()).collect()
+    }
+
+    /// Check that the machine that recorded this trace still produces the same states.
+    pub fn replay(&self) -> Result<(), Divergence<M::State>> {
+        self.replay_with::<M>()
+    }
+
+    /// Check that a different implementation of the same machine produces the same states.
+    pub fn replay_with<N>(&self) -> Result<(), Divergence<M::State>>
+    where
+        N: StateMachine<State = M::State, Transition = M::Transition>,
+    {
+        let mut state = self.start.clone();
+        for (step, (t, expected)) in self.steps.iter().enumerate() {
+            let actual = N::next_state(&state, t);
+            if actual != *expected {
+                return Err(Divergence {
+                    step,
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+            state = actual;
+        }
+        Ok(())
+    }
+}
+
+impl<M> Trace<M>
+where
+    M: TraceCodec,
+    M::State: Clone + PartialEq,
+    M::Transition: Clone,
+{
+    /// Write the trace in the text format described in the module documentation.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("# {}\n{}\n", M::human_name(), M::encode_state(&self.start));
+        for (t, s) in &self.steps {
+            text.push_str(&format!(
+                "{}\t{}\n",
+                M::encode_transition(t),
+                M::encode_state(s)
+            ));
+        }
+        text
+    }
+
+    /// Read a trace written by `to_text`.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty() && !l.starts_with('#'));
+
+        let (_, start) = lines.next().ok_or("trace has no start state")?;
+        let mut trace = Self::new(M::decode_state(start.trim())?);
+        for (number, line) in lines {
+            let parse = || -> Result<_, String> {
+                let (t, s) = line.split_once('\t').ok_or("expected a tab")?;
+                Ok((M::decode_transition(t.trim())?, M::decode_state(s.trim())?))
+            };
+            let step = parse().map_err(|e| format!("line {}: {}", number + 1, e))?;
+            trace.steps.push(step);
+        }
+        Ok(trace)
+    }
+
+    /// Save the trace to a file.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_text()).map_err(|e| format!("could not write {}: {}", path, e))
+    }
+
+    /// Load a trace from a file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        Self::from_text(&text)
+    }
+}
+
+impl<M> fmt::Debug for Trace<M>
+where
+    M: StateMachine,
+    M::State: fmt::Debug,
+    M::Transition: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("start", &self.start)
+            .field("steps", &self.steps)
+            .finish()
+    }
+}
+
+impl<M> PartialEq for Trace<M>
+where
+    M: StateMachine,
+    M::State: PartialEq,
+    M::Transition: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.start == other.start && self.steps == other.steps
+    }
+}
+
+#[cfg(test)]
+use super::p2_laundry_machine::{ClothesAction, ClothesMachine, ClothesState};
+
+#[test]
+fn trace_records_every_step() {
+    let mut trace = Trace::<ClothesMachine>::new(ClothesState::Clean(5));
+    trace.record(ClothesAction::Wear);
+    trace.record(ClothesAction::Wash);
+
+    assert_eq!(trace.current(), &ClothesState::Wet(3));
+    assert_eq!(
+        trace.steps,
+        vec![
+            (ClothesAction::Wear, ClothesState::Dirty(4)),
+            (ClothesAction::Wash, ClothesState::Wet(3)),
+        ]
+    );
+    assert_eq!(trace.replay(), Ok(()));
+}
+
+#[test]
+fn trace_text_round_trip() {
+    let trace = Trace::<ClothesMachine>::run(
+        ClothesState::Clean(3),
+        &[ClothesAction::Wear, ClothesAction::Dry, ClothesAction::Wash],
+    );
+    let text = trace.to_text();
+
+    assert_eq!(
+        text,
+        "# Clothes\nClean(3)\nwear\tDirty(2)\ndry\tTattered\nwash\tTattered\n"
+    );
+    assert_eq!(Trace::<ClothesMachine>::from_text(&text), Ok(trace));
+}
+
+#[test]
+fn trace_file_round_trip() {
+    let path = std::env::temp_dir().join("diy_blockchain_trace_file_round_trip.trace");
+    let path = path.to_str().unwrap();
+    let trace = Trace::<ClothesMachine>::run(
+        ClothesState::Wet(8),
+        &[ClothesAction::Dry, ClothesAction::Wear],
+    );
+
+    trace.save(path).unwrap();
+    assert_eq!(Trace::<ClothesMachine>::load(path), Ok(trace));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn trace_from_text_reports_bad_line() {
+    let text = "# Clothes\nClean(3)\nwear\tDirty(2)\nwear Dirty(0)\n";
+    let err = Trace::<ClothesMachine>::from_text(text).unwrap_err();
+    assert!(err.starts_with("line 4"));
+}
+
+#[test]
+fn trace_replay_detects_tampering() {
+    let mut trace = Trace::<ClothesMachine>::run(
+        ClothesState::Clean(5),
+        &[ClothesAction::Wear, ClothesAction::Wear],
+    );
+    trace.steps[1].1 = ClothesState::Dirty(3);
+
+    assert_eq!(
+        trace.replay(),
+        Err(Divergence {
+            step: 1,
+            expected: ClothesState::Dirty(3),
+            actual: ClothesState::Dirty(2),
+        })
+    );
+}
+
+#[test]
+fn trace_replay_against_other_implementation() {
+    use super::StayOnError;
+
+    // The two implementations agree until the clothes wear out.
+    let trace = Trace::<ClothesMachine>::run(
+        ClothesState::Clean(2),
+        &[ClothesAction::Wear, ClothesAction::Wear],
+    );
+    assert_eq!(
+        trace.replay_with::<StayOnError<ClothesMachine>>(),
+        Err(Divergence {
+            step: 1,
+            expected: ClothesState::Tattered,
+            actual: ClothesState::Dirty(1),
+        })
+    );
+}